@@ -0,0 +1,7 @@
+CREATE TABLE service_tokens (
+    name TEXT PRIMARY KEY NOT NULL,
+    token_hash TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+CREATE TABLE sessions (
+    jti TEXT PRIMARY KEY NOT NULL,
+    email TEXT NOT NULL,
+    upstream_token TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX sessions_email_idx ON sessions(email);
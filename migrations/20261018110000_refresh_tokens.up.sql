@@ -0,0 +1,13 @@
+CREATE TABLE refresh_tokens (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    family_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    session_jti TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens(family_id);
+CREATE INDEX refresh_tokens_email_idx ON refresh_tokens(email);
+CREATE INDEX refresh_tokens_session_jti_idx ON refresh_tokens(session_jti);
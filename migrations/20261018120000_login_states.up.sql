@@ -0,0 +1,6 @@
+CREATE TABLE login_states (
+    csrf_state TEXT PRIMARY KEY NOT NULL,
+    data JSONB NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX login_states_expires_at_idx ON login_states(expires_at);
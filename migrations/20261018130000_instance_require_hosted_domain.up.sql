@@ -0,0 +1 @@
+ALTER TABLE instances ADD COLUMN require_hosted_domain BOOLEAN NOT NULL DEFAULT FALSE;
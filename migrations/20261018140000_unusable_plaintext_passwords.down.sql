@@ -0,0 +1,2 @@
+-- The replaced plaintext values can't be restored, and were never usable for login.
+SELECT 1;
@@ -0,0 +1,3 @@
+-- Users were created with a random plaintext value as their password hash. Nobody knows those values, so they are
+-- replaced with a marker no password verifies against until the user sets one.
+UPDATE auth_users SET password_hash = '!' WHERE password_hash NOT LIKE '$argon2id$%';
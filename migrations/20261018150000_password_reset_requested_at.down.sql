@@ -0,0 +1 @@
+ALTER TABLE auth_users DROP COLUMN password_reset_requested_at;
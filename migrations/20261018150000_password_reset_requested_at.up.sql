@@ -0,0 +1 @@
+ALTER TABLE auth_users ADD COLUMN password_reset_requested_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,2 @@
+DROP TABLE failed_logins_by_ip;
+ALTER TABLE auth_users DROP COLUMN last_failed_login_at;
@@ -0,0 +1,7 @@
+ALTER TABLE auth_users ADD COLUMN last_failed_login_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE failed_logins_by_ip (
+    ip TEXT PRIMARY KEY NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
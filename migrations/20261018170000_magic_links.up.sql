@@ -0,0 +1,8 @@
+CREATE TABLE magic_links (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    email TEXT NOT NULL,
+    return_to TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX magic_links_email_idx ON magic_links(email);
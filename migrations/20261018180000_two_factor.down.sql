@@ -0,0 +1,4 @@
+DROP TABLE two_factor_challenges;
+DROP TABLE recovery_codes;
+DROP TABLE totp_credentials;
+ALTER TABLE instances DROP COLUMN require_two_factor;
@@ -0,0 +1,24 @@
+ALTER TABLE instances ADD COLUMN require_two_factor BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE totp_credentials (
+    email TEXT PRIMARY KEY NOT NULL REFERENCES auth_users(email) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    confirmed_at TIMESTAMP WITH TIME ZONE,
+    last_used_step BIGINT
+);
+
+CREATE TABLE recovery_codes (
+    email TEXT NOT NULL REFERENCES auth_users(email) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (email, code_hash)
+);
+
+CREATE TABLE two_factor_challenges (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    email TEXT NOT NULL REFERENCES auth_users(email) ON DELETE CASCADE,
+    return_to TEXT,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX two_factor_challenges_expires_at_idx ON two_factor_challenges(expires_at);
@@ -0,0 +1,3 @@
+DROP TABLE webauthn_challenges;
+DROP TABLE passkeys;
+ALTER TABLE auth_users DROP COLUMN webauthn_user_handle;
@@ -0,0 +1,20 @@
+ALTER TABLE auth_users ADD COLUMN webauthn_user_handle TEXT UNIQUE;
+
+CREATE TABLE passkeys (
+    credential_id TEXT PRIMARY KEY NOT NULL,
+    email TEXT NOT NULL REFERENCES auth_users(email) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    cose_key TEXT NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX passkeys_email_idx ON passkeys(email);
+
+CREATE TABLE webauthn_challenges (
+    challenge_hash TEXT PRIMARY KEY NOT NULL,
+    email TEXT REFERENCES auth_users(email) ON DELETE CASCADE,
+    return_to TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX webauthn_challenges_expires_at_idx ON webauthn_challenges(expires_at);
@@ -0,0 +1,2 @@
+DROP TABLE saml_requests;
+DROP TABLE instance_saml_configs;
@@ -0,0 +1,20 @@
+CREATE TABLE instance_saml_configs (
+    client_id TEXT PRIMARY KEY NOT NULL REFERENCES instances(client_id) ON DELETE CASCADE,
+    idp_entity_id TEXT NOT NULL,
+    idp_sso_url TEXT NOT NULL,
+    idp_certificate TEXT NOT NULL,
+    email_attribute TEXT,
+    role_attribute TEXT,
+    role_mapping JSONB NOT NULL DEFAULT '{}',
+    default_role TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE TABLE saml_requests (
+    request_id TEXT PRIMARY KEY NOT NULL,
+    client_id TEXT NOT NULL REFERENCES instances(client_id) ON DELETE CASCADE,
+    return_to TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX saml_requests_expires_at_idx ON saml_requests(expires_at);
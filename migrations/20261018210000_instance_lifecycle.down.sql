@@ -0,0 +1,5 @@
+ALTER TABLE instances DROP COLUMN churned_at;
+ALTER TABLE instances DROP COLUMN expired_at;
+ALTER TABLE instances DROP COLUMN activated_at;
+ALTER TABLE instances DROP CONSTRAINT instances_instance_state_check;
+ALTER TABLE instances ALTER COLUMN instance_state SET DEFAULT 'UNASSIGNED';
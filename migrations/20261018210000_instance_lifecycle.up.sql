@@ -0,0 +1,15 @@
+-- The column default used to be UNASSIGNED while the app wrote Unassigned
+UPDATE instances SET instance_state = CASE upper(instance_state)
+    WHEN 'UNASSIGNED' THEN 'Unassigned'
+    WHEN 'TRIAL' THEN 'Trial'
+    WHEN 'ACTIVE' THEN 'Active'
+    WHEN 'EXPIRED' THEN 'Expired'
+    WHEN 'CHURNED' THEN 'Churned'
+    ELSE instance_state
+END;
+ALTER TABLE instances ALTER COLUMN instance_state SET DEFAULT 'Unassigned';
+ALTER TABLE instances ADD CONSTRAINT instances_instance_state_check
+    CHECK (instance_state IN ('Unassigned', 'Trial', 'Active', 'Expired', 'Churned'));
+ALTER TABLE instances ADD COLUMN activated_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE instances ADD COLUMN expired_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE instances ADD COLUMN churned_at TIMESTAMP WITH TIME ZONE;
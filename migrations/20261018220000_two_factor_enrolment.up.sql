@@ -0,0 +1 @@
+ALTER TABLE two_factor_challenges ADD COLUMN enrolment_secret TEXT;
@@ -0,0 +1,2 @@
+-- The dropped plaintext tokens can't be brought back, and encrypted ones are still valid values for the column.
+SELECT 1;
@@ -0,0 +1,3 @@
+-- Upstream tokens are now encrypted before they're stored. Plaintext ones from before can't be told apart from
+-- those, so they are dropped and their sessions just aren't revoked upstream on logout.
+UPDATE sessions SET upstream_token = NULL WHERE upstream_token IS NOT NULL;
@@ -0,0 +1,2 @@
+-- The dropped plaintext secrets can't be brought back, and sealed ones are still valid values for the columns.
+SELECT 1;
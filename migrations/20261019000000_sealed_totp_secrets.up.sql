@@ -0,0 +1,5 @@
+-- TOTP secrets are now sealed with the keyring before they're stored. Plaintext ones from before can't be sealed in
+-- SQL, so those authenticators are dropped along with their recovery codes and have to be set up again.
+DELETE FROM recovery_codes WHERE email IN (SELECT email FROM totp_credentials WHERE secret NOT LIKE 'v4.local.%');
+DELETE FROM totp_credentials WHERE secret NOT LIKE 'v4.local.%';
+UPDATE two_factor_challenges SET enrolment_secret = NULL WHERE enrolment_secret NOT LIKE 'v4.local.%';
@@ -0,0 +1,57 @@
+use crate::auth::secret::{hash_secret, random_secret};
+use crate::db;
+use crate::errors::AuthAppError;
+use actix_web::http::header::Header;
+use actix_web::HttpRequest;
+use actix_web_httpauth::headers::authorization::{Authorization, Basic};
+use sqlx::PgPool;
+
+/// `instance_keys.key_type` for credentials Unleash instances use to call the introspection endpoint.
+pub const INTROSPECTION_KEY_TYPE: &str = "introspection";
+const SECRET_LENGTH: usize = 40;
+
+/// Generates a new instance secret, returning the secret to hand out and the hash to store.
+pub fn generate() -> (String, String) {
+    let secret = random_secret(SECRET_LENGTH);
+    let key_hash = hash_secret(&secret);
+    (secret, key_hash)
+}
+
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let auth = Authorization::<Basic>::parse(req).ok()?.into_scheme();
+    let secret = auth.password()?.to_string();
+    Some((auth.user_id().to_string(), secret))
+}
+
+/// Authenticates an instance by the `client_id:secret` pair in its `Authorization: Basic` header and returns
+/// the client id.
+pub async fn authenticate(
+    conn: &PgPool,
+    req: &HttpRequest,
+    key_type: &str,
+) -> Result<String, AuthAppError> {
+    let (client_id, secret) = basic_credentials(req).ok_or(AuthAppError::NotAuthenticated)?;
+    if db::instance_key::is_active(conn, &client_id, &hash_secret(&secret), key_type).await? {
+        Ok(client_id)
+    } else {
+        Err(AuthAppError::NotAuthenticated)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn credentials_are_read_from_basic_auth() {
+    use actix_web::http::header;
+    use actix_web::test::TestRequest;
+    let req = TestRequest::default()
+        .insert_header((header::AUTHORIZATION, "Basic ZGVtbzpzM2NyM3Q=")) // demo:s3cr3t
+        .to_http_request();
+    assert_eq!(
+        basic_credentials(&req),
+        Some(("demo".to_string(), "s3cr3t".to_string()))
+    );
+    let without_password = TestRequest::default()
+        .insert_header((header::AUTHORIZATION, "Basic ZGVtbw==")) // demo
+        .to_http_request();
+    assert_eq!(basic_credentials(&without_password), None);
+}
@@ -0,0 +1,246 @@
+use crate::errors::AuthAppError;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+
+/// A public key published by an identity provider in its JWKS document.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub alg: Option<String>,
+    pub crv: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    /// Finds the key a token was signed with. Tokens without a `kid` are only accepted from providers with one key.
+    pub fn find(&self, kid: Option<&str>) -> Option<&Jwk> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|k| k.kid.as_deref() == Some(kid)),
+            None if self.keys.len() == 1 => self.keys.first(),
+            None => None,
+        }
+    }
+}
+
+impl Jwk {
+    /// Checks a JWS signature. Only asymmetric algorithms are supported, so `none` and HMAC tokens never verify.
+    pub fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> bool {
+        if self.alg.as_deref().is_some_and(|a| a != alg) {
+            return false;
+        }
+        match (self.kty.as_str(), alg) {
+            ("RSA", "RS256") => {
+                self.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+            }
+            ("RSA", "RS384") => {
+                self.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA384, message, signature)
+            }
+            ("RSA", "RS512") => {
+                self.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA512, message, signature)
+            }
+            ("RSA", "PS256") => {
+                self.verify_rsa(&signature::RSA_PSS_2048_8192_SHA256, message, signature)
+            }
+            ("RSA", "PS384") => {
+                self.verify_rsa(&signature::RSA_PSS_2048_8192_SHA384, message, signature)
+            }
+            ("RSA", "PS512") => {
+                self.verify_rsa(&signature::RSA_PSS_2048_8192_SHA512, message, signature)
+            }
+            ("EC", "ES256") if self.crv.as_deref() == Some("P-256") => {
+                self.verify_ec(&signature::ECDSA_P256_SHA256_FIXED, message, signature)
+            }
+            ("EC", "ES384") if self.crv.as_deref() == Some("P-384") => {
+                self.verify_ec(&signature::ECDSA_P384_SHA384_FIXED, message, signature)
+            }
+            _ => false,
+        }
+    }
+
+    fn verify_rsa(
+        &self,
+        params: &'static signature::RsaParameters,
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        match (decode(&self.n), decode(&self.e)) {
+            (Some(n), Some(e)) => RsaPublicKeyComponents { n, e }
+                .verify(params, message, signature)
+                .is_ok(),
+            _ => false,
+        }
+    }
+
+    fn verify_ec(
+        &self,
+        algorithm: &'static signature::EcdsaVerificationAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        match (decode(&self.x), decode(&self.y)) {
+            (Some(x), Some(y)) => {
+                // Uncompressed SEC1 point
+                let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+                UnparsedPublicKey::new(algorithm, point)
+                    .verify(message, signature)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn decode(value: &Option<String>) -> Option<Vec<u8>> {
+    value.as_ref().and_then(|v| URL_SAFE_NO_PAD.decode(v).ok())
+}
+
+#[derive(Deserialize)]
+struct JwsHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// A compact serialized JWS, split up but not yet verified.
+pub struct SignedToken {
+    pub kid: Option<String>,
+    alg: String,
+    signing_input: String,
+    signature: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl SignedToken {
+    pub fn parse(token: &str) -> Result<SignedToken, AuthAppError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthAppError::InvalidToken);
+        };
+        let decoded_header: JwsHeader = URL_SAFE_NO_PAD
+            .decode(header)
+            .ok()
+            .and_then(|h| serde_json::from_slice(&h).ok())
+            .ok_or(AuthAppError::InvalidToken)?;
+        Ok(SignedToken {
+            kid: decoded_header.kid,
+            alg: decoded_header.alg,
+            signing_input: format!("{header}.{payload}"),
+            signature: URL_SAFE_NO_PAD
+                .decode(signature)
+                .map_err(|_| AuthAppError::InvalidToken)?,
+            payload: URL_SAFE_NO_PAD
+                .decode(payload)
+                .map_err(|_| AuthAppError::InvalidToken)?,
+        })
+    }
+
+    /// Returns the payload if the token was signed by its key in `keys`.
+    pub fn verify(&self, keys: &JwkSet) -> Result<&[u8], AuthAppError> {
+        let key = keys
+            .find(self.kid.as_deref())
+            .ok_or(AuthAppError::InvalidToken)?;
+        if key.verify(&self.alg, self.signing_input.as_bytes(), &self.signature) {
+            Ok(&self.payload)
+        } else {
+            Err(AuthAppError::InvalidToken)
+        }
+    }
+}
+
+#[cfg(test)]
+fn es256_key() -> (ring::signature::EcdsaKeyPair, Jwk) {
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    let key_pair =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+    let point = key_pair.public_key().as_ref();
+    let jwk = Jwk {
+        kty: "EC".to_string(),
+        kid: Some("test".to_string()),
+        alg: None,
+        crv: Some("P-256".to_string()),
+        n: None,
+        e: None,
+        x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+        y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+    };
+    (key_pair, jwk)
+}
+
+#[cfg(test)]
+fn sign(key_pair: &ring::signature::EcdsaKeyPair, header: &str, payload: &str) -> String {
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header),
+        URL_SAFE_NO_PAD.encode(payload)
+    );
+    let signature = key_pair
+        .sign(&ring::rand::SystemRandom::new(), signing_input.as_bytes())
+        .unwrap();
+    format!("{signing_input}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
+
+#[cfg(test)]
+#[test]
+fn es256_tokens_verify_with_their_key() {
+    let (key_pair, jwk) = es256_key();
+    let keys = JwkSet { keys: vec![jwk] };
+    let token = sign(
+        &key_pair,
+        r#"{"alg":"ES256","kid":"test"}"#,
+        r#"{"sub":"1"}"#,
+    );
+    let parsed = SignedToken::parse(&token).unwrap();
+    assert_eq!(parsed.verify(&keys).unwrap(), br#"{"sub":"1"}"#);
+
+    let (other_pair, _) = es256_key();
+    let forged = sign(
+        &other_pair,
+        r#"{"alg":"ES256","kid":"test"}"#,
+        r#"{"sub":"1"}"#,
+    );
+    assert!(SignedToken::parse(&forged).unwrap().verify(&keys).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn unsigned_and_unknown_key_tokens_are_rejected() {
+    let (key_pair, jwk) = es256_key();
+    let keys = JwkSet { keys: vec![jwk] };
+    let unsigned = format!(
+        "{}.{}.",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"none","kid":"test"}"#),
+        URL_SAFE_NO_PAD.encode(r#"{"sub":"1"}"#)
+    );
+    assert!(
+        SignedToken::parse(&unsigned)
+            .unwrap()
+            .verify(&keys)
+            .is_err()
+    );
+    let unknown_kid = sign(
+        &key_pair,
+        r#"{"alg":"ES256","kid":"other"}"#,
+        r#"{"sub":"1"}"#,
+    );
+    assert!(
+        SignedToken::parse(&unknown_kid)
+            .unwrap()
+            .verify(&keys)
+            .is_err()
+    );
+    assert!(SignedToken::parse("not-a-jwt").is_err());
+}
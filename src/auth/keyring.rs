@@ -0,0 +1,385 @@
+use crate::AppConfig;
+use crate::auth::token::TokenPurpose;
+use anyhow::{Context, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rusty_paseto::prelude::*;
+use serde::Deserialize;
+use std::fs;
+
+const KEY_LENGTH: usize = 32;
+const PUBLIC_KEY_LENGTH: usize = 32;
+
+/// A symmetric key used for session tokens, identified by the `kid` we put in the token footer.
+pub struct TokenKey {
+    pub id: String,
+    key: [u8; KEY_LENGTH],
+}
+
+impl TokenKey {
+    pub fn new(id: &str, secret: &str) -> anyhow::Result<TokenKey> {
+        let key: [u8; KEY_LENGTH] = secret
+            .as_bytes()
+            .try_into()
+            .map_err(|_| anyhow!("Secret for key {id} must be exactly {KEY_LENGTH} bytes"))?;
+        Ok(TokenKey {
+            id: id.to_string(),
+            key,
+        })
+    }
+
+    pub fn paseto_key(&self) -> PasetoSymmetricKey<V4, Local> {
+        PasetoSymmetricKey::<V4, Local>::from(Key::from(&self.key))
+    }
+}
+
+/// An Ed25519 key used to sign v4.public tokens, created from a hex encoded 32 byte seed.
+pub struct SigningKey {
+    pub id: String,
+    keypair: Key<64>,
+}
+
+impl SigningKey {
+    pub fn new(id: &str, hex_seed: &str) -> anyhow::Result<SigningKey> {
+        let seed: [u8; KEY_LENGTH] = hex::decode(hex_seed)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                anyhow!("Signing key {id} must be a hex encoded {KEY_LENGTH} byte seed")
+            })?;
+        let keypair = ed25519_dalek::SigningKey::from_bytes(&seed).to_keypair_bytes();
+        Ok(SigningKey {
+            id: id.to_string(),
+            keypair: Key::from(keypair),
+        })
+    }
+
+    pub fn paseto_key(&self) -> PasetoAsymmetricPrivateKey<'_, V4, Public> {
+        PasetoAsymmetricPrivateKey::<V4, Public>::from(&self.keypair)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            id: self.id.clone(),
+            key: Key::from(&self.keypair[KEY_LENGTH..]),
+        }
+    }
+}
+
+/// The public half of a [SigningKey], all a consumer needs to verify v4.public tokens.
+pub struct PublicKey {
+    pub id: String,
+    key: Key<PUBLIC_KEY_LENGTH>,
+}
+
+impl PublicKey {
+    pub fn new(id: &str, hex_key: &str) -> anyhow::Result<PublicKey> {
+        let key: [u8; PUBLIC_KEY_LENGTH] = hex::decode(hex_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                anyhow!("Public key {id} must be a hex encoded {PUBLIC_KEY_LENGTH} byte key")
+            })?;
+        Ok(PublicKey {
+            id: id.to_string(),
+            key: Key::from(key),
+        })
+    }
+
+    pub fn paseto_key(&self) -> PasetoAsymmetricPublicKey<'_, V4, Public> {
+        PasetoAsymmetricPublicKey::<V4, Public>::from(&self.key)
+    }
+
+    /// The key serialized as a PASERK, e.g. `k4.public.<base64url>`.
+    pub fn paserk(&self) -> String {
+        format!("k4.public.{}", URL_SAFE_NO_PAD.encode(self.key.as_ref()))
+    }
+}
+
+/// The active key used to mint new tokens, plus retired keys still accepted until their tokens expire.
+/// Signing keys for v4.public tokens follow the same scheme, except retired ones only need their public key.
+pub struct Keyring {
+    current: TokenKey,
+    previous: Vec<TokenKey>,
+    signing_key: Option<SigningKey>,
+    public_keys: Vec<PublicKey>,
+}
+
+#[derive(Deserialize)]
+struct KeyringFileEntry {
+    id: String,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+struct SigningKeyFileEntry {
+    id: String,
+    seed: String,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyFileEntry {
+    id: String,
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+struct SigningKeysFile {
+    current: SigningKeyFileEntry,
+    #[serde(default)]
+    previous: Vec<PublicKeyFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyringFile {
+    current: KeyringFileEntry,
+    #[serde(default)]
+    previous: Vec<KeyringFileEntry>,
+    signing: Option<SigningKeysFile>,
+}
+
+impl Keyring {
+    pub fn new(current: TokenKey, previous: Vec<TokenKey>) -> anyhow::Result<Keyring> {
+        let mut ids: Vec<&str> = previous.iter().map(|k| k.id.as_str()).collect();
+        ids.push(current.id.as_str());
+        ids.sort_unstable();
+        if let Some(duplicate) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(anyhow!("Key id {} is used more than once", duplicate[0]));
+        }
+        Ok(Keyring {
+            current,
+            previous,
+            signing_key: None,
+            public_keys: vec![],
+        })
+    }
+
+    /// Adds the key used to sign v4.public tokens, and the public keys of retired signing keys.
+    pub fn with_signing_keys(
+        self,
+        signing_key: SigningKey,
+        previous: Vec<PublicKey>,
+    ) -> anyhow::Result<Keyring> {
+        let mut public_keys = vec![signing_key.public_key()];
+        public_keys.extend(previous);
+        let mut ids: Vec<&str> = public_keys.iter().map(|k| k.id.as_str()).collect();
+        ids.sort_unstable();
+        if let Some(duplicate) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(anyhow!(
+                "Signing key id {} is used more than once",
+                duplicate[0]
+            ));
+        }
+        Ok(Keyring {
+            signing_key: Some(signing_key),
+            public_keys,
+            ..self
+        })
+    }
+
+    /// Loads the keyring from `keyring_file` if set, otherwise from `secret`, `secret_key_id` and `previous_secrets`,
+    /// plus `signing_key`, `signing_key_id` and `previous_public_keys`.
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Keyring> {
+        let keyring = Keyring::load(config)?;
+        if config.token_purpose == TokenPurpose::Public && keyring.signing_key().is_none() {
+            return Err(anyhow!("Issuing public tokens requires a signing key"));
+        }
+        Ok(keyring)
+    }
+
+    fn load(config: &AppConfig) -> anyhow::Result<Keyring> {
+        match &config.keyring_file {
+            Some(path) => Keyring::from_file(path),
+            None => {
+                let current = TokenKey::new(&config.secret_key_id, &config.secret)?;
+                let previous = config
+                    .previous_secrets
+                    .iter()
+                    .map(|entry| {
+                        entry
+                            .split_once(':')
+                            .ok_or_else(|| {
+                                anyhow!("Previous secrets must be given as <id>:<secret>")
+                            })
+                            .and_then(|(id, secret)| TokenKey::new(id, secret))
+                    })
+                    .collect::<anyhow::Result<Vec<TokenKey>>>()?;
+                Keyring::new(current, previous)?.with_configured_signing_keys(config)
+            }
+        }
+    }
+
+    fn with_configured_signing_keys(self, config: &AppConfig) -> anyhow::Result<Keyring> {
+        let Some(seed) = &config.signing_key else {
+            return Ok(self);
+        };
+        let signing_key = SigningKey::new(&config.signing_key_id, seed)?;
+        let previous = config
+            .previous_public_keys
+            .iter()
+            .map(|entry| {
+                entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Previous public keys must be given as <id>:<hex key>"))
+                    .and_then(|(id, key)| PublicKey::new(id, key))
+            })
+            .collect::<anyhow::Result<Vec<PublicKey>>>()?;
+        self.with_signing_keys(signing_key, previous)
+    }
+
+    pub fn from_file(path: &str) -> anyhow::Result<Keyring> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read keyring file {path}"))?;
+        Keyring::from_json(&contents)
+    }
+
+    fn from_json(contents: &str) -> anyhow::Result<Keyring> {
+        let file: KeyringFile =
+            serde_json::from_str(contents).context("Couldn't parse keyring file")?;
+        let current = TokenKey::new(&file.current.id, &file.current.secret)?;
+        let previous = file
+            .previous
+            .iter()
+            .map(|entry| TokenKey::new(&entry.id, &entry.secret))
+            .collect::<anyhow::Result<Vec<TokenKey>>>()?;
+        let keyring = Keyring::new(current, previous)?;
+        match file.signing {
+            Some(signing) => {
+                let signing_key = SigningKey::new(&signing.current.id, &signing.current.seed)?;
+                let previous = signing
+                    .previous
+                    .iter()
+                    .map(|entry| PublicKey::new(&entry.id, &entry.public_key))
+                    .collect::<anyhow::Result<Vec<PublicKey>>>()?;
+                keyring.with_signing_keys(signing_key, previous)
+            }
+            None => Ok(keyring),
+        }
+    }
+
+    pub fn current(&self) -> &TokenKey {
+        &self.current
+    }
+
+    pub fn find(&self, id: &str) -> Option<&TokenKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|k| k.id == id)
+    }
+
+    pub fn signing_key(&self) -> Option<&SigningKey> {
+        self.signing_key.as_ref()
+    }
+
+    /// Public keys for every signing key we still accept tokens from, the current one first.
+    pub fn public_keys(&self) -> &[PublicKey] {
+        &self.public_keys
+    }
+
+    pub fn find_public_key(&self, id: &str) -> Option<&PublicKey> {
+        self.public_keys.iter().find(|k| k.id == id)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn keyring_file_can_hold_current_and_previous_keys() {
+    let keyring = Keyring::from_json(
+        r#"{
+            "current": {"id": "2024", "secret": "abc123abc123abc123abc123abc12323"},
+            "previous": [{"id": "2023", "secret": "def456def456def456def456def45656"}]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(keyring.current().id, "2024");
+    assert!(keyring.find("2023").is_some());
+    assert!(keyring.find("2022").is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn previous_secrets_are_read_from_config() {
+    let config = AppConfig {
+        secret: "abc123abc123abc123abc123abc12323".to_string(),
+        secret_key_id: "new".to_string(),
+        previous_secrets: vec!["old:def456def456def456def456def45656".to_string()],
+        ..Default::default()
+    };
+    let keyring = Keyring::from_config(&config).unwrap();
+    assert_eq!(keyring.current().id, "new");
+    assert!(keyring.find("old").is_some());
+}
+
+#[cfg(test)]
+#[test]
+fn secrets_of_the_wrong_length_and_reused_ids_are_rejected() {
+    assert!(TokenKey::new("short", "too-short").is_err());
+    let duplicate = Keyring::new(
+        TokenKey::new("a", "abc123abc123abc123abc123abc12323").unwrap(),
+        vec![TokenKey::new("a", "def456def456def456def456def45656").unwrap()],
+    );
+    assert!(duplicate.is_err());
+}
+
+#[cfg(test)]
+const TEST_SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+#[cfg(test)]
+#[test]
+fn signing_keys_publish_their_public_half() {
+    // Test vector 1 from RFC 8032
+    let signing_key = SigningKey::new("signing", TEST_SEED).unwrap();
+    let public_key = signing_key.public_key();
+    assert_eq!(public_key.id, "signing");
+    assert_eq!(
+        hex::encode(public_key.key.as_ref()),
+        "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+    );
+    assert!(public_key.paserk().starts_with("k4.public."));
+    assert!(SigningKey::new("short", "abcd").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn public_tokens_require_a_signing_key() {
+    let config = AppConfig {
+        secret: "abc123abc123abc123abc123abc12323".to_string(),
+        token_purpose: TokenPurpose::Public,
+        ..Default::default()
+    };
+    assert!(Keyring::from_config(&config).is_err());
+    let with_signing_key = AppConfig {
+        signing_key: Some(TEST_SEED.to_string()),
+        signing_key_id: "signing-2024".to_string(),
+        previous_public_keys: vec![
+            "signing-2023:3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"
+                .to_string(),
+        ],
+        ..config
+    };
+    let keyring = Keyring::from_config(&with_signing_key).unwrap();
+    assert_eq!(keyring.signing_key().unwrap().id, "signing-2024");
+    assert_eq!(keyring.public_keys().len(), 2);
+    assert!(keyring.find_public_key("signing-2023").is_some());
+}
+
+#[cfg(test)]
+#[test]
+fn keyring_file_can_hold_signing_keys() {
+    let keyring = Keyring::from_json(&format!(
+        r#"{{
+            "current": {{"id": "2024", "secret": "abc123abc123abc123abc123abc12323"}},
+            "signing": {{
+                "current": {{"id": "signing-2024", "seed": "{TEST_SEED}"}},
+                "previous": [{{
+                    "id": "signing-2023",
+                    "public_key": "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"
+                }}]
+            }}
+        }}"#
+    ))
+    .unwrap();
+    assert_eq!(keyring.signing_key().unwrap().id, "signing-2024");
+    assert_eq!(keyring.public_keys()[0].id, "signing-2024");
+    assert!(keyring.find_public_key("signing-2023").is_some());
+}
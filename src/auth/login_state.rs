@@ -0,0 +1,157 @@
+use crate::errors::AuthAppError;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+/// What we need to remember between sending the user to the identity provider and the callback, keyed by the
+/// CSRF state we sent along.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LoginState {
+    pub pkce_verifier: String,
+    /// Expected in the ID token, for providers that issue one
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// The OIDC provider the login was started with, so its callback can't be completed by another provider
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// Validated page to send the user to once logged in
+    #[serde(default)]
+    pub return_to: Option<String>,
+}
+
+/// Holds login state for a limited time. `take` removes the state, so each one can only complete one login.
+#[async_trait]
+pub trait LoginStateStore: Send + Sync {
+    async fn save(&self, csrf_state: &str, login_state: LoginState) -> Result<(), AuthAppError>;
+
+    async fn take(&self, csrf_state: &str) -> Result<Option<LoginState>, AuthAppError>;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginStateStoreKind {
+    /// Only works when all logins for a user hit the same replica
+    #[default]
+    Memory,
+    Postgres,
+}
+
+pub fn store_for(
+    kind: LoginStateStoreKind,
+    ttl: Duration,
+    conn: PgPool,
+) -> Box<dyn LoginStateStore> {
+    match kind {
+        LoginStateStoreKind::Memory => Box::new(InMemoryLoginStateStore::new(ttl)),
+        LoginStateStoreKind::Postgres => Box::new(PostgresLoginStateStore::new(ttl, conn)),
+    }
+}
+
+pub struct InMemoryLoginStateStore {
+    ttl: Duration,
+    states: DashMap<String, (LoginState, DateTime<Utc>)>,
+}
+
+impl InMemoryLoginStateStore {
+    pub fn new(ttl: Duration) -> InMemoryLoginStateStore {
+        InMemoryLoginStateStore {
+            ttl,
+            states: DashMap::new(),
+        }
+    }
+
+    fn save_at(&self, csrf_state: &str, login_state: LoginState, now: DateTime<Utc>) {
+        // Abandoned logins are never taken, so sweep them out whenever a new one starts
+        self.states.retain(|_, (_, expires_at)| *expires_at > now);
+        self.states
+            .insert(csrf_state.to_string(), (login_state, now + self.ttl));
+    }
+
+    fn take_at(&self, csrf_state: &str, now: DateTime<Utc>) -> Option<LoginState> {
+        self.states
+            .remove(csrf_state)
+            .filter(|(_, (_, expires_at))| *expires_at > now)
+            .map(|(_, (login_state, _))| login_state)
+    }
+}
+
+#[async_trait]
+impl LoginStateStore for InMemoryLoginStateStore {
+    async fn save(&self, csrf_state: &str, login_state: LoginState) -> Result<(), AuthAppError> {
+        self.save_at(csrf_state, login_state, Utc::now());
+        Ok(())
+    }
+
+    async fn take(&self, csrf_state: &str) -> Result<Option<LoginState>, AuthAppError> {
+        Ok(self.take_at(csrf_state, Utc::now()))
+    }
+}
+
+/// Shares login state between replicas, so the callback can land on a different one than the login.
+pub struct PostgresLoginStateStore {
+    ttl: Duration,
+    conn: PgPool,
+}
+
+impl PostgresLoginStateStore {
+    pub fn new(ttl: Duration, conn: PgPool) -> PostgresLoginStateStore {
+        PostgresLoginStateStore { ttl, conn }
+    }
+}
+
+#[async_trait]
+impl LoginStateStore for PostgresLoginStateStore {
+    async fn save(&self, csrf_state: &str, login_state: LoginState) -> Result<(), AuthAppError> {
+        crate::db::login_state::delete_expired(&self.conn).await?;
+        crate::db::login_state::create(
+            &self.conn,
+            csrf_state,
+            Json(login_state),
+            Utc::now() + self.ttl,
+        )
+        .await
+    }
+
+    async fn take(&self, csrf_state: &str) -> Result<Option<LoginState>, AuthAppError> {
+        crate::db::login_state::take(&self.conn, csrf_state)
+            .await
+            .map(|state| state.map(|Json(s)| s))
+    }
+}
+
+#[cfg(test)]
+fn login_state(verifier: &str) -> LoginState {
+    LoginState {
+        pkce_verifier: verifier.to_string(),
+        nonce: None,
+        provider: None,
+        return_to: None,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn in_memory_login_state_can_only_be_taken_once() {
+    let store = InMemoryLoginStateStore::new(Duration::minutes(10));
+    let now = Utc::now();
+    store.save_at("state", login_state("verifier"), now);
+    assert_eq!(store.take_at("state", now), Some(login_state("verifier")));
+    assert_eq!(store.take_at("state", now), None);
+    assert_eq!(store.take_at("unknown", now), None);
+}
+
+#[cfg(test)]
+#[test]
+fn in_memory_login_state_expires_after_ttl() {
+    let store = InMemoryLoginStateStore::new(Duration::minutes(10));
+    let now = Utc::now();
+    store.save_at("abandoned", login_state("first"), now);
+    store.save_at("expired", login_state("second"), now);
+    assert_eq!(store.take_at("expired", now + Duration::minutes(11)), None);
+    store.save_at("new", login_state("third"), now + Duration::minutes(11));
+    assert_eq!(store.states.len(), 1, "expired states are swept on save");
+}
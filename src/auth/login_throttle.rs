@@ -0,0 +1,198 @@
+use crate::AppConfig;
+use crate::errors::AuthAppError;
+use actix_web::HttpRequest;
+use actix_web::http::header::HeaderName;
+use chrono::{DateTime, Duration, Utc};
+use std::net::IpAddr;
+
+/// Recent failed password logins for an account or a source IP.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailedLogins {
+    pub attempts: i32,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+/// Slows down password guessing: after a few failures each further attempt has to wait twice as long as the last,
+/// and after `max_attempts` the account or IP is locked out. Failures are forgotten once `lockout` has passed since
+/// the last one, or when the account logs in successfully.
+pub struct LoginThrottle {
+    delay_after: i32,
+    max_attempts: i32,
+    pub lockout: Duration,
+}
+
+impl LoginThrottle {
+    pub fn for_account(config: &AppConfig) -> LoginThrottle {
+        LoginThrottle {
+            delay_after: config.login_delay_after_attempts,
+            max_attempts: config.login_max_attempts,
+            lockout: Duration::seconds(config.login_lockout_secs),
+        }
+    }
+
+    /// Many users can share an IP behind a NAT, so IPs get a higher limit and no delays before it.
+    pub fn for_ip(config: &AppConfig) -> LoginThrottle {
+        LoginThrottle {
+            delay_after: config.login_max_attempts_per_ip,
+            max_attempts: config.login_max_attempts_per_ip,
+            lockout: Duration::seconds(config.login_lockout_secs),
+        }
+    }
+
+    pub fn locked_until(&self, failures: &FailedLogins) -> Option<DateTime<Utc>> {
+        if failures.attempts >= self.max_attempts {
+            Some(failures.last_failed_at + self.lockout)
+        } else if failures.attempts >= self.delay_after {
+            let exponent = (failures.attempts - self.delay_after).min(30) as u32;
+            let delay = Duration::seconds(2_i64.pow(exponent)).min(self.lockout);
+            Some(failures.last_failed_at + delay)
+        } else {
+            None
+        }
+    }
+
+    pub fn check(
+        &self,
+        failures: Option<&FailedLogins>,
+        now: DateTime<Utc>,
+    ) -> Result<(), AuthAppError> {
+        match failures.and_then(|f| self.locked_until(f)) {
+            Some(until) if until > now => Err(AuthAppError::AccountLocked),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The address failures are counted against. Requests through `trusted_proxies` are from the last address they
+/// forwarded for that isn't another of our proxies, since addresses before it were put there by the client.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let Some(mut client) = req.peer_addr().map(|a| a.ip()) else {
+        return "unknown".to_string();
+    };
+    let forwarded_for = req
+        .headers()
+        .get_all(HeaderName::from_static("x-forwarded-for"))
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect::<Vec<&str>>();
+    for hop in forwarded_for.into_iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client.to_string()
+}
+
+#[cfg(test)]
+fn throttle() -> LoginThrottle {
+    LoginThrottle {
+        delay_after: 3,
+        max_attempts: 10,
+        lockout: Duration::minutes(15),
+    }
+}
+
+#[cfg(test)]
+fn failures(attempts: i32, last_failed_at: DateTime<Utc>) -> FailedLogins {
+    FailedLogins {
+        attempts,
+        last_failed_at,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn delays_double_after_the_first_few_failures() {
+    let throttle = throttle();
+    let now = Utc::now();
+    assert!(throttle.check(None, now).is_ok());
+    assert!(throttle.check(Some(&failures(2, now)), now).is_ok());
+    assert_eq!(
+        throttle.locked_until(&failures(3, now)),
+        Some(now + Duration::seconds(1))
+    );
+    assert_eq!(
+        throttle.locked_until(&failures(5, now)),
+        Some(now + Duration::seconds(4))
+    );
+    assert!(
+        throttle
+            .check(Some(&failures(5, now)), now + Duration::seconds(3))
+            .is_err()
+    );
+    assert!(
+        throttle
+            .check(Some(&failures(5, now)), now + Duration::seconds(4))
+            .is_ok()
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn too_many_failures_lock_out_until_the_lockout_passes() {
+    let throttle = throttle();
+    let now = Utc::now();
+    assert_eq!(
+        throttle.locked_until(&failures(10, now)),
+        Some(now + Duration::minutes(15))
+    );
+    assert!(matches!(
+        throttle.check(Some(&failures(12, now)), now + Duration::minutes(14)),
+        Err(AuthAppError::AccountLocked)
+    ));
+    assert!(
+        throttle
+            .check(Some(&failures(12, now)), now + Duration::minutes(15))
+            .is_ok()
+    );
+    assert_eq!(
+        throttle.locked_until(&failures(9, now)),
+        Some(now + Duration::seconds(64))
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn forwarded_addresses_are_only_trusted_from_our_proxies() {
+    use actix_web::test::TestRequest;
+    let proxies: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+    let request = |peer: &str, forwarded_for: Option<&str>| {
+        let req = TestRequest::default().peer_addr(format!("{peer}:443").parse().unwrap());
+        match forwarded_for {
+            Some(f) => req.insert_header(("x-forwarded-for", f)),
+            None => req,
+        }
+        .to_http_request()
+    };
+    assert_eq!(
+        client_ip(&request("203.0.113.7", None), &proxies),
+        "203.0.113.7"
+    );
+    assert_eq!(
+        client_ip(&request("203.0.113.7", Some("198.51.100.1")), &proxies),
+        "203.0.113.7"
+    );
+    assert_eq!(
+        client_ip(&request("10.0.0.1", Some("198.51.100.1")), &proxies),
+        "198.51.100.1"
+    );
+    assert_eq!(
+        client_ip(
+            &request("10.0.0.1", Some("192.0.2.66, 198.51.100.1, 10.0.0.2")),
+            &proxies
+        ),
+        "198.51.100.1"
+    );
+    assert_eq!(
+        client_ip(&request("10.0.0.1", Some("not an address")), &proxies),
+        "10.0.0.1"
+    );
+    assert_eq!(
+        client_ip(&TestRequest::default().to_http_request(), &proxies),
+        "unknown"
+    );
+}
@@ -0,0 +1,178 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::auth::service_token;
+use crate::auth::token::AuthenticatedTokenUser;
+use crate::errors::AuthAppError;
+use crate::service::session;
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::http::header::Header;
+use actix_web::web::Data;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web_grants::authorities::{AuthDetails, AuthoritiesCheck};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
+use paperclip::actix::Apiv2Security;
+use sqlx::{Pool, Postgres};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Granted to users listed in `admin_emails`, allows managing every instance.
+pub const ADMIN: &str = "ADMIN";
+
+/// Authority for holding `role` on the instance `client_id`, e.g. `demo:ADMIN`.
+pub fn instance_authority(client_id: &str, role: &str) -> String {
+    format!("{}:{}", client_id, role.to_uppercase())
+}
+
+/// Reads the session token from the configured cookie, falling back to an `Authorization: Bearer` header.
+fn session_token(req: &HttpRequest, config: &AppConfig) -> Option<String> {
+    req.cookie(&config.cookie_name)
+        .map(|c| c.value().to_string())
+        .or_else(|| bearer_token(req))
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    Authorization::<Bearer>::parse(req)
+        .ok()
+        .map(|auth| auth.into_scheme().token().to_string())
+}
+
+async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedTokenUser, AuthAppError> {
+    let config = req
+        .app_data::<Data<AppConfig>>()
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    let keyring = req
+        .app_data::<Data<Keyring>>()
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    let conn = req
+        .app_data::<Data<Pool<Postgres>>>()
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    let session_token = session_token(req, config).ok_or(AuthAppError::NotAuthenticated)?;
+    session::validate(conn, config.as_ref().clone(), keyring, session_token).await
+}
+
+fn authorities_for(user: &AuthenticatedTokenUser, config: &AppConfig) -> HashSet<String> {
+    let mut authorities: HashSet<String> = user
+        .access
+        .iter()
+        .map(|a| instance_authority(&a.client_id, &a.role))
+        .collect();
+    if config
+        .admin_emails
+        .iter()
+        .any(|admin| admin.eq_ignore_ascii_case(&user.email))
+    {
+        authorities.insert(ADMIN.to_string());
+    }
+    authorities
+}
+
+/// Authorities extractor for [actix_web_grants::GrantsMiddleware]. Service tokens are granted their scopes, session
+/// tokens their roles, and the [AuthenticatedTokenUser] is made available to handlers behind the middleware.
+/// Requests without valid credentials are rejected.
+pub async fn extract_authorities(req: &ServiceRequest) -> Result<HashSet<String>, Error> {
+    if let Some(token) = bearer_token(req.request()).filter(|t| service_token::is_service_token(t)) {
+        let conn = req
+            .app_data::<Data<Pool<Postgres>>>()
+            .ok_or(AuthAppError::NotAuthenticated)?;
+        let scopes = service_token::authenticate(conn, &token).await?;
+        return Ok(scopes.into_iter().collect());
+    }
+    let user = authenticate(req.request()).await?;
+    let config = req
+        .app_data::<Data<AppConfig>>()
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    let authorities = authorities_for(&user, config);
+    req.extensions_mut().insert(user);
+    Ok(authorities)
+}
+
+impl FromRequest for AuthenticatedTokenUser {
+    type Error = AuthAppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let already_authenticated = req.extensions().get::<AuthenticatedTokenUser>().cloned();
+        let req = req.clone();
+        Box::pin(async move {
+            match already_authenticated {
+                Some(user) => Ok(user),
+                None => authenticate(&req).await,
+            }
+        })
+    }
+}
+
+/// The authorities the grants middleware attached to the request, for handlers that need to require one.
+#[derive(Apiv2Security)]
+#[openapi(
+    apiKey,
+    alias = "sessionToken",
+    in = "header",
+    name = "Authorization",
+    description = "Bearer token, or the session cookie set by the login callback"
+)]
+pub struct Authorities(AuthDetails);
+
+impl Authorities {
+    pub fn require(&self, authority: &str) -> Result<(), AuthAppError> {
+        self.require_any(&[authority])
+    }
+
+    pub fn require_any(&self, authorities: &[&str]) -> Result<(), AuthAppError> {
+        if self.0.has_any_authority(authorities) {
+            Ok(())
+        } else {
+            Err(AuthAppError::InsufficientPermissions)
+        }
+    }
+}
+
+impl FromRequest for Authorities {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let details = AuthDetails::from_request(req, payload);
+        Box::pin(async move {
+            details
+                .await
+                .map(Authorities)
+                .map_err(|_| AuthAppError::NotAuthenticated.into())
+        })
+    }
+}
+
+#[cfg(test)]
+#[actix_web::test]
+async fn requests_without_a_session_token_are_unauthorized() {
+    use crate::auth::keyring::TokenKey;
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::{App, HttpResponse, test, web};
+    use actix_web_grants::GrantsMiddleware;
+    async fn who_am_i(user: AuthenticatedTokenUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.email)
+    }
+    let keyring = Keyring::new(
+        TokenKey::new("primary", "abc123abc123abc123abc123abc12323").unwrap(),
+        vec![],
+    )
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(AppConfig::default()))
+            .app_data(web::Data::new(keyring))
+            .service(
+                web::scope("/protected")
+                    .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+                    .route("/me", web::get().to(who_am_i)),
+            ),
+    )
+    .await;
+    let res = app
+        .call(test::TestRequest::get().uri("/protected/me").to_request())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
@@ -1 +1,18 @@
+pub mod instance_key;
+pub mod jwks;
+pub mod keyring;
+pub mod login_state;
+pub mod login_throttle;
+pub mod middleware;
+pub mod oidc;
+pub mod password;
+pub mod refresh_token;
+pub mod return_to;
+pub mod saml;
+pub mod sealed;
+pub mod secret;
+pub mod service_token;
 pub mod token;
+pub mod totp;
+pub mod webauthn;
+pub mod xml;
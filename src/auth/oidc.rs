@@ -0,0 +1,411 @@
+use crate::AppConfig;
+use crate::auth::jwks::{JwkSet, SignedToken};
+use crate::errors::AuthAppError;
+use anyhow::{Context, anyhow};
+use chrono::Utc;
+use log::info;
+use oauth2::basic::{
+    BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+    BasicTokenType,
+};
+use oauth2::url::Url;
+use oauth2::{
+    AuthUrl, Client, ClientId, ClientSecret, EndpointNotSet, EndpointSet, ExtraTokenFields,
+    RedirectUrl, StandardRevocableToken, StandardTokenResponse, TokenUrl,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
+
+/// Paths already taken under `/api/auth`, so no provider can be registered there.
+const RESERVED_NAMES: [&str; 9] = [
+    "github",
+    "google",
+    "introspect",
+    "magic-link",
+    "passkey",
+    "password",
+    "refresh",
+    "saml",
+    "two-factor",
+];
+
+/// One entry in the providers file, e.g. an Okta or Keycloak tenant.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".to_string(), "email".to_string()]
+}
+
+/// The parts of `.well-known/openid-configuration` we need.
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdTokenFields {
+    pub id_token: String,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+pub type OidcTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+
+pub type OidcClient = Client<
+    BasicErrorResponse,
+    OidcTokenResponse,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointSet,
+>;
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct IdTokenClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+impl IdTokenClaims {
+    /// The email the provider vouches for. Providers that let users set an address without proving they own it
+    /// leave out `email_verified` or set it to false, and those addresses can't be trusted to pick an account.
+    pub fn verified_email(self) -> Option<String> {
+        match self.email_verified {
+            Some(true) => self.email,
+            _ => None,
+        }
+    }
+}
+
+pub struct OidcProvider {
+    pub name: String,
+    pub client: OidcClient,
+    pub scopes: Vec<String>,
+    issuer: String,
+    client_id: String,
+    jwks_uri: Url,
+    keys: RwLock<JwkSet>,
+    leeway_secs: i64,
+}
+
+impl OidcProvider {
+    /// Reads the provider's discovery document and signing keys.
+    pub async fn discover(
+        http_client: &oauth2::reqwest::Client,
+        provider: OidcProviderConfig,
+        config: &AppConfig,
+    ) -> anyhow::Result<OidcProvider> {
+        let name = provider.name;
+        if !valid_name(&name) {
+            return Err(anyhow!(
+                "OIDC provider name {name} must be lowercase letters, digits and dashes, and not one of {RESERVED_NAMES:?}"
+            ));
+        }
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = get_json(http_client, &discovery_url)
+            .await
+            .with_context(|| format!("Couldn't discover OIDC provider {name}"))?;
+        if metadata.issuer != provider.issuer {
+            return Err(anyhow!(
+                "OIDC provider {name} reports issuer {} instead of {}",
+                metadata.issuer,
+                provider.issuer
+            ));
+        }
+        let redirect_url = Url::parse(&config.public_url)
+            .and_then(|u| u.join(&format!("/api/auth/{name}/callback")))
+            .with_context(|| format!("Invalid redirect URL for OIDC provider {name}"))?;
+        let client = Client::new(ClientId::new(provider.client_id.clone()))
+            .set_client_secret(ClientSecret::new(provider.client_secret))
+            .set_auth_uri(AuthUrl::new(metadata.authorization_endpoint)?)
+            .set_token_uri(TokenUrl::new(metadata.token_endpoint)?)
+            .set_redirect_uri(RedirectUrl::from_url(redirect_url));
+        let jwks_uri = Url::parse(&metadata.jwks_uri)?;
+        let keys: JwkSet = get_json(http_client, jwks_uri.as_str())
+            .await
+            .with_context(|| format!("Couldn't fetch signing keys of OIDC provider {name}"))?;
+        Ok(OidcProvider {
+            name,
+            client,
+            scopes: provider.scopes,
+            issuer: metadata.issuer,
+            client_id: provider.client_id,
+            jwks_uri,
+            keys: RwLock::new(keys),
+            leeway_secs: config.token_leeway_secs,
+        })
+    }
+
+    /// Checks the signature and claims of an ID token from the token endpoint. Keys are refetched once when the
+    /// token names a key we haven't seen, since that is how providers rotate.
+    pub async fn validate_id_token(
+        &self,
+        http_client: &oauth2::reqwest::Client,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AuthAppError> {
+        let token = SignedToken::parse(id_token)?;
+        let known_key = self
+            .keys
+            .read()
+            .expect("OIDC key lock poisoned")
+            .find(token.kid.as_deref())
+            .is_some();
+        if !known_key {
+            info!("Refreshing signing keys of OIDC provider {}", self.name);
+            match get_json::<JwkSet>(http_client, self.jwks_uri.as_str()).await {
+                Ok(keys) => *self.keys.write().expect("OIDC key lock poisoned") = keys,
+                Err(e) => info!("Couldn't refresh signing keys of {}: {e:#}", self.name),
+            }
+        }
+        let claims: IdTokenClaims = {
+            let keys = self.keys.read().expect("OIDC key lock poisoned");
+            serde_json::from_slice(token.verify(&keys)?).map_err(|_| AuthAppError::InvalidToken)?
+        };
+        self.validate_claims_at(&claims, nonce, Utc::now().timestamp())?;
+        Ok(claims)
+    }
+
+    fn validate_claims_at(
+        &self,
+        claims: &IdTokenClaims,
+        nonce: &str,
+        now: i64,
+    ) -> Result<(), AuthAppError> {
+        if claims.iss != self.issuer || !claims.aud.contains(&self.client_id) {
+            return Err(AuthAppError::InvalidToken);
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AuthAppError::InvalidToken);
+        }
+        if claims.exp + self.leeway_secs < now {
+            return Err(AuthAppError::TokenExpired);
+        }
+        Ok(())
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED_NAMES.contains(&name)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+async fn get_json<T: DeserializeOwned>(
+    http_client: &oauth2::reqwest::Client,
+    url: &str,
+) -> anyhow::Result<T> {
+    let bytes = http_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// All configured OIDC providers, by the name used in their login and callback paths.
+#[derive(Default)]
+pub struct OidcProviders {
+    providers: HashMap<String, OidcProvider>,
+}
+
+impl OidcProviders {
+    pub fn new(providers: Vec<OidcProvider>) -> OidcProviders {
+        OidcProviders {
+            providers: providers.into_iter().map(|p| (p.name.clone(), p)).collect(),
+        }
+    }
+
+    /// Discovers every provider in the configured providers file, or none when there is no file.
+    pub async fn from_config(
+        http_client: &oauth2::reqwest::Client,
+        config: &AppConfig,
+    ) -> anyhow::Result<OidcProviders> {
+        let Some(path) = &config.oidc_providers_file else {
+            return Ok(OidcProviders::default());
+        };
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read OIDC providers file {path}"))?;
+        let provider_configs: Vec<OidcProviderConfig> = serde_json::from_str(&json)
+            .with_context(|| format!("Couldn't parse OIDC providers file {path}"))?;
+        let mut providers = vec![];
+        for provider in provider_configs {
+            providers.push(OidcProvider::discover(http_client, provider, config).await?);
+        }
+        Ok(OidcProviders::new(providers))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OidcProvider> {
+        self.providers.get(name)
+    }
+}
+
+#[cfg(test)]
+fn test_provider() -> OidcProvider {
+    OidcProvider {
+        name: "okta".to_string(),
+        client: Client::new(ClientId::new("client".to_string()))
+            .set_auth_uri(AuthUrl::new("https://idp.example.com/auth".to_string()).unwrap())
+            .set_token_uri(TokenUrl::new("https://idp.example.com/token".to_string()).unwrap()),
+        scopes: default_scopes(),
+        issuer: "https://idp.example.com".to_string(),
+        client_id: "client".to_string(),
+        jwks_uri: Url::parse("https://idp.example.com/jwks").unwrap(),
+        keys: RwLock::new(JwkSet::default()),
+        leeway_secs: 30,
+    }
+}
+
+#[cfg(test)]
+fn claims(iss: &str, aud: Audience, exp: i64, nonce: Option<&str>) -> IdTokenClaims {
+    IdTokenClaims {
+        iss: iss.to_string(),
+        aud,
+        exp,
+        nonce: nonce.map(|n| n.to_string()),
+        email: Some("user@example.com".to_string()),
+        email_verified: Some(true),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn id_token_claims_must_match_provider_client_and_nonce() {
+    let provider = test_provider();
+    let now = 1_700_000_000;
+    let issuer = "https://idp.example.com";
+    let client = || Audience::One("client".to_string());
+    assert!(
+        provider
+            .validate_claims_at(&claims(issuer, client(), now + 60, Some("n")), "n", now)
+            .is_ok()
+    );
+    let many = Audience::Many(vec!["other".to_string(), "client".to_string()]);
+    assert!(
+        provider
+            .validate_claims_at(&claims(issuer, many, now + 60, Some("n")), "n", now)
+            .is_ok()
+    );
+
+    assert!(
+        provider
+            .validate_claims_at(
+                &claims("https://evil.example.com", client(), now + 60, Some("n")),
+                "n",
+                now
+            )
+            .is_err()
+    );
+    assert!(
+        provider
+            .validate_claims_at(
+                &claims(
+                    issuer,
+                    Audience::One("other".to_string()),
+                    now + 60,
+                    Some("n")
+                ),
+                "n",
+                now
+            )
+            .is_err()
+    );
+    assert!(
+        provider
+            .validate_claims_at(
+                &claims(issuer, client(), now + 60, Some("replayed")),
+                "n",
+                now
+            )
+            .is_err()
+    );
+    assert!(
+        provider
+            .validate_claims_at(&claims(issuer, client(), now + 60, None), "n", now)
+            .is_err()
+    );
+    assert!(
+        provider
+            .validate_claims_at(&claims(issuer, client(), now - 60, Some("n")), "n", now)
+            .is_err()
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn only_verified_emails_are_used() {
+    let issuer = "https://idp.example.com";
+    let verified = |email_verified| IdTokenClaims {
+        email_verified,
+        ..claims(issuer, Audience::One("client".to_string()), 0, None)
+    };
+    assert_eq!(
+        verified(Some(true)).verified_email(),
+        Some("user@example.com".to_string())
+    );
+    assert_eq!(verified(Some(false)).verified_email(), None);
+    assert_eq!(verified(None).verified_email(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn provider_names_cannot_shadow_other_auth_routes() {
+    assert!(valid_name("okta"));
+    assert!(valid_name("azure-ad"));
+    assert!(!valid_name("google"));
+    assert!(!valid_name("github"));
+    assert!(!valid_name("refresh"));
+    assert!(!valid_name("password"));
+    assert!(!valid_name("magic-link"));
+    assert!(!valid_name("two-factor"));
+    assert!(!valid_name("passkey"));
+    assert!(!valid_name("saml"));
+    assert!(!valid_name("Okta"));
+    assert!(!valid_name("okta/../x"));
+    assert!(!valid_name(""));
+}
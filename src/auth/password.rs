@@ -0,0 +1,115 @@
+use crate::errors::AuthAppError;
+use argon2::Config;
+use rand::Rng;
+use std::sync::LazyLock;
+
+/// Stored for users who haven't set a password. It is not a valid argon2 hash, so nothing verifies against it.
+pub const UNUSABLE_PASSWORD: &str = "!";
+
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+/// Bounds the work a single login attempt can cause.
+pub const MAX_PASSWORD_LENGTH: usize = 256;
+
+/// Checked instead of a stored hash that isn't usable, so a login takes as long whether or not the email has a password.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_blocking(&rand::rng().random::<[u8; 16]>()));
+
+pub fn validate_new(password: &str) -> Result<(), AuthAppError> {
+    let length = password.chars().count();
+    if (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        Ok(())
+    } else {
+        Err(AuthAppError::InvalidPassword)
+    }
+}
+
+pub fn is_usable(password_hash: &str) -> bool {
+    password_hash.starts_with("$argon2id$")
+}
+
+fn hash_blocking(password: &[u8]) -> String {
+    let salt: [u8; 16] = rand::rng().random();
+    argon2::hash_encoded(password, &salt, &Config::default()).expect("Argon2 config is valid")
+}
+
+/// The stored hash when it is usable. Unknown users and users without a password are checked against [DUMMY_HASH]
+/// instead, so the check costs the same and timing doesn't tell which emails have a password.
+fn hash_to_verify(password_hash: Option<&str>) -> &str {
+    password_hash
+        .filter(|hash| is_usable(hash))
+        .unwrap_or(&DUMMY_HASH)
+}
+
+fn verify_blocking(password: &[u8], password_hash: Option<&str>) -> bool {
+    let valid = argon2::verify_encoded(hash_to_verify(password_hash), password).unwrap_or(false);
+    valid && password_hash.is_some_and(is_usable)
+}
+
+/// Hashes a password with argon2id, off the async runtime since it is deliberately slow.
+pub async fn hash(password: &str) -> String {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_blocking(password.as_bytes()))
+        .await
+        .expect("Password hashing panicked")
+}
+
+/// Checks a password against the stored hash, `None` when there is no such user.
+pub async fn verify(password: &str, password_hash: Option<String>) -> bool {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        verify_blocking(password.as_bytes(), password_hash.as_deref())
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+#[test]
+fn passwords_verify_against_their_own_hash_only() {
+    let hash = hash_blocking(b"correct horse battery staple");
+    assert!(hash.starts_with("$argon2id$v=19$"));
+    assert!(verify_blocking(
+        b"correct horse battery staple",
+        Some(&hash)
+    ));
+    assert!(!verify_blocking(
+        b"correct horse battery stapler",
+        Some(&hash)
+    ));
+    assert_ne!(hash, hash_blocking(b"correct horse battery staple"));
+}
+
+#[cfg(test)]
+#[test]
+fn unusable_and_missing_hashes_never_verify() {
+    assert!(!verify_blocking(b"!", Some(UNUSABLE_PASSWORD)));
+    assert!(!verify_blocking(
+        b"Plaintext-Pa55word",
+        Some("Plaintext-Pa55word")
+    ));
+    assert!(!verify_blocking(b"anything", None));
+    let argon2i =
+        "$argon2i$v=19$m=4096,t=3,p=1$c29tZXNhbHQ$OlcSvlN20Lz43sK3jhCJ9K04oejhiY0AmI+ck6nuETo";
+    assert!(!verify_blocking(b"password", Some(argon2i)));
+}
+
+#[cfg(test)]
+#[test]
+fn only_usable_hashes_are_verified_against() {
+    let hash = hash_blocking(b"correct horse battery staple");
+    assert_eq!(hash_to_verify(Some(&hash)), hash);
+    assert_eq!(hash_to_verify(Some(UNUSABLE_PASSWORD)), DUMMY_HASH.as_str());
+    assert_eq!(
+        hash_to_verify(Some("Plaintext-Pa55word")),
+        DUMMY_HASH.as_str()
+    );
+    assert_eq!(hash_to_verify(None), DUMMY_HASH.as_str());
+}
+
+#[cfg(test)]
+#[test]
+fn new_passwords_must_have_a_sensible_length() {
+    assert!(validate_new("short").is_err());
+    assert!(validate_new("twelve chars").is_ok());
+    assert!(validate_new(&"x".repeat(MAX_PASSWORD_LENGTH + 1)).is_err());
+}
@@ -0,0 +1,36 @@
+use crate::auth::secret::{hash_secret, random_secret};
+
+const REFRESH_TOKEN_LENGTH: usize = 48;
+const FAMILY_ID_LENGTH: usize = 24;
+
+/// Refresh tokens are opaque and single use. Each one belongs to a family started at login, so reuse of a
+/// spent token can take down every token descended from the same login.
+pub struct NewRefreshToken {
+    pub token: String,
+    pub token_hash: String,
+}
+
+pub fn generate() -> NewRefreshToken {
+    let token = random_secret(REFRESH_TOKEN_LENGTH);
+    let token_hash = hash_secret(&token);
+    NewRefreshToken { token, token_hash }
+}
+
+pub fn new_family_id() -> String {
+    random_secret(FAMILY_ID_LENGTH)
+}
+
+pub fn hash(token: &str) -> String {
+    hash_secret(token)
+}
+
+#[cfg(test)]
+#[test]
+fn refresh_tokens_are_stored_by_hash() {
+    let first = generate();
+    let second = generate();
+    assert_eq!(first.token.len(), REFRESH_TOKEN_LENGTH);
+    assert_eq!(hash(&first.token), first.token_hash);
+    assert_ne!(first.token, second.token);
+    assert_ne!(new_family_id(), new_family_id());
+}
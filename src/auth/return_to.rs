@@ -0,0 +1,116 @@
+use crate::AppConfig;
+use crate::errors::AuthAppError;
+use oauth2::url::Url;
+
+/// Where users go after logging in when they didn't ask for anywhere in particular.
+pub const DEFAULT_RETURN_TO: &str = crate::controllers::pages::INSTANCE_CHOOSER_PATH;
+
+/// Checks a post-login redirect target so we can't be used as an open redirect. Paths on this app are always fine,
+/// absolute URLs only when they point at this app, at an instance under `base_url`, or at one of `return_to_hosts`.
+pub fn validate(config: &AppConfig, return_to: &str) -> Result<String, AuthAppError> {
+    // Browsers drop tabs and newlines from URLs, so `/\t/evil.com` would end up as `//evil.com`
+    if return_to
+        .chars()
+        .any(|c| c.is_control() || c.is_whitespace())
+    {
+        return Err(AuthAppError::InvalidReturnTo);
+    }
+    if return_to.starts_with('/') {
+        // `//host` and `/\host` are protocol relative URLs to browsers, not paths, so resolve the path the way a
+        // browser would and make sure we stay on this app
+        let public_url =
+            Url::parse(&config.public_url).map_err(|_| AuthAppError::InvalidReturnTo)?;
+        let resolved = public_url
+            .join(return_to)
+            .map_err(|_| AuthAppError::InvalidReturnTo)?;
+        return if resolved.origin() == public_url.origin() {
+            Ok(return_to.to_string())
+        } else {
+            Err(AuthAppError::InvalidReturnTo)
+        };
+    }
+    let url = Url::parse(return_to).map_err(|_| AuthAppError::InvalidReturnTo)?;
+    let allowed_scheme = matches!(url.scheme(), "https" | "http");
+    let has_credentials = !url.username().is_empty() || url.password().is_some();
+    match url.host_str() {
+        Some(host) if allowed_scheme && !has_credentials && allowed_host(config, host) => {
+            Ok(url.to_string())
+        }
+        _ => Err(AuthAppError::InvalidReturnTo),
+    }
+}
+
+fn allowed_host(config: &AppConfig, host: &str) -> bool {
+    let public_host = Url::parse(&config.public_url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()));
+    if public_host.as_deref() == Some(host) {
+        return true;
+    }
+    let instance_hosts = format!("*.{}", config.base_url);
+    std::iter::once(config.base_url.as_str())
+        .chain(std::iter::once(instance_hosts.as_str()))
+        .chain(config.return_to_hosts.iter().map(|h| h.as_str()))
+        .any(|pattern| host_matches(pattern, host))
+}
+
+/// `*.example.com` matches any subdomain of example.com, but not example.com itself.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+fn config() -> AppConfig {
+    AppConfig {
+        public_url: "https://auth.example.com".to_string(),
+        base_url: "app.unleash-hosted.com".to_string(),
+        return_to_hosts: vec!["*.customer.io".to_string()],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn paths_and_our_hosts_are_valid_return_targets() {
+    let config = config();
+    assert_eq!(validate(&config, "/instances/1").unwrap(), "/instances/1");
+    for url in [
+        "https://auth.example.com/admin",
+        "https://app.unleash-hosted.com/demo/",
+        "https://eu.app.unleash-hosted.com/demo/",
+        "https://unleash.customer.io/",
+    ] {
+        assert_eq!(validate(&config, url).unwrap(), url);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn open_redirects_are_rejected() {
+    let config = config();
+    for url in [
+        "//evil.com/",
+        "/\\evil.com",
+        "/\t/evil.com",
+        "/\n/evil.com",
+        "/\r/evil.com",
+        "/ /evil.com",
+        "\t//evil.com",
+        "https://evil.com/",
+        "https://app.unleash-hosted.com.evil.com/",
+        "https://evilapp.unleash-hosted.com/",
+        "https://customer.io/",
+        "https://user@evil.com@app.unleash-hosted.com/",
+        "javascript:alert(1)",
+        "evil.com",
+    ] {
+        assert!(validate(&config, url).is_err(), "{url} should be rejected");
+    }
+}
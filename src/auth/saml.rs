@@ -0,0 +1,529 @@
+use crate::AppConfig;
+use crate::auth::xml::{self, Element, escape_attribute, escape_text};
+use crate::errors::AuthAppError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use log::info;
+use oauth2::url::Url;
+use ring::digest;
+use rustls_pki_types::CertificateDer;
+use std::collections::HashMap;
+use std::io::Write;
+use subtle::ConstantTimeEq;
+use webpki::EndEntityCert;
+
+pub const SAML_PATH: &str = "/api/auth/saml";
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const METADATA_NS: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const HTTP_REDIRECT_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const EMAIL_NAME_ID: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+/// Allowed difference between our clock and the IdP's when checking validity windows.
+const CLOCK_SKEW_SECS: i64 = 60;
+
+/// What we need from an IdP's metadata to send users there and trust what they come back with.
+#[derive(Debug, PartialEq)]
+pub struct IdentityProvider {
+    pub entity_id: String,
+    /// Where AuthnRequests are sent with the HTTP-Redirect binding
+    pub sso_url: String,
+    /// DER of the certificate the IdP signs with
+    pub certificate: Vec<u8>,
+}
+
+/// Us, as the service provider of one instance.
+#[derive(Debug, PartialEq)]
+pub struct ServiceProvider {
+    pub entity_id: String,
+    pub acs_url: String,
+}
+
+/// The parts of a validated assertion we act on.
+#[derive(Debug, PartialEq)]
+pub struct SamlAssertion {
+    /// ID of the AuthnRequest the assertion answers, unsolicited responses are refused
+    pub in_response_to: String,
+    pub name_id: Option<String>,
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+impl SamlAssertion {
+    pub fn attribute_values(&self, name: &str) -> &[String] {
+        self.attributes
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+impl ServiceProvider {
+    /// Every instance is a service provider of its own, so IdPs can tell them apart.
+    pub fn for_instance(
+        config: &AppConfig,
+        client_id: &str,
+    ) -> Result<ServiceProvider, AuthAppError> {
+        let base = Url::parse(&config.public_url)
+            .and_then(|u| u.join(&format!("{SAML_PATH}/{client_id}/")))
+            .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+        Ok(ServiceProvider {
+            entity_id: format!("{base}metadata"),
+            acs_url: format!("{base}acs"),
+        })
+    }
+
+    /// Metadata customers upload to their IdP to set us up.
+    pub fn metadata(&self) -> String {
+        format!(
+            "<md:EntityDescriptor xmlns:md=\"{METADATA_NS}\" entityID=\"{}\">\
+             <md:SPSSODescriptor AuthnRequestsSigned=\"false\" WantAssertionsSigned=\"true\" \
+             protocolSupportEnumeration=\"{PROTOCOL_NS}\">\
+             <md:NameIDFormat>{EMAIL_NAME_ID}</md:NameIDFormat>\
+             <md:AssertionConsumerService Binding=\"{HTTP_POST_BINDING}\" Location=\"{}\" index=\"0\" isDefault=\"true\"/>\
+             </md:SPSSODescriptor>\
+             </md:EntityDescriptor>",
+            escape_attribute(&self.entity_id),
+            escape_attribute(&self.acs_url)
+        )
+    }
+
+    /// Where to send the user to log in at the IdP, carrying an AuthnRequest with `request_id` as the HTTP-Redirect
+    /// binding does.
+    pub fn login_url(
+        &self,
+        idp: &IdentityProvider,
+        request_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Url, AuthAppError> {
+        let request = format!(
+            "<samlp:AuthnRequest xmlns:samlp=\"{PROTOCOL_NS}\" xmlns:saml=\"{ASSERTION_NS}\" ID=\"{}\" Version=\"2.0\" \
+             IssueInstant=\"{}\" Destination=\"{}\" AssertionConsumerServiceURL=\"{}\" ProtocolBinding=\"{HTTP_POST_BINDING}\">\
+             <saml:Issuer>{}</saml:Issuer>\
+             <samlp:NameIDPolicy AllowCreate=\"true\"/>\
+             </samlp:AuthnRequest>",
+            escape_attribute(request_id),
+            now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            escape_attribute(&idp.sso_url),
+            escape_attribute(&self.acs_url),
+            escape_text(&self.entity_id)
+        );
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let deflated = encoder
+            .write_all(request.as_bytes())
+            .and_then(|_| encoder.finish())
+            .expect("Deflating into memory doesn't fail");
+        let mut url =
+            Url::parse(&idp.sso_url).map_err(|e| AuthAppError::InvalidSaml(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(deflated));
+        Ok(url)
+    }
+
+    /// Checks a response posted to our ACS URL: it has to be signed by the IdP, meant for us, current, and answer one
+    /// of our requests. Which request is left to the caller, as only it knows which ones are outstanding.
+    pub fn validate_response(
+        &self,
+        idp: &IdentityProvider,
+        saml_response: &str,
+        now: DateTime<Utc>,
+    ) -> Result<SamlAssertion, AuthAppError> {
+        let xml = decode_base64(saml_response).and_then(|bytes| {
+            String::from_utf8(bytes).map_err(|e| AuthAppError::InvalidSaml(e.to_string()))
+        })?;
+        let response = xml::parse(&xml)?;
+        if !response.is(PROTOCOL_NS, "Response") {
+            return Err(AuthAppError::InvalidSaml("Not a SAML response".into()));
+        }
+        if response
+            .attribute("Destination")
+            .is_some_and(|d| d != self.acs_url)
+        {
+            return Err(refuse("response is meant for another destination"));
+        }
+        if let Some(issuer) = response.child(ASSERTION_NS, "Issuer")
+            && issuer.text().trim() != idp.entity_id
+        {
+            return Err(refuse("response comes from another issuer"));
+        }
+        let status = response
+            .child(PROTOCOL_NS, "Status")
+            .and_then(|s| s.child(PROTOCOL_NS, "StatusCode"))
+            .and_then(|c| c.attribute("Value"));
+        if status != Some(STATUS_SUCCESS) {
+            return Err(refuse(&format!("IdP reported status {status:?}")));
+        }
+        if response.child(ASSERTION_NS, "EncryptedAssertion").is_some() {
+            return Err(AuthAppError::InvalidSaml(
+                "Encrypted assertions are not supported".into(),
+            ));
+        }
+        let assertions: Vec<&Element> =
+            response.children_named(ASSERTION_NS, "Assertion").collect();
+        let [assertion] = assertions.as_slice() else {
+            return Err(refuse("response doesn't hold exactly one assertion"));
+        };
+        // Either signature covers the assertion we read below, as both are checked against the element they are in
+        let response_signed = verify_signature(&response, idp)?;
+        let assertion_signed = verify_signature(assertion, idp)?;
+        if !response_signed && !assertion_signed {
+            return Err(refuse("neither the response nor the assertion is signed"));
+        }
+        self.validate_assertion(&response, assertion, idp, now)
+    }
+
+    fn validate_assertion(
+        &self,
+        response: &Element,
+        assertion: &Element,
+        idp: &IdentityProvider,
+        now: DateTime<Utc>,
+    ) -> Result<SamlAssertion, AuthAppError> {
+        let skew = Duration::seconds(CLOCK_SKEW_SECS);
+        if assertion
+            .child(ASSERTION_NS, "Issuer")
+            .is_none_or(|i| i.text().trim() != idp.entity_id)
+        {
+            return Err(refuse("assertion comes from another issuer"));
+        }
+
+        let conditions = assertion
+            .child(ASSERTION_NS, "Conditions")
+            .ok_or_else(|| refuse("assertion has no conditions"))?;
+        if let Some(not_before) = conditions.attribute("NotBefore")
+            && parse_instant(not_before)? > now + skew
+        {
+            return Err(refuse("assertion is not valid yet"));
+        }
+        if let Some(not_on_or_after) = conditions.attribute("NotOnOrAfter")
+            && parse_instant(not_on_or_after)? <= now - skew
+        {
+            return Err(refuse("assertion has expired"));
+        }
+        let restrictions: Vec<&Element> = conditions
+            .children_named(ASSERTION_NS, "AudienceRestriction")
+            .collect();
+        let for_us = |restriction: &&Element| {
+            restriction
+                .children_named(ASSERTION_NS, "Audience")
+                .any(|a| a.text().trim() == self.entity_id)
+        };
+        if restrictions.is_empty() || !restrictions.iter().all(for_us) {
+            return Err(refuse("assertion is meant for another audience"));
+        }
+
+        let subject = assertion
+            .child(ASSERTION_NS, "Subject")
+            .ok_or_else(|| refuse("assertion has no subject"))?;
+        let mut in_response_to = None;
+        for confirmation in subject.children_named(ASSERTION_NS, "SubjectConfirmation") {
+            let Some(data) = confirmation.child(ASSERTION_NS, "SubjectConfirmationData") else {
+                continue;
+            };
+            let current = match data.attribute("NotOnOrAfter") {
+                Some(not_on_or_after) => parse_instant(not_on_or_after)? > now - skew,
+                None => false,
+            };
+            if confirmation.attribute("Method") == Some(BEARER)
+                && data.attribute("Recipient") == Some(self.acs_url.as_str())
+                && current
+            {
+                in_response_to = data.attribute("InResponseTo");
+                break;
+            }
+        }
+        let in_response_to = in_response_to
+            .ok_or_else(|| refuse("no bearer confirmation for us answering a request"))?;
+        if response
+            .attribute("InResponseTo")
+            .is_some_and(|r| r != in_response_to)
+        {
+            return Err(refuse("response and assertion answer different requests"));
+        }
+
+        let name_id = subject
+            .child(ASSERTION_NS, "NameID")
+            .map(|n| n.text().trim().to_string())
+            .filter(|n| !n.is_empty());
+        let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+        for statement in assertion.children_named(ASSERTION_NS, "AttributeStatement") {
+            for attribute in statement.children_named(ASSERTION_NS, "Attribute") {
+                let Some(name) = attribute.attribute("Name") else {
+                    continue;
+                };
+                attributes.entry(name.to_string()).or_default().extend(
+                    attribute
+                        .children_named(ASSERTION_NS, "AttributeValue")
+                        .map(|v| v.text().trim().to_string()),
+                );
+            }
+        }
+        Ok(SamlAssertion {
+            in_response_to: in_response_to.to_string(),
+            name_id,
+            attributes,
+        })
+    }
+}
+
+/// Reads the IdP metadata customers download from their IdP.
+pub fn parse_idp_metadata(metadata: &str) -> Result<IdentityProvider, AuthAppError> {
+    let invalid = |reason: &str| AuthAppError::InvalidSaml(format!("IdP metadata: {reason}"));
+    let entity = xml::parse(metadata)?;
+    if !entity.is(METADATA_NS, "EntityDescriptor") {
+        return Err(invalid("expected an EntityDescriptor"));
+    }
+    let entity_id = entity
+        .attribute("entityID")
+        .ok_or_else(|| invalid("missing entityID"))?;
+    let idp = entity
+        .child(METADATA_NS, "IDPSSODescriptor")
+        .ok_or_else(|| invalid("missing IDPSSODescriptor"))?;
+    let sso_url = idp
+        .children_named(METADATA_NS, "SingleSignOnService")
+        .find(|s| s.attribute("Binding") == Some(HTTP_REDIRECT_BINDING))
+        .and_then(|s| s.attribute("Location"))
+        .ok_or_else(|| invalid("no SingleSignOnService with the HTTP-Redirect binding"))?;
+    Url::parse(sso_url).map_err(|e| invalid(&format!("SingleSignOnService location: {e}")))?;
+    let certificate = idp
+        .children_named(METADATA_NS, "KeyDescriptor")
+        .filter(|k| k.attribute("use").is_none_or(|u| u == "signing"))
+        .find_map(|k| {
+            k.child(DSIG_NS, "KeyInfo")?
+                .child(DSIG_NS, "X509Data")?
+                .child(DSIG_NS, "X509Certificate")
+        })
+        .ok_or_else(|| invalid("no signing certificate"))?;
+    let certificate = decode_base64(&certificate.text())?;
+    EndEntityCert::try_from(&CertificateDer::from(certificate.as_slice()))
+        .map_err(|e| invalid(&format!("signing certificate: {e}")))?;
+    Ok(IdentityProvider {
+        entity_id: entity_id.to_string(),
+        sso_url: sso_url.to_string(),
+        certificate,
+    })
+}
+
+/// Responses that are well formed but not to be trusted. The reason is only logged, the IdP's user can't act on it.
+fn refuse(reason: &str) -> AuthAppError {
+    info!("Refusing SAML response: {reason}");
+    AuthAppError::AccessNotAllowed
+}
+
+/// Base64 in XML and form posts may be wrapped over several lines.
+fn decode_base64(text: &str) -> Result<Vec<u8>, AuthAppError> {
+    let compact: String = text.split_whitespace().collect();
+    STANDARD
+        .decode(compact)
+        .map_err(|e| AuthAppError::InvalidSaml(e.to_string()))
+}
+
+fn parse_instant(value: &str) -> Result<DateTime<Utc>, AuthAppError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| AuthAppError::InvalidSaml(format!("{value}: {e}")))
+}
+
+fn algorithm(element: Option<&Element>) -> Option<&str> {
+    element.and_then(|e| e.attribute("Algorithm"))
+}
+
+fn inclusive_prefixes(transform: &Element) -> Vec<&str> {
+    transform
+        .child(EXC_C14N, "InclusiveNamespaces")
+        .and_then(|i| i.attribute("PrefixList"))
+        .map(|p| p.split_whitespace().collect())
+        .unwrap_or_default()
+}
+
+/// Verifies the enveloped signature of `signed` with the IdP's certificate, returning whether there is one. Only
+/// the algorithms SAML IdPs use by default are accepted, and keys sent along in the message are ignored.
+fn verify_signature(signed: &Element, idp: &IdentityProvider) -> Result<bool, AuthAppError> {
+    let Some(signature) = signed.child(DSIG_NS, "Signature") else {
+        return Ok(false);
+    };
+    let signed_info = signature
+        .child(DSIG_NS, "SignedInfo")
+        .ok_or_else(|| refuse("signature without SignedInfo"))?;
+    let canonicalization = signed_info.child(DSIG_NS, "CanonicalizationMethod");
+    if algorithm(canonicalization) != Some(EXC_C14N) {
+        return Err(refuse("unsupported canonicalization"));
+    }
+    if algorithm(signed_info.child(DSIG_NS, "SignatureMethod")) != Some(RSA_SHA256) {
+        return Err(refuse("unsupported signature method"));
+    }
+    let references: Vec<&Element> = signed_info.children_named(DSIG_NS, "Reference").collect();
+    let [reference] = references.as_slice() else {
+        return Err(refuse("signature doesn't have exactly one reference"));
+    };
+    let id = signed
+        .attribute("ID")
+        .ok_or_else(|| refuse("signed element has no ID"))?;
+    if reference.attribute("URI") != Some(format!("#{id}").as_str()) {
+        return Err(refuse("signature references another element"));
+    }
+    let mut prefixes = vec![];
+    for transform in reference
+        .child(DSIG_NS, "Transforms")
+        .into_iter()
+        .flat_map(|t| t.children_named(DSIG_NS, "Transform"))
+    {
+        match transform.attribute("Algorithm") {
+            Some(ENVELOPED_SIGNATURE) => {}
+            Some(EXC_C14N) => prefixes = inclusive_prefixes(transform),
+            _ => return Err(refuse("unsupported transform")),
+        }
+    }
+    if algorithm(reference.child(DSIG_NS, "DigestMethod")) != Some(SHA256) {
+        return Err(refuse("unsupported digest method"));
+    }
+    let expected_digest = decode_base64(
+        &reference
+            .child(DSIG_NS, "DigestValue")
+            .ok_or_else(|| refuse("reference without DigestValue"))?
+            .text(),
+    )?;
+    let digest = digest::digest(
+        &digest::SHA256,
+        signed.canonicalize(Some(signature), &prefixes).as_bytes(),
+    );
+    if !bool::from(digest.as_ref().ct_eq(&expected_digest)) {
+        return Err(refuse("digest doesn't match the signed element"));
+    }
+
+    let signature_value = decode_base64(
+        &signature
+            .child(DSIG_NS, "SignatureValue")
+            .ok_or_else(|| refuse("signature without SignatureValue"))?
+            .text(),
+    )?;
+    let signed_info_prefixes = canonicalization.map(inclusive_prefixes).unwrap_or_default();
+    let certificate = CertificateDer::from(idp.certificate.as_slice());
+    EndEntityCert::try_from(&certificate)
+        .and_then(|cert| {
+            cert.verify_signature(
+                webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+                signed_info
+                    .canonicalize(None, &signed_info_prefixes)
+                    .as_bytes(),
+                &signature_value,
+            )
+        })
+        .map_err(|e| refuse(&format!("signature doesn't verify: {e}")))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+fn test_idp() -> IdentityProvider {
+    IdentityProvider {
+        entity_id: "https://idp.example.com/metadata".to_string(),
+        sso_url: "https://idp.example.com/sso?tenant=acme".to_string(),
+        certificate: include_bytes!("../../tests/fixtures/saml_idp_cert.der").to_vec(),
+    }
+}
+
+#[cfg(test)]
+fn test_sp() -> ServiceProvider {
+    let config = AppConfig {
+        public_url: "https://auth.example.com".to_string(),
+        ..Default::default()
+    };
+    ServiceProvider::for_instance(&config, "acme").unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn idp_metadata_yields_the_redirect_endpoint_and_signing_certificate() {
+    let idp = test_idp();
+    let metadata = format!(
+        "<EntityDescriptor xmlns=\"{METADATA_NS}\" xmlns:ds=\"{DSIG_NS}\" entityID=\"{}\">
+           <IDPSSODescriptor protocolSupportEnumeration=\"{PROTOCOL_NS}\">
+             <KeyDescriptor use=\"encryption\"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>AAAA</ds:X509Certificate></ds:X509Data></ds:KeyInfo></KeyDescriptor>
+             <KeyDescriptor use=\"signing\"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>\n{}\n</ds:X509Certificate></ds:X509Data></ds:KeyInfo></KeyDescriptor>
+             <SingleSignOnService Binding=\"{HTTP_POST_BINDING}\" Location=\"https://idp.example.com/post\"/>
+             <SingleSignOnService Binding=\"{HTTP_REDIRECT_BINDING}\" Location=\"https://idp.example.com/sso?tenant=acme\"/>
+           </IDPSSODescriptor>
+         </EntityDescriptor>",
+        idp.entity_id,
+        STANDARD.encode(&idp.certificate)
+    );
+    assert_eq!(parse_idp_metadata(&metadata).unwrap(), idp);
+    let no_redirect = metadata.replace(HTTP_REDIRECT_BINDING, HTTP_POST_BINDING);
+    assert!(parse_idp_metadata(&no_redirect).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn authn_requests_are_deflated_into_the_redirect() {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    let sp = test_sp();
+    assert_eq!(
+        sp.entity_id,
+        "https://auth.example.com/api/auth/saml/acme/metadata"
+    );
+    assert_eq!(
+        sp.acs_url,
+        "https://auth.example.com/api/auth/saml/acme/acs"
+    );
+    let url = sp
+        .login_url(
+            &test_idp(),
+            "_request1",
+            DateTime::from_timestamp(0, 0).unwrap(),
+        )
+        .unwrap();
+    let query: HashMap<_, _> = url.query_pairs().collect();
+    assert_eq!(query["tenant"], "acme");
+    let mut request = String::new();
+    DeflateDecoder::new(
+        STANDARD
+            .decode(query["SAMLRequest"].as_bytes())
+            .unwrap()
+            .as_slice(),
+    )
+    .read_to_string(&mut request)
+    .unwrap();
+    let request = xml::parse(&request).unwrap();
+    assert!(request.is(PROTOCOL_NS, "AuthnRequest"));
+    assert_eq!(request.attribute("ID"), Some("_request1"));
+    assert_eq!(
+        request.attribute("IssueInstant"),
+        Some("1970-01-01T00:00:00Z")
+    );
+    assert_eq!(
+        request.attribute("AssertionConsumerServiceURL"),
+        Some(sp.acs_url.as_str())
+    );
+    assert_eq!(
+        request.child(ASSERTION_NS, "Issuer").unwrap().text(),
+        sp.entity_id
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn unsigned_responses_are_refused() {
+    let sp = test_sp();
+    let response = format!(
+        "<samlp:Response xmlns:samlp=\"{PROTOCOL_NS}\" xmlns:saml=\"{ASSERTION_NS}\" ID=\"_r\" Version=\"2.0\">
+           <samlp:Status><samlp:StatusCode Value=\"{STATUS_SUCCESS}\"/></samlp:Status>
+           <saml:Assertion ID=\"_a\" Version=\"2.0\"><saml:Issuer>https://idp.example.com/metadata</saml:Issuer></saml:Assertion>
+         </samlp:Response>"
+    );
+    let result = sp.validate_response(&test_idp(), &STANDARD.encode(response), Utc::now());
+    assert!(matches!(result, Err(AuthAppError::AccessNotAllowed)));
+    let result = sp.validate_response(&test_idp(), "not base64!", Utc::now());
+    assert!(matches!(result, Err(AuthAppError::InvalidSaml(_))));
+}
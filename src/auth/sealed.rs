@@ -0,0 +1,90 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::token::{key_id_footer, token_footer};
+use crate::errors::AuthAppError;
+use rusty_paseto::prelude::*;
+use serde_json::Value;
+
+/// Secrets we keep encrypted with the keyring, so reading the database isn't enough to use them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sealed {
+    /// A provider's access token, kept to revoke it when the session ends
+    UpstreamToken,
+    /// A user's hex encoded TOTP secret
+    TotpSecret,
+}
+
+impl Sealed {
+    /// Binds sealed values to their kind, so one can't be passed off as another or as a session token.
+    fn implicit_assertion(self) -> &'static str {
+        match self {
+            Sealed::UpstreamToken => "upstream-token",
+            Sealed::TotpSecret => "totp-secret",
+        }
+    }
+
+    /// Encrypts `value` with the current key.
+    pub fn seal(self, keyring: &Keyring, value: &str) -> Result<String, AuthAppError> {
+        let key = keyring.current();
+        let footer = key_id_footer(&key.id);
+        let nonce = Key::<32>::try_new_random().map_err(|_| AuthAppError::InvalidToken)?;
+        Paseto::<V4, Local>::builder()
+            .set_payload(Payload::from(value))
+            .set_footer(Footer::from(footer.as_str()))
+            .set_implicit_assertion(ImplicitAssertion::from(self.implicit_assertion()))
+            .try_encrypt(&key.paseto_key(), &PasetoNonce::<V4, Local>::from(&nonce))
+            .map_err(|_| AuthAppError::InvalidToken)
+    }
+
+    /// Decrypts a value from [Sealed::seal], with whichever key of the keyring it was sealed with.
+    pub fn open(self, keyring: &Keyring, sealed: &str) -> Option<String> {
+        let footer = token_footer(sealed)?;
+        let key_id = serde_json::from_str::<Value>(&footer)
+            .ok()
+            .and_then(|f| f["kid"].as_str().map(|kid| kid.to_string()))?;
+        let key = keyring.find(&key_id)?.paseto_key();
+        Paseto::<V4, Local>::try_decrypt(
+            sealed,
+            &key,
+            Footer::from(footer.as_str()),
+            ImplicitAssertion::from(self.implicit_assertion()),
+        )
+        .ok()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sealed_values_open_with_any_key_of_the_keyring_only() {
+    use crate::auth::keyring::TokenKey;
+    let old = Keyring::new(
+        TokenKey::new("old", "def456def456def456def456def45656").unwrap(),
+        vec![],
+    )
+    .unwrap();
+    let sealed = Sealed::UpstreamToken
+        .seal(&old, "ya29.access-token")
+        .unwrap();
+    assert!(!sealed.contains("ya29"));
+    assert_eq!(
+        Sealed::UpstreamToken.open(&old, &sealed).as_deref(),
+        Some("ya29.access-token")
+    );
+
+    let rotated = Keyring::new(
+        TokenKey::new("new", "abc123abc123abc123abc123abc12323").unwrap(),
+        vec![TokenKey::new("old", "def456def456def456def456def45656").unwrap()],
+    )
+    .unwrap();
+    assert_eq!(
+        Sealed::UpstreamToken.open(&rotated, &sealed).as_deref(),
+        Some("ya29.access-token")
+    );
+    let other = Keyring::new(
+        TokenKey::new("other", "abc123abc123abc123abc123abc12323").unwrap(),
+        vec![],
+    )
+    .unwrap();
+    assert_eq!(Sealed::UpstreamToken.open(&other, &sealed), None);
+    assert_eq!(Sealed::UpstreamToken.open(&old, "ya29.access-token"), None);
+    assert_eq!(Sealed::TotpSecret.open(&old, &sealed), None);
+}
@@ -0,0 +1,24 @@
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub fn random_secret(length: usize) -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Secrets are only stored as their SHA-256 hash, they are long and random enough not to need a slow hash.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub fn verify(secret: &str, secret_hash: &str) -> bool {
+    hash_secret(secret)
+        .as_bytes()
+        .ct_eq(secret_hash.as_bytes())
+        .into()
+}
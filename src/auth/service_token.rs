@@ -0,0 +1,67 @@
+use crate::auth::secret::{hash_secret, random_secret, verify};
+use crate::db;
+use crate::errors::AuthAppError;
+use sqlx::PgPool;
+
+/// Service tokens look like `svc.<name>.<secret>`, which keeps them apart from PASETO session tokens.
+const PREFIX: &str = "svc.";
+const SECRET_LENGTH: usize = 40;
+
+pub fn is_service_token(token: &str) -> bool {
+    token.starts_with(PREFIX)
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Generates a new token for `name`, returning the token to hand out and the hash to store.
+pub fn generate(name: &str) -> (String, String) {
+    let secret = random_secret(SECRET_LENGTH);
+    (format!("{PREFIX}{name}.{secret}"), hash_secret(&secret))
+}
+
+fn parse(token: &str) -> Option<(&str, &str)> {
+    token.strip_prefix(PREFIX)?.split_once('.')
+}
+
+/// Looks up the service token by name and returns its scopes if the secret matches.
+pub async fn authenticate(conn: &PgPool, token: &str) -> Result<Vec<String>, AuthAppError> {
+    let (name, secret) = parse(token).ok_or(AuthAppError::NotAuthenticated)?;
+    match db::service_token::get_by_name(conn, name).await? {
+        Some(stored) if verify(secret, &stored.token_hash) => Ok(stored.scopes),
+        _ => Err(AuthAppError::NotAuthenticated),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn generated_tokens_verify_against_their_hash_only() {
+    let (token, hash) = generate("provisioning");
+    let (name, secret) = parse(&token).unwrap();
+    assert_eq!(name, "provisioning");
+    assert_eq!(secret.len(), SECRET_LENGTH);
+    assert!(verify(secret, &hash));
+    let (_, other_hash) = generate("provisioning");
+    assert!(!verify(secret, &other_hash));
+}
+
+#[cfg(test)]
+#[test]
+fn service_tokens_are_told_apart_from_session_tokens() {
+    assert!(is_service_token("svc.provisioning.abc"));
+    assert!(!is_service_token("v4.local.abc"));
+    assert!(parse("svc.no-secret").is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn names_are_limited_to_url_safe_characters() {
+    assert!(valid_name("provisioning-pipeline_1"));
+    assert!(!valid_name(""));
+    assert!(!valid_name("has.dot"));
+    assert!(!valid_name("has space"));
+}
@@ -1,72 +1,619 @@
-use crate::errors::AuthAppError;
 use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::errors::AuthAppError;
+use crate::model::user::UserAccess;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::ValueEnum;
 use itertools::Itertools;
+use paperclip::actix::Apiv2Security;
+use rand::Rng;
+use rand::distr::Alphanumeric;
 use rusty_paseto::prelude::*;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+/// Tokens are accepted in either purpose, so switching `token_purpose` doesn't end existing sessions.
+const PUBLIC_TOKEN_HEADER: &str = "v4.public.";
+
+/// A freshly minted token, with the claims needed to track it as a session.
+pub struct IssuedToken {
+    pub token: String,
+    pub token_id: String,
+    pub expires_at: DateTime<Utc>,
+}
 
 pub fn create_token(
     config: AppConfig,
+    keyring: &Keyring,
     email: String,
-    client_ids: Vec<String>,
+    access: Vec<UserAccess>,
 ) -> Result<String, AuthAppError> {
-    let key = PasetoSymmetricKey::<V4, Local>::from(Key::from(config.secret.as_bytes()));
-    PasetoBuilder::<V4, Local>::default()
-        .set_claim(
-            CustomClaim::try_from(("client_ids", client_ids.iter().join(",")))
-                .expect("Couldn't set client_id claim"),
-        )
-        .set_claim(CustomClaim::try_from(("email", email)).expect("Couldn't set email claim"))
-        .build(&key)
-        .map_err(|_| AuthAppError::InvalidToken)
+    issue_token(config, keyring, email, access).map(|issued| issued.token)
+}
+
+pub fn issue_token(
+    config: AppConfig,
+    keyring: &Keyring,
+    email: String,
+    access: Vec<UserAccess>,
+) -> Result<IssuedToken, AuthAppError> {
+    issue_token_at(config, keyring, email, access, Utc::now())
+}
+
+#[cfg(test)]
+fn create_token_at(
+    config: AppConfig,
+    keyring: &Keyring,
+    email: String,
+    access: Vec<UserAccess>,
+    now: DateTime<Utc>,
+) -> Result<String, AuthAppError> {
+    issue_token_at(config, keyring, email, access, now).map(|issued| issued.token)
+}
+
+fn issue_token_at(
+    config: AppConfig,
+    keyring: &Keyring,
+    email: String,
+    access: Vec<UserAccess>,
+    now: DateTime<Utc>,
+) -> Result<IssuedToken, AuthAppError> {
+    if config.cookie_life_time_secs <= 0 {
+        return Err(AuthAppError::InvalidCookieDuration);
+    }
+    let expires_at = now + Duration::seconds(config.cookie_life_time_secs);
+    let claims = SessionClaims {
+        issuer: config.token_issuer,
+        audience: config.token_audience,
+        token_id: generate_token_id(),
+        issued_at: claim_time(now),
+        expires_at: claim_time(expires_at),
+        client_ids: access.iter().map(|a| a.client_id.as_str()).join(","),
+        roles: access
+            .into_iter()
+            .map(|a| (a.client_id, Value::String(a.role)))
+            .collect(),
+        email,
+    };
+    let token = match config.token_purpose {
+        TokenPurpose::Local => {
+            let key = keyring.current();
+            let footer = key_id_footer(&key.id);
+            let mut builder = PasetoBuilder::<V4, Local>::default();
+            claims.set_on(&mut builder)?;
+            builder
+                .set_footer(Footer::from(footer.as_str()))
+                .build(&key.paseto_key())
+                .map_err(|_| AuthAppError::InvalidToken)
+        }
+        TokenPurpose::Public => {
+            let key = keyring.signing_key().ok_or(AuthAppError::InvalidToken)?;
+            let footer = key_id_footer(&key.id);
+            let mut builder = PasetoBuilder::<V4, Public>::default();
+            claims.set_on(&mut builder)?;
+            builder
+                .set_footer(Footer::from(footer.as_str()))
+                .build(&key.paseto_key())
+                .map_err(|_| AuthAppError::InvalidToken)
+        }
+    }?;
+    Ok(IssuedToken {
+        token,
+        token_id: claims.token_id,
+        expires_at,
+    })
+}
+
+/// `local` tokens are encrypted with our secret, `public` tokens are signed with an Ed25519 key and can be
+/// verified by anyone holding the public key.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenPurpose {
+    #[default]
+    Local,
+    Public,
+}
+
+struct SessionClaims {
+    issuer: String,
+    audience: String,
+    token_id: String,
+    issued_at: String,
+    expires_at: String,
+    client_ids: String,
+    roles: Map<String, Value>,
+    email: String,
+}
+
+impl SessionClaims {
+    fn set_on<'a, Purpose>(
+        &'a self,
+        builder: &mut PasetoBuilder<'a, V4, Purpose>,
+    ) -> Result<(), AuthAppError> {
+        builder
+            .set_claim(IssuerClaim::from(self.issuer.as_str()))
+            .set_claim(AudienceClaim::from(self.audience.as_str()))
+            .set_claim(TokenIdentifierClaim::from(self.token_id.as_str()))
+            .set_claim(
+                IssuedAtClaim::try_from(self.issued_at.as_str())
+                    .map_err(|_| AuthAppError::InvalidToken)?,
+            )
+            .set_claim(
+                NotBeforeClaim::try_from(self.issued_at.as_str())
+                    .map_err(|_| AuthAppError::InvalidToken)?,
+            )
+            .set_claim(
+                ExpirationClaim::try_from(self.expires_at.as_str())
+                    .map_err(|_| AuthAppError::InvalidToken)?,
+            )
+            .set_claim(
+                CustomClaim::try_from(("client_ids", self.client_ids.as_str()))
+                    .expect("Couldn't set client_id claim"),
+            )
+            .set_claim(
+                CustomClaim::try_from(("access", self.roles.clone()))
+                    .expect("Couldn't set access claim"),
+            )
+            .set_claim(
+                CustomClaim::try_from(("email", self.email.as_str()))
+                    .expect("Couldn't set email claim"),
+            );
+        Ok(())
+    }
 }
 
+pub fn key_id_footer(key_id: &str) -> String {
+    json!({ "kid": key_id }).to_string()
+}
+
+#[derive(Clone, Apiv2Security)]
+#[openapi(
+    apiKey,
+    alias = "sessionToken",
+    in = "header",
+    name = "Authorization",
+    description = "Bearer token, or the session cookie set by the login callback"
+)]
 pub struct AuthenticatedTokenUser {
     pub email: String,
     pub client_ids: Vec<String>,
+    pub access: Vec<UserAccess>,
+    pub token_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl AuthenticatedTokenUser {
+    pub fn role_for(&self, client_id: &str) -> Option<&str> {
+        self.access
+            .iter()
+            .find(|a| a.client_id == client_id)
+            .map(|a| a.role.as_str())
+    }
+
+    pub fn require_access(&self, client_id: &str) -> Result<&str, AuthAppError> {
+        self.role_for(client_id)
+            .ok_or(AuthAppError::AccessNotAllowed)
+    }
 }
 
 pub fn validate_token(
     config: AppConfig,
+    keyring: &Keyring,
     token: String,
 ) -> Result<AuthenticatedTokenUser, AuthAppError> {
-    let key = PasetoSymmetricKey::<V4, Local>::from(Key::from(config.secret.as_bytes()));
-    let token_result = PasetoParser::<V4, Local>::default().parse(&token, &key);
-    match token_result {
-        Ok(token_value) => {
-            let email = token_value["email"].as_str();
-            let client_ids = token_value["client_ids"]
-                .as_str()
-                .map(|ids| Vec::from_iter(ids.split(',').into_iter().map(|c| c.to_string())));
-
-            email
-                .zip(client_ids)
-                .map(|(e, c)| {
-                    Ok(AuthenticatedTokenUser {
-                        email: e.to_string(),
-                        client_ids: c,
-                    })
+    validate_token_at(config, keyring, token, Utc::now())
+}
+
+fn validate_token_at(
+    config: AppConfig,
+    keyring: &Keyring,
+    token: String,
+    now: DateTime<Utc>,
+) -> Result<AuthenticatedTokenUser, AuthAppError> {
+    let footer = token_footer(&token).ok_or(AuthAppError::InvalidToken)?;
+    let key_id = serde_json::from_str::<Value>(&footer)
+        .ok()
+        .and_then(|f| f["kid"].as_str().map(|kid| kid.to_string()))
+        .ok_or(AuthAppError::InvalidToken)?;
+    // The default parser checks exp and nbf without any leeway, so we check the time claims ourselves
+    let token_value = if token.starts_with(PUBLIC_TOKEN_HEADER) {
+        let key = keyring
+            .find_public_key(&key_id)
+            .ok_or(AuthAppError::InvalidToken)?
+            .paseto_key();
+        PasetoParser::<V4, Public>::new()
+            .set_footer(Footer::from(footer.as_str()))
+            .parse(&token, &key)
+    } else {
+        let key = keyring
+            .find(&key_id)
+            .ok_or(AuthAppError::InvalidToken)?
+            .paseto_key();
+        PasetoParser::<V4, Local>::new()
+            .set_footer(Footer::from(footer.as_str()))
+            .parse(&token, &key)
+    }
+    .map_err(|_| AuthAppError::InvalidToken)?;
+    if token_value["iss"].as_str() != Some(config.token_issuer.as_str())
+        || token_value["aud"].as_str() != Some(config.token_audience.as_str())
+    {
+        return Err(AuthAppError::InvalidToken);
+    }
+    let leeway = Duration::seconds(config.token_leeway_secs);
+    let expires_at = parse_claim_time(&token_value["exp"])?;
+    let not_before = parse_claim_time(&token_value["nbf"])?;
+    if now - leeway >= expires_at || now + leeway < not_before {
+        return Err(AuthAppError::TokenExpired);
+    }
+    let email = token_value["email"].as_str();
+    let token_id = token_value["jti"].as_str();
+    let client_ids = token_value["client_ids"].as_str().map(|ids| {
+        ids.split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+    });
+    let access = token_value["access"].as_object().map(|roles| {
+        roles
+            .iter()
+            .filter_map(|(client_id, role)| {
+                role.as_str().map(|r| UserAccess {
+                    client_id: client_id.clone(),
+                    role: r.to_string(),
                 })
-                .unwrap_or(Err(AuthAppError::InvalidToken))
+            })
+            .collect::<Vec<UserAccess>>()
+    });
+    match (email, client_ids, access, token_id) {
+        // Every client id we hand out must come with the role the user has on that instance
+        (Some(e), Some(c), Some(a), Some(t))
+            if c.len() == a.len() && c.iter().all(|id| a.iter().any(|r| &r.client_id == id)) =>
+        {
+            Ok(AuthenticatedTokenUser {
+                email: e.to_string(),
+                client_ids: c,
+                access: a,
+                token_id: t.to_string(),
+                expires_at,
+            })
         }
-        Err(_) => Err(AuthAppError::InvalidToken),
+        _ => Err(AuthAppError::InvalidToken),
     }
 }
 
+/// The footer is not encrypted, so we can read the key id before picking the key to decrypt with.
+pub fn token_footer(token: &str) -> Option<String> {
+    let encoded = token.splitn(4, '.').nth(3)?;
+    URL_SAFE_NO_PAD
+        .decode(encoded)
+        .ok()
+        .and_then(|f| String::from_utf8(f).ok())
+}
+
+fn claim_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_claim_time(value: &Value) -> Result<DateTime<Utc>, AuthAppError> {
+    value
+        .as_str()
+        .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|v| v.with_timezone(&Utc))
+        .ok_or(AuthAppError::InvalidToken)
+}
+
+fn generate_token_id() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
 #[cfg(test)]
-#[test]
-fn should_be_able_to_create_and_parse_token() {
-    let config = AppConfig {
+fn test_keyring() -> Keyring {
+    use crate::auth::keyring::TokenKey;
+    Keyring::new(
+        TokenKey::new("current", "abc123abc123abc123abc123abc12323").unwrap(),
+        vec![],
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn test_config() -> AppConfig {
+    AppConfig {
         secret: "abc123abc123abc123abc123abc12323".to_string(),
+        token_issuer: "auth-app".to_string(),
+        token_audience: "unleash".to_string(),
+        token_leeway_secs: 30,
+        cookie_life_time_secs: 3600,
         ..Default::default()
-    };
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn should_be_able_to_create_and_parse_token() {
+    let config = test_config();
     let token = create_token(
         config.clone(),
+        &test_keyring(),
         "test@test.com".to_string(),
-        vec!["admin".to_string(), "demo".to_string(), "test".to_string()],
+        vec![
+            access("admin", "ADMIN"),
+            access("demo", "WRITER"),
+            access("test", "WRITER"),
+        ],
     );
     assert!(token.is_ok());
-    let authenticated_user = validate_token(config, token.unwrap());
+    let authenticated_user = validate_token(config, &test_keyring(), token.unwrap());
     assert!(authenticated_user.is_ok());
     let u = authenticated_user.unwrap();
     assert_eq!(u.email, "test@test.com".to_string());
     assert_eq!(u.client_ids.len(), 3);
+    assert_eq!(u.token_id.len(), 32);
+}
+
+#[cfg(test)]
+fn access(client_id: &str, role: &str) -> UserAccess {
+    UserAccess {
+        client_id: client_id.to_string(),
+        role: role.to_string(),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn token_carries_the_role_for_each_instance() {
+    let config = test_config();
+    let token = create_token(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![access("demo", "ADMIN"), access("test", "WRITER")],
+    )
+    .unwrap();
+    let u = validate_token(config, &test_keyring(), token).unwrap();
+    assert_eq!(u.client_ids, vec!["demo".to_string(), "test".to_string()]);
+    assert_eq!(u.role_for("demo"), Some("ADMIN"));
+    assert_eq!(u.require_access("test").unwrap(), "WRITER");
+    assert!(matches!(
+        u.require_access("other"),
+        Err(AuthAppError::AccessNotAllowed)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn token_without_access_has_no_client_ids() {
+    let config = test_config();
+    let token = create_token(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+    )
+    .unwrap();
+    let u = validate_token(config, &test_keyring(), token).unwrap();
+    assert!(u.client_ids.is_empty());
+    assert!(u.access.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn expiry_follows_configured_cookie_life_time() {
+    let config = test_config();
+    let now = Utc::now();
+    let token = create_token_at(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+        now,
+    )
+    .unwrap();
+    let u = validate_token_at(config, &test_keyring(), token, now).unwrap();
+    assert_eq!(u.expires_at.timestamp(), now.timestamp() + 3600);
+}
+
+#[cfg(test)]
+#[test]
+fn expired_token_is_rejected() {
+    let config = test_config();
+    let issued = Utc::now() - Duration::hours(2);
+    let token = create_token_at(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+        issued,
+    )
+    .unwrap();
+    assert!(matches!(
+        validate_token(config, &test_keyring(), token),
+        Err(AuthAppError::TokenExpired)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn recently_expired_token_is_accepted_within_leeway() {
+    let config = test_config();
+    let issued = Utc::now();
+    let token = create_token_at(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+        issued,
+    )
+    .unwrap();
+    let just_after_expiry = issued + Duration::seconds(3600 + 10);
+    assert!(
+        validate_token_at(
+            config.clone(),
+            &test_keyring(),
+            token.clone(),
+            just_after_expiry
+        )
+        .is_ok()
+    );
+    let after_leeway = issued + Duration::seconds(3600 + 30);
+    assert!(matches!(
+        validate_token_at(config, &test_keyring(), token, after_leeway),
+        Err(AuthAppError::TokenExpired)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn token_from_a_clock_slightly_ahead_is_accepted_within_leeway() {
+    let config = test_config();
+    let now = Utc::now();
+    let ahead = create_token_at(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+        now + Duration::seconds(20),
+    )
+    .unwrap();
+    assert!(validate_token_at(config.clone(), &test_keyring(), ahead, now).is_ok());
+    let too_far_ahead = create_token_at(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+        now + Duration::seconds(60),
+    )
+    .unwrap();
+    assert!(matches!(
+        validate_token_at(config, &test_keyring(), too_far_ahead, now),
+        Err(AuthAppError::TokenExpired)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn token_for_another_audience_is_rejected() {
+    let config = test_config();
+    let token = create_token(
+        config.clone(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+    )
+    .unwrap();
+    let other_audience = AppConfig {
+        token_audience: "someone-else".to_string(),
+        ..config
+    };
+    assert!(matches!(
+        validate_token(other_audience, &test_keyring(), token),
+        Err(AuthAppError::InvalidToken)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn non_positive_cookie_life_time_is_rejected() {
+    let config = AppConfig {
+        cookie_life_time_secs: 0,
+        ..test_config()
+    };
+    assert!(matches!(
+        create_token(config, &test_keyring(), "test@test.com".to_string(), vec![]),
+        Err(AuthAppError::InvalidCookieDuration)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn tokens_signed_with_a_retired_key_still_validate_after_rotation() {
+    use crate::auth::keyring::TokenKey;
+    let config = test_config();
+    let old_keyring = test_keyring();
+    let token = create_token(
+        config.clone(),
+        &old_keyring,
+        "test@test.com".to_string(),
+        vec![],
+    )
+    .unwrap();
+    let rotated = Keyring::new(
+        TokenKey::new("next", "def456def456def456def456def45656").unwrap(),
+        vec![TokenKey::new("current", "abc123abc123abc123abc123abc12323").unwrap()],
+    )
+    .unwrap();
+    assert!(validate_token(config.clone(), &rotated, token).is_ok());
+    let new_token = create_token(
+        config.clone(),
+        &rotated,
+        "test@test.com".to_string(),
+        vec![],
+    )
+    .unwrap();
+    assert!(matches!(
+        validate_token(config, &old_keyring, new_token),
+        Err(AuthAppError::InvalidToken)
+    ));
+}
+
+#[cfg(test)]
+fn test_keyring_with_signing_key() -> Keyring {
+    use crate::auth::keyring::SigningKey;
+    test_keyring()
+        .with_signing_keys(
+            SigningKey::new(
+                "signing",
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            )
+            .unwrap(),
+            vec![],
+        )
+        .unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn public_tokens_are_signed_and_verified_with_the_signing_key() {
+    let config = AppConfig {
+        token_purpose: TokenPurpose::Public,
+        ..test_config()
+    };
+    let keyring = test_keyring_with_signing_key();
+    let token = create_token(
+        config.clone(),
+        &keyring,
+        "test@test.com".to_string(),
+        vec![access("demo", "ADMIN")],
+    )
+    .unwrap();
+    assert!(token.starts_with(PUBLIC_TOKEN_HEADER));
+    let u = validate_token(config.clone(), &keyring, token.clone()).unwrap();
+    assert_eq!(u.role_for("demo"), Some("ADMIN"));
+    assert!(matches!(
+        validate_token(config, &test_keyring(), token),
+        Err(AuthAppError::InvalidToken)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn local_tokens_still_validate_after_switching_to_public_tokens() {
+    let local = create_token(
+        test_config(),
+        &test_keyring(),
+        "test@test.com".to_string(),
+        vec![],
+    )
+    .unwrap();
+    let config = AppConfig {
+        token_purpose: TokenPurpose::Public,
+        ..test_config()
+    };
+    assert!(validate_token(config, &test_keyring_with_signing_key(), local).is_ok());
 }
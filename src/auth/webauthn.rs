@@ -0,0 +1,359 @@
+use crate::AppConfig;
+use crate::errors::AuthAppError;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ciborium::Value;
+use ring::signature;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// COSE algorithm identifiers of the keys we accept, in order of preference.
+pub const ES256: i64 = -7;
+pub const RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Who passkeys are registered with: `id` is the domain they are bound to, `origin` where the browser must have used
+/// them.
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn from_config(config: &AppConfig) -> Result<RelyingParty, AuthAppError> {
+        let public_url = oauth2::url::Url::parse(&config.public_url)
+            .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+        let id = match (&config.webauthn_rp_id, public_url.host_str()) {
+            (Some(id), _) => id.clone(),
+            (None, Some(host)) => host.to_string(),
+            (None, None) => {
+                return Err(AuthAppError::InvalidConfiguration(
+                    "public_url needs a host for passkeys".into(),
+                ));
+            }
+        };
+        Ok(RelyingParty {
+            id,
+            origin: public_url.origin().ascii_serialization(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+/// A credential's public key, parsed from the COSE key the authenticator gave us.
+enum PublicKey {
+    Es256(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+/// What a registration tells us about the new passkey.
+pub struct NewCredential {
+    pub challenge: String,
+    pub credential_id: String,
+    pub cose_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// What a verified login tells us, beyond that the signature was right.
+pub struct Assertion {
+    pub challenge: String,
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode(text: &str) -> Result<Vec<u8>, AuthAppError> {
+    URL_SAFE_NO_PAD
+        .decode(text.trim_end_matches('='))
+        .map_err(|_| AuthAppError::InvalidPasskey)
+}
+
+/// Checks a `navigator.credentials.create()` response, returning the challenge it answers for the caller to check.
+/// Attestation isn't verified, we ask for none and don't restrict which authenticators can be used.
+pub fn verify_registration(
+    rp: &RelyingParty,
+    client_data_json: &str,
+    attestation_object: &str,
+) -> Result<NewCredential, AuthAppError> {
+    let challenge = check_client_data(rp, &decode(client_data_json)?, "webauthn.create")?;
+    let attestation = cbor_map(&decode(attestation_object)?)?;
+    let auth_data = match field(&attestation, Value::Text("authData".into())) {
+        Some(Value::Bytes(bytes)) => bytes.clone(),
+        _ => return Err(AuthAppError::InvalidPasskey),
+    };
+    let (flags, sign_count) = check_authenticator_data(rp, &auth_data)?;
+    if flags & FLAG_ATTESTED_CREDENTIAL == 0 || auth_data.len() < 55 {
+        return Err(AuthAppError::InvalidPasskey);
+    }
+    // Attested credential data: a 16 byte AAGUID, the id length and id, then the COSE key
+    let id_length = u16::from_be_bytes([auth_data[53], auth_data[54]]) as usize;
+    let key_start = 55 + id_length;
+    if auth_data.len() <= key_start {
+        return Err(AuthAppError::InvalidPasskey);
+    }
+    let mut rest = &auth_data[key_start..];
+    let key: Value =
+        ciborium::de::from_reader(&mut rest).map_err(|_| AuthAppError::InvalidPasskey)?;
+    let cose_key = auth_data[key_start..auth_data.len() - rest.len()].to_vec();
+    parse_public_key(&key)?;
+    Ok(NewCredential {
+        challenge,
+        credential_id: encode(&auth_data[55..key_start]),
+        cose_key,
+        sign_count,
+    })
+}
+
+/// Checks a `navigator.credentials.get()` response against the stored key of the credential it names.
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    cose_key: &[u8],
+    client_data_json: &str,
+    authenticator_data: &str,
+    signature: &str,
+) -> Result<Assertion, AuthAppError> {
+    let client_data_json = decode(client_data_json)?;
+    let challenge = check_client_data(rp, &client_data_json, "webauthn.get")?;
+    let auth_data = decode(authenticator_data)?;
+    let (flags, sign_count) = check_authenticator_data(rp, &auth_data)?;
+    let key: Value =
+        ciborium::de::from_reader(cose_key).map_err(|_| AuthAppError::InvalidPasskey)?;
+    let signed = [auth_data.as_slice(), &Sha256::digest(&client_data_json)].concat();
+    let signature = decode(signature)?;
+    let verified = match parse_public_key(&key)? {
+        PublicKey::Es256(point) => {
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                .verify(&signed, &signature)
+        }
+        PublicKey::Rs256 { n, e } => signature::RsaPublicKeyComponents { n, e }.verify(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            &signed,
+            &signature,
+        ),
+    };
+    verified.map_err(|_| AuthAppError::AccessNotAllowed)?;
+    Ok(Assertion {
+        challenge,
+        sign_count,
+        user_verified: flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+fn check_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    ceremony: &str,
+) -> Result<String, AuthAppError> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| AuthAppError::InvalidPasskey)?;
+    if client_data.ceremony != ceremony || client_data.origin != rp.origin {
+        return Err(AuthAppError::InvalidPasskey);
+    }
+    Ok(client_data.challenge)
+}
+
+/// Returns the flags and signature counter once the data is known to be for us, with the user present.
+fn check_authenticator_data(
+    rp: &RelyingParty,
+    auth_data: &[u8],
+) -> Result<(u8, u32), AuthAppError> {
+    if auth_data.len() < 37 || auth_data[..32] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(AuthAppError::InvalidPasskey);
+    }
+    let flags = auth_data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(AuthAppError::InvalidPasskey);
+    }
+    let sign_count =
+        u32::from_be_bytes([auth_data[33], auth_data[34], auth_data[35], auth_data[36]]);
+    Ok((flags, sign_count))
+}
+
+fn cbor_map(bytes: &[u8]) -> Result<Vec<(Value, Value)>, AuthAppError> {
+    match ciborium::de::from_reader(bytes) {
+        Ok(Value::Map(entries)) => Ok(entries),
+        _ => Err(AuthAppError::InvalidPasskey),
+    }
+}
+
+fn field(map: &[(Value, Value)], key: Value) -> Option<&Value> {
+    map.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+fn int_field(map: &[(Value, Value)], key: i64) -> Option<&Value> {
+    field(map, Value::Integer(key.into()))
+}
+
+fn bytes_field(map: &[(Value, Value)], key: i64) -> Result<Vec<u8>, AuthAppError> {
+    match int_field(map, key) {
+        Some(Value::Bytes(bytes)) => Ok(bytes.clone()),
+        _ => Err(AuthAppError::InvalidPasskey),
+    }
+}
+
+fn parse_public_key(key: &Value) -> Result<PublicKey, AuthAppError> {
+    let Value::Map(key) = key else {
+        return Err(AuthAppError::InvalidPasskey);
+    };
+    let alg = int_field(key, 3)
+        .and_then(Value::as_integer)
+        .and_then(|a| i64::try_from(a).ok());
+    match alg {
+        // EC2 key on P-256, as an uncompressed point
+        Some(ES256) if int_field(key, -1) == Some(&Value::Integer(1.into())) => {
+            let (x, y) = (bytes_field(key, -2)?, bytes_field(key, -3)?);
+            if x.len() != 32 || y.len() != 32 {
+                return Err(AuthAppError::InvalidPasskey);
+            }
+            Ok(PublicKey::Es256([&[4], x.as_slice(), &y].concat()))
+        }
+        Some(RS256) => Ok(PublicKey::Rs256 {
+            n: bytes_field(key, -1)?,
+            e: bytes_field(key, -2)?,
+        }),
+        _ => Err(AuthAppError::InvalidPasskey),
+    }
+}
+
+#[cfg(test)]
+fn test_rp() -> RelyingParty {
+    RelyingParty {
+        id: "auth.example.com".to_string(),
+        origin: "https://auth.example.com".to_string(),
+    }
+}
+
+#[cfg(test)]
+fn cbor_bytes(value: &Value) -> Vec<u8> {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(value, &mut bytes).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+fn test_key() -> signature::EcdsaKeyPair {
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+            .unwrap();
+    signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+        pkcs8.as_ref(),
+        &rng,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn client_data(ceremony: &str, origin: &str) -> String {
+    encode(
+        serde_json::json!({ "type": ceremony, "challenge": "abc", "origin": origin })
+            .to_string()
+            .as_bytes(),
+    )
+}
+
+#[cfg(test)]
+fn registration(key: &signature::EcdsaKeyPair, rp_id: &str) -> String {
+    use ring::signature::KeyPair;
+    let point = key.public_key().as_ref();
+    let cose_key = Value::Map(vec![
+        (Value::Integer(1.into()), Value::Integer(2.into())),
+        (Value::Integer(3.into()), Value::Integer(ES256.into())),
+        (Value::Integer((-1).into()), Value::Integer(1.into())),
+        (
+            Value::Integer((-2).into()),
+            Value::Bytes(point[1..33].to_vec()),
+        ),
+        (
+            Value::Integer((-3).into()),
+            Value::Bytes(point[33..].to_vec()),
+        ),
+    ]);
+    let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+    auth_data.push(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL);
+    auth_data.extend(0_u32.to_be_bytes());
+    auth_data.extend([0; 16]);
+    auth_data.extend(3_u16.to_be_bytes());
+    auth_data.extend(b"key");
+    auth_data.extend(cbor_bytes(&cose_key));
+    encode(&cbor_bytes(&Value::Map(vec![
+        (Value::Text("fmt".into()), Value::Text("none".into())),
+        (Value::Text("attStmt".into()), Value::Map(vec![])),
+        (Value::Text("authData".into()), Value::Bytes(auth_data)),
+    ])))
+}
+
+#[cfg(test)]
+#[test]
+fn registrations_yield_the_credential_and_its_key() {
+    let key = test_key();
+    let rp = test_rp();
+    let created = client_data("webauthn.create", &rp.origin);
+    let credential = verify_registration(&rp, &created, &registration(&key, &rp.id)).unwrap();
+    assert_eq!(credential.challenge, "abc");
+    assert_eq!(credential.credential_id, encode(b"key"));
+    assert_eq!(credential.sign_count, 0);
+
+    let elsewhere = client_data("webauthn.create", "https://evil.example.com");
+    assert!(verify_registration(&rp, &elsewhere, &registration(&key, &rp.id)).is_err());
+    assert!(verify_registration(&rp, &created, &registration(&key, "evil.example.com")).is_err());
+    let login = client_data("webauthn.get", &rp.origin);
+    assert!(verify_registration(&rp, &login, &registration(&key, &rp.id)).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn assertions_need_a_signature_from_the_registered_key() {
+    let key = test_key();
+    let rp = test_rp();
+    let created = client_data("webauthn.create", &rp.origin);
+    let credential = verify_registration(&rp, &created, &registration(&key, &rp.id)).unwrap();
+
+    let client_data_json = client_data("webauthn.get", &rp.origin);
+    let mut auth_data = Sha256::digest(rp.id.as_bytes()).to_vec();
+    auth_data.push(FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+    auth_data.extend(7_u32.to_be_bytes());
+    let signed = [
+        auth_data.as_slice(),
+        &Sha256::digest(decode(&client_data_json).unwrap()),
+    ]
+    .concat();
+    let rng = ring::rand::SystemRandom::new();
+    let signature = encode(key.sign(&rng, &signed).unwrap().as_ref());
+    let assertion = verify_assertion(
+        &rp,
+        &credential.cose_key,
+        &client_data_json,
+        &encode(&auth_data),
+        &signature,
+    )
+    .unwrap();
+    assert_eq!(assertion.sign_count, 7);
+    assert!(assertion.user_verified);
+
+    let other_key = encode(test_key().sign(&rng, &signed).unwrap().as_ref());
+    assert!(matches!(
+        verify_assertion(
+            &rp,
+            &credential.cose_key,
+            &client_data_json,
+            &encode(&auth_data),
+            &other_key,
+        ),
+        Err(AuthAppError::AccessNotAllowed)
+    ));
+}
@@ -0,0 +1,361 @@
+use crate::errors::AuthAppError;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::BTreeMap;
+
+/// An element of a parsed document. Just enough of the XML data model to read SAML messages and canonicalize the
+/// parts that are signed: comments and processing instructions are dropped, DTDs refused.
+#[derive(Debug)]
+pub struct Element {
+    /// Qualified name as written, e.g. `saml:Assertion`
+    pub name: String,
+    /// Namespace URI of the element, if it has one
+    pub namespace: Option<String>,
+    /// Attributes other than namespace declarations, by qualified name
+    pub attributes: Vec<(String, String)>,
+    /// Namespaces in scope here by prefix, the default namespace under ""
+    in_scope: BTreeMap<String, String>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn local_name(&self) -> &str {
+        split_name(&self.name).1
+    }
+
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.namespace.as_deref() == Some(namespace) && self.local_name() == local_name
+    }
+
+    /// Value of an attribute without a prefix, which is how SAML and XML Signature attributes are written.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|c| match c {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn children_named<'a>(
+        &'a self,
+        namespace: &'a str,
+        local_name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |e| e.is(namespace, local_name))
+    }
+
+    pub fn child(&self, namespace: &str, local_name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.is(namespace, local_name))
+    }
+
+    /// The element's text content, without that of child elements.
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|c| match c {
+                Node::Text(t) => Some(t.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    /// Exclusive XML Canonicalization 1.0 without comments of this element, leaving out `exclude` (e.g. an enveloped
+    /// signature). `inclusive_prefixes` is the InclusiveNamespaces PrefixList, with `#default` for the default
+    /// namespace.
+    pub fn canonicalize(&self, exclude: Option<&Element>, inclusive_prefixes: &[&str]) -> String {
+        let mut out = String::new();
+        self.write_canonical(&BTreeMap::new(), exclude, inclusive_prefixes, &mut out);
+        out
+    }
+
+    fn write_canonical(
+        &self,
+        rendered: &BTreeMap<String, String>,
+        exclude: Option<&Element>,
+        inclusive_prefixes: &[&str],
+        out: &mut String,
+    ) {
+        // Exclusive canonicalization only declares the namespaces this element and its attributes use
+        let mut utilized: Vec<&str> = vec![split_name(&self.name).0];
+        utilized.extend(
+            self.attributes
+                .iter()
+                .map(|(name, _)| split_name(name).0)
+                .filter(|prefix| !prefix.is_empty()),
+        );
+        utilized.extend(
+            inclusive_prefixes
+                .iter()
+                .map(|p| if *p == "#default" { "" } else { p })
+                .filter(|p| self.in_scope.contains_key(*p)),
+        );
+        utilized.sort_unstable();
+        utilized.dedup();
+        let mut rendered_here = rendered.clone();
+        out.push('<');
+        out.push_str(&self.name);
+        for prefix in utilized {
+            let uri = self.in_scope.get(prefix).map(String::as_str).unwrap_or("");
+            // Skip what an output ancestor already declared. Having no default namespace needs no xmlns="" either,
+            // unless an ancestor declared one
+            let declared = match rendered.get(prefix) {
+                Some(rendered_uri) => rendered_uri == uri,
+                None => uri.is_empty(),
+            };
+            if declared {
+                continue;
+            }
+            if prefix.is_empty() {
+                out.push_str(" xmlns=\"");
+            } else {
+                out.push_str(" xmlns:");
+                out.push_str(prefix);
+                out.push_str("=\"");
+            }
+            out.push_str(&escape_attribute(uri));
+            out.push('"');
+            rendered_here.insert(prefix.to_string(), uri.to_string());
+        }
+        let mut attributes: Vec<(&str, &str, &str, &str)> = self
+            .attributes
+            .iter()
+            .map(|(name, value)| {
+                let (prefix, local) = split_name(name);
+                let namespace = if prefix.is_empty() {
+                    ""
+                } else {
+                    self.in_scope.get(prefix).map(String::as_str).unwrap_or("")
+                };
+                (namespace, local, name.as_str(), value.as_str())
+            })
+            .collect();
+        attributes.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        for (_, _, name, value) in attributes {
+            out.push(' ');
+            out.push_str(name);
+            out.push_str("=\"");
+            out.push_str(&escape_attribute(value));
+            out.push('"');
+        }
+        out.push('>');
+        for child in &self.children {
+            match child {
+                Node::Text(text) => out.push_str(&escape_text(text)),
+                Node::Element(e) if exclude.is_some_and(|x| std::ptr::eq(x, e)) => {}
+                Node::Element(e) => {
+                    e.write_canonical(&rendered_here, exclude, inclusive_prefixes, out)
+                }
+            }
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push('>');
+    }
+}
+
+/// Splits a qualified name into prefix, "" when there is none, and local name.
+fn split_name(name: &str) -> (&str, &str) {
+    name.split_once(':').unwrap_or(("", name))
+}
+
+pub fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+pub fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+fn invalid(e: impl std::fmt::Display) -> AuthAppError {
+    AuthAppError::InvalidSaml(e.to_string())
+}
+
+/// Line ends are normalized before entities are expanded, so `&#xD;` survives as a carriage return.
+fn normalize_line_ends(raw: &str) -> String {
+    raw.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn unescape(raw: &str) -> Result<String, AuthAppError> {
+    quick_xml::escape::unescape(raw)
+        .map(|s| s.into_owned())
+        .map_err(invalid)
+}
+
+fn start_element(
+    start: &BytesStart,
+    parent_scope: &BTreeMap<String, String>,
+) -> Result<Element, AuthAppError> {
+    let name = String::from_utf8(start.name().as_ref().to_vec()).map_err(invalid)?;
+    let mut in_scope = parent_scope.clone();
+    let mut attributes = vec![];
+    for attribute in start.attributes().with_checks(true) {
+        let attribute = attribute.map_err(invalid)?;
+        let key = String::from_utf8(attribute.key.as_ref().to_vec()).map_err(invalid)?;
+        let raw = std::str::from_utf8(&attribute.value).map_err(invalid)?;
+        // Attribute value normalization: literal whitespace becomes spaces, references keep what they stand for
+        let value = unescape(&normalize_line_ends(raw).replace(['\n', '\t'], " "))?;
+        if key == "xmlns" {
+            in_scope.insert(String::new(), value);
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            in_scope.insert(prefix.to_string(), value);
+        } else {
+            attributes.push((key, value));
+        }
+    }
+    if in_scope.get("").is_some_and(String::is_empty) {
+        in_scope.remove("");
+    }
+    let prefix = split_name(&name).0;
+    let namespace = match in_scope.get(prefix) {
+        Some(uri) => Some(uri.clone()),
+        None if prefix.is_empty() => None,
+        None => return Err(invalid(format!("Undeclared prefix {prefix}"))),
+    };
+    Ok(Element {
+        name,
+        namespace,
+        attributes,
+        in_scope,
+        children: vec![],
+    })
+}
+
+/// Parses a document into its root element.
+pub fn parse(xml: &str) -> Result<Element, AuthAppError> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Element> = vec![];
+    let mut root = None;
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(start) => {
+                if root.is_some() {
+                    return Err(invalid("Content after the root element"));
+                }
+                let scope = stack.last().map(|e| e.in_scope.clone()).unwrap_or_default();
+                stack.push(start_element(&start, &scope)?);
+            }
+            Event::Empty(start) => {
+                if root.is_some() {
+                    return Err(invalid("Content after the root element"));
+                }
+                let scope = stack.last().map(|e| e.in_scope.clone()).unwrap_or_default();
+                let element = start_element(&start, &scope)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Node::Element(element)),
+                    None => root = Some(element),
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().ok_or_else(|| invalid("Unbalanced end tag"))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Node::Element(element)),
+                    None => root = Some(element),
+                }
+            }
+            Event::Text(text) => {
+                let raw = std::str::from_utf8(&text).map_err(invalid)?;
+                let text = unescape(&normalize_line_ends(raw))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Node::Text(text)),
+                    None if text.trim().is_empty() => {}
+                    None => return Err(invalid("Text outside the root element")),
+                }
+            }
+            Event::CData(cdata) => {
+                let text = std::str::from_utf8(&cdata).map_err(invalid)?;
+                let parent = stack
+                    .last_mut()
+                    .ok_or_else(|| invalid("CDATA outside the root element"))?;
+                parent.children.push(Node::Text(normalize_line_ends(text)));
+            }
+            Event::DocType(_) => return Err(invalid("DTDs are not allowed")),
+            Event::Eof => break,
+            Event::Decl(_) | Event::Comment(_) | Event::PI(_) => {}
+        }
+    }
+    match (root, stack.is_empty()) {
+        (Some(root), true) => Ok(root),
+        _ => Err(invalid("Incomplete document")),
+    }
+}
+
+#[cfg(test)]
+const SIGNED_DOCUMENT: &str = "<?xml version=\"1.0\"?>
+<samlp:Response xmlns:samlp=\"urn:oasis:names:tc:SAML:2.0:protocol\" xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" xmlns:unused=\"urn:unused\" ID=\"r1\" Version=\"2.0\">
+  <!-- a comment -->
+  <saml:Assertion xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" Version=\"2.0\" ID=\"a1\" IssueInstant=\"2026-10-18T12:00:00Z\">
+    <saml:Issuer>https://idp.example.com/</saml:Issuer>
+    <saml:Attribute Name=\"groups\" xsi:type=\"xs:string\" FriendlyName='a&amp;b \"c\"\ttab'>Tom &amp; Jerry &lt;3 &gt; &#xD; <![CDATA[<raw & stuff>]]></saml:Attribute>
+    <Plain xmlns=\"urn:default\"><Inner xmlns=\"\"/><Empty attr=\"1\"\r\n      b=\"2\"/></Plain>
+  </saml:Assertion>
+</samlp:Response>
+";
+
+#[cfg(test)]
+#[test]
+fn canonicalization_matches_xmllint() {
+    // What `xmllint --exc-c14n` prints, less the comment
+    let expected = "<samlp:Response xmlns:samlp=\"urn:oasis:names:tc:SAML:2.0:protocol\" ID=\"r1\" Version=\"2.0\">
+  \n  <saml:Assertion xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" ID=\"a1\" IssueInstant=\"2026-10-18T12:00:00Z\" Version=\"2.0\">
+    <saml:Issuer>https://idp.example.com/</saml:Issuer>
+    <saml:Attribute xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" FriendlyName=\"a&amp;b &quot;c&quot; tab\" Name=\"groups\" xsi:type=\"xs:string\">Tom &amp; Jerry &lt;3 &gt; &#xD; &lt;raw &amp; stuff&gt;</saml:Attribute>
+    <Plain xmlns=\"urn:default\"><Inner xmlns=\"\"></Inner><Empty attr=\"1\" b=\"2\"></Empty></Plain>
+  </saml:Assertion>
+</samlp:Response>";
+    let response = parse(SIGNED_DOCUMENT).unwrap();
+    assert_eq!(response.canonicalize(None, &[]), expected);
+    let assertion = response
+        .child("urn:oasis:names:tc:SAML:2.0:assertion", "Assertion")
+        .unwrap();
+    let canonical_assertion = assertion.canonicalize(None, &[]);
+    assert!(canonical_assertion.starts_with("<saml:Assertion xmlns:saml="));
+    assert!(expected.contains(&canonical_assertion));
+}
+
+#[cfg(test)]
+#[test]
+fn canonicalization_can_leave_out_an_element_and_include_prefixes() {
+    let response = parse(SIGNED_DOCUMENT).unwrap();
+    let assertion = response
+        .child("urn:oasis:names:tc:SAML:2.0:assertion", "Assertion")
+        .unwrap();
+    let issuer = assertion
+        .child("urn:oasis:names:tc:SAML:2.0:assertion", "Issuer")
+        .unwrap();
+    assert_eq!(issuer.text(), "https://idp.example.com/");
+    let canonical = assertion.canonicalize(Some(issuer), &["xs", "unknown"]);
+    assert!(canonical.starts_with(
+        "<saml:Assertion xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" xmlns:xs=\"http://www.w3.org/2001/XMLSchema\" ID="
+    ));
+    assert!(!canonical.contains("Issuer"));
+}
+
+#[cfg(test)]
+#[test]
+fn documents_with_dtds_or_undeclared_prefixes_are_refused() {
+    assert!(parse("<!DOCTYPE x [<!ENTITY e \"boom\">]><x>&e;</x>").is_err());
+    assert!(parse("<a:x/>").is_err());
+    assert!(parse("<x><y></x>").is_err());
+    assert!(parse("<x/><y/>").is_err());
+}
@@ -0,0 +1,155 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::login_state::{LoginState, LoginStateStore};
+use crate::controllers::api::sign_in::{sign_in, LoginParams};
+use crate::errors::AuthAppError;
+use crate::{AppConfig, AppState, GithubOAuth};
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use log::info;
+use oauth2::reqwest::redirect::Policy;
+use oauth2::url::Url;
+use oauth2::{
+    AccessToken, AuthorizationCode, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, Scope,
+    TokenResponse, reqwest,
+};
+use paperclip::actix::web::ServiceConfig;
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct GithubCallback {
+    code: String,
+    state: String,
+}
+
+/// One entry from GitHub's `GET /user/emails`.
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+fn github(app_state: &AppState) -> Result<&GithubOAuth, AuthAppError> {
+    app_state
+        .github
+        .as_ref()
+        .ok_or(AuthAppError::UnknownProvider)
+}
+
+#[api_v2_operation]
+async fn login(
+    app_state: web::Data<AppState>,
+    config: web::Data<AppConfig>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    params: web::Query<LoginParams>,
+) -> Result<HttpResponse, AuthAppError> {
+    let github = github(&app_state)?;
+    let return_to = params.return_to(&config)?;
+    let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (authorize_url, csrf_state) = github
+        .oauth
+        .authorize_url(CsrfToken::new_random)
+        .add_scope(Scope::new("user:email".to_string()))
+        .set_pkce_challenge(pkce_code_challenge)
+        .url();
+    login_states
+        .save(
+            csrf_state.secret(),
+            LoginState {
+                pkce_verifier: pkce_code_verifier.into_secret(),
+                nonce: None,
+                provider: Some("github".to_string()),
+                return_to,
+            },
+        )
+        .await?;
+    Ok(HttpResponse::Found()
+        .append_header((header::LOCATION, authorize_url.to_string()))
+        .finish())
+}
+
+/// Only the primary address, and only once the user has verified it with GitHub, identifies them to us.
+async fn get_primary_email(
+    http_client: &reqwest::Client,
+    url: Url,
+    access_token: &AccessToken,
+) -> Result<String, AuthAppError> {
+    let response = http_client
+        .get(url)
+        .bearer_auth(access_token.secret())
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "auth-app-rs")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| {
+            info!("Failed to get emails from GitHub: {e}");
+            AuthAppError::AccessNotAllowed
+        })?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|_| AuthAppError::AccessNotAllowed)?;
+    let emails: Vec<GithubEmail> =
+        serde_json::from_slice(&bytes).map_err(|_| AuthAppError::AccessNotAllowed)?;
+    emails
+        .into_iter()
+        .find(|e| e.primary && e.verified)
+        .map(|e| e.email)
+        .ok_or(AuthAppError::AccessNotAllowed)
+}
+
+#[api_v2_operation]
+async fn callback(
+    conn: web::Data<Pool<Postgres>>,
+    app_state: web::Data<AppState>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    params: web::Query<GithubCallback>,
+) -> Result<HttpResponse, AuthAppError> {
+    let github = github(&app_state)?;
+    let login_state = login_states
+        .take(&params.state)
+        .await?
+        .filter(|s| s.provider.as_deref() == Some("github"))
+        .ok_or(AuthAppError::AccessNotAllowed)?;
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to build httpclient");
+    let token = github
+        .oauth
+        .exchange_code(AuthorizationCode::new(params.code.clone()))
+        .set_pkce_verifier(PkceCodeVerifier::new(login_state.pkce_verifier))
+        .request_async(&http_client)
+        .await
+        .map_err(|e| {
+            info!("GitHub token request failed: {e}");
+            AuthAppError::AccessNotAllowed
+        })?;
+    let email = get_primary_email(
+        &http_client,
+        github.emails_url.clone(),
+        token.access_token(),
+    )
+    .await?;
+    // GitHub tokens can't be revoked through the standard revocation endpoint, so they aren't kept with the session
+    sign_in(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        email,
+        None,
+        None,
+        login_state.return_to,
+    )
+    .await
+}
+
+pub fn configure_github_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/login").route(web::get().to(login)));
+    cfg.service(web::resource("/callback").route(web::get().to(callback)));
+}
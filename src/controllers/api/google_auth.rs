@@ -1,12 +1,13 @@
-use crate::auth::token;
+use crate::auth::keyring::Keyring;
+use crate::auth::login_state::{LoginState, LoginStateStore};
+use crate::auth::token::AuthenticatedTokenUser;
+use crate::controllers::api::session_cookies;
+use crate::controllers::api::sign_in::{sign_in, LoginParams};
 use crate::errors::AuthAppError;
 use crate::{service, AppConfig, AppState};
-use actix_web::cookie::time::Duration;
 use actix_web::http::header;
 use actix_web::HttpResponse;
-use awc::cookie::{Cookie, SameSite};
-use dashmap::DashMap;
-use log::{info, warn};
+use log::info;
 use oauth2::reqwest::redirect::Policy;
 use oauth2::url::Url;
 use oauth2::{
@@ -20,8 +21,11 @@ use sqlx::{Pool, Postgres};
 #[api_v2_operation]
 async fn login(
     app_state: web::Data<AppState>,
-    code_verifiers: web::Data<DashMap<String, String>>,
-) -> HttpResponse {
+    config: web::Data<AppConfig>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    params: web::Query<LoginParams>,
+) -> Result<HttpResponse, AuthAppError> {
+    let return_to = params.return_to(&config)?;
     let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
 
     // Generate the authorization URL to which we'll redirect the user.
@@ -34,20 +38,42 @@ async fn login(
 
     let csrf = csrf_state.secret().to_string();
     let verifier = pkce_code_verifier.secret().to_string();
-    warn!("csrftoken: {:#?}", csrf);
-    warn!("Verifier: {:#?}", verifier);
 
-    code_verifiers.insert(csrf, verifier);
-    HttpResponse::Found()
+    login_states
+        .save(
+            &csrf,
+            LoginState {
+                pkce_verifier: verifier,
+                nonce: None,
+                provider: None,
+                return_to,
+            },
+        )
+        .await?;
+    Ok(HttpResponse::Found()
         .append_header((header::LOCATION, authorize_url.to_string()))
-        .finish()
+        .finish())
 }
 
+/// Revokes the current session and its Google token, and expires the session cookies.
 #[api_v2_operation]
-async fn logout() -> HttpResponse {
-    HttpResponse::Found()
+async fn logout(
+    conn: web::Data<Pool<Postgres>>,
+    app_state: web::Data<AppState>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    user: Option<AuthenticatedTokenUser>,
+) -> Result<HttpResponse, AuthAppError> {
+    if let Some(u) = user {
+        service::session::end(conn.as_ref(), app_state.as_ref(), keyring.as_ref(), &u).await?;
+    }
+    let mut response = HttpResponse::Found();
+    for cookie in session_cookies::removal_cookies(&config) {
+        response.cookie(cookie);
+    }
+    Ok(response
         .append_header((header::LOCATION, "/".to_string()))
-        .finish()
+        .finish())
 }
 
 #[derive(Serialize, Deserialize, Apiv2Schema)]
@@ -61,6 +87,8 @@ pub struct AuthRequest {
 pub struct AuthInfo {
     email: String,
     verified_email: bool,
+    /// Only present for Google Workspace accounts
+    hd: Option<String>,
 }
 
 async fn get_auth_info(http_client: &oauth2::reqwest::Client, url: Url, access_token: &AccessToken) -> Result<AuthInfo, AuthAppError> {
@@ -72,7 +100,7 @@ async fn get_auth_info(http_client: &oauth2::reqwest::Client, url: Url, access_t
         Ok(response) => {
             let bytes = response.bytes().await.expect("Failed to get string");
             let slice = bytes.iter().as_slice();
-            serde_json::from_slice(&slice).map_err(|_| AuthAppError::AccessNotAllowed)
+            serde_json::from_slice(slice).map_err(|_| AuthAppError::AccessNotAllowed)
         }
         Err(_) => {
             info!("Failed to make scope request");
@@ -87,46 +115,41 @@ async fn callback(
     data: web::Data<AppState>,
     config: web::Data<AppConfig>,
     params: web::Query<AuthRequest>,
-    code_verifiers: web::Data<DashMap<String, String>>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    keyring: web::Data<Keyring>,
 ) -> Result<HttpResponse, AuthAppError> {
     let code = AuthorizationCode::new(params.code.clone());
     let state = CsrfToken::new(params.state.clone());
     let _scope = params.scope.clone();
     let http_client: oauth2::reqwest::Client = reqwest::ClientBuilder::new().redirect(Policy::none()).build().expect("Failed to build httpclient");
-    let verifier = code_verifiers.get(state.secret());
-    match verifier {
-        Some(v) => {
-            let verifier = PkceCodeVerifier::new(v.to_string());
+    let login_state = login_states.take(state.secret()).await?;
+    match login_state {
+        Some(s) => {
+            let verifier = PkceCodeVerifier::new(s.pkce_verifier);
 
             let token = &data
                 .oauth
                 .exchange_code(code).map(|ec| ec.set_pkce_verifier(verifier)).unwrap().request_async(&http_client).await;
             match token {
                 Ok(btr) => {
-                    warn!("Made token request {:#?}", btr);
-                    let auth_info = get_auth_info(&http_client, "https://www.googleapis.com/oauth2/v1/userinfo".parse().expect("Failed to parse info"), btr.access_token()).await;
+                    let auth_info = get_auth_info(&http_client, data.userinfo_url.clone(), btr.access_token()).await;
                     match auth_info {
+                        Ok(auth) if !auth.verified_email => {
+                            info!("Refusing login with unverified email {}", auth.email);
+                            Err(AuthAppError::AccessNotAllowed)
+                        }
                         Ok(auth) => {
                             info!("Got authinfo: {:#?}", auth);
-                            let auth_app_user =
-                                service::user::get_or_create_user(conn.as_ref(), auth.email)
-                                    .await?;
-                            let token = token::create_token(
-                                config.as_ref().clone(),
-                                auth_app_user.email,
-                                vec![],
-                            )?;
-                            let cookie_name = config.as_ref().clone().cookie_name;
-                            let session_cookie = Cookie::build(cookie_name, token)
-                                .max_age(Duration::seconds(config.cookie_life_time_secs))
-                                .domain(config.as_ref().clone().cookie_domain)
-                                .http_only(true)
-                                .same_site(SameSite::Strict)
-                                .finish();
-                            Ok(HttpResponse::Found()
-                                .cookie(session_cookie)
-                                .append_header((header::LOCATION, "/admin/instances"))
-                                .finish())
+                            sign_in(
+                                conn.as_ref(),
+                                &config,
+                                keyring.as_ref(),
+                                auth.email,
+                                auth.hd.as_deref(),
+                                Some(btr.access_token().secret()),
+                                s.return_to,
+                            )
+                            .await
                         }
                         Err(_) => {
                             info!("Could not find auth_info in response");
@@ -141,7 +164,7 @@ async fn callback(
             }
         }
         None => {
-            println!("Could not find {}", state.secret());
+            info!("Could not find login state");
             Err(AuthAppError::AccessNotAllowed)
         }
     }
@@ -1,12 +1,20 @@
 use actix_web::web::Json;
-use paperclip::actix::{api_v2_operation, get, post, web, Apiv2Schema, CreatedJson};
+use paperclip::actix::{api_v2_operation, delete, get, post, put, web, Apiv2Schema, CreatedJson};
 use paperclip_actix::web::ServiceConfig;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 
+use crate::auth::instance_key::{self, INTROSPECTION_KEY_TYPE};
+use crate::auth::middleware::{Authorities, ADMIN};
 use crate::db;
-use crate::model::instance::{CreateInstanceBody, InstanceRow, InstanceStatus};
+use crate::model::instance::{
+    CreateInstanceBody, CreatedInstanceKey, InstanceRow, InstanceStatus, InstanceTransition,
+};
+use crate::model::saml::{SamlConfig, SamlConfigBody};
+use crate::model::service_token::ServiceScope;
 use crate::model::{AuthAppResult, CreatedAuthAppResult};
+use crate::service;
+use crate::AppConfig;
 
 #[derive(Serialize, Deserialize, Apiv2Schema)]
 pub struct ClientIdPathInfo {
@@ -17,39 +25,260 @@ pub struct ClientIdPathInfo {
 #[get("/status/{client_id}")]
 async fn get_instance_status(
     conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
     clientid_path: web::Path<ClientIdPathInfo>,
 ) -> AuthAppResult<InstanceStatus> {
+    authorities.require_any(&[
+        ADMIN,
+        ServiceScope::InstancesRead.into(),
+        ServiceScope::InstancesWrite.into(),
+    ])?;
     db::instance_status::get_instance_status(conn, clientid_path.client_id.clone())
         .await
         .map(Json)
 }
 
+/// Pushes back the end of the instance's trial by the configured extension, up to the configured number of times.
 #[api_v2_operation]
-#[post("/extend/{client_id}")]
+#[post("/{client_id}/extend")]
 async fn extend_trial(
     conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
     clientid_path: web::Path<ClientIdPathInfo>,
 ) -> AuthAppResult<InstanceStatus> {
-    db::instance_status::extend_trial(conn, clientid_path.client_id.clone())
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    service::instance::extend_trial(conn.as_ref(), &config, &clientid_path.client_id)
         .await
         .map(Json)
 }
 
+async fn transition_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    client_id: &str,
+    transition: InstanceTransition,
+) -> AuthAppResult<InstanceStatus> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    service::instance::transition(conn.as_ref(), &config, client_id, transition)
+        .await
+        .map(Json)
+}
+
+/// Unassigned to Trial, which ends after the configured trial length.
+#[api_v2_operation]
+#[post("/{client_id}/trial")]
+async fn start_trial(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<InstanceStatus> {
+    transition_instance(
+        conn,
+        config,
+        authorities,
+        &clientid_path.client_id,
+        InstanceTransition::StartTrial,
+    )
+    .await
+}
+
+/// Unassigned, Trial or Expired to Active.
+#[api_v2_operation]
+#[post("/{client_id}/activate")]
+async fn activate_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<InstanceStatus> {
+    transition_instance(
+        conn,
+        config,
+        authorities,
+        &clientid_path.client_id,
+        InstanceTransition::Activate,
+    )
+    .await
+}
+
+/// Trial to Expired.
+#[api_v2_operation]
+#[post("/{client_id}/expire")]
+async fn expire_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<InstanceStatus> {
+    transition_instance(
+        conn,
+        config,
+        authorities,
+        &clientid_path.client_id,
+        InstanceTransition::Expire,
+    )
+    .await
+}
+
+/// Active or Expired to Churned.
+#[api_v2_operation]
+#[post("/{client_id}/churn")]
+async fn churn_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<InstanceStatus> {
+    transition_instance(
+        conn,
+        config,
+        authorities,
+        &clientid_path.client_id,
+        InstanceTransition::Churn,
+    )
+    .await
+}
+
+/// Churned to Active.
+#[api_v2_operation]
+#[post("/{client_id}/reactivate")]
+async fn reactivate_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<InstanceStatus> {
+    transition_instance(
+        conn,
+        config,
+        authorities,
+        &clientid_path.client_id,
+        InstanceTransition::Reactivate,
+    )
+    .await
+}
+
 #[api_v2_operation]
 async fn create_instance(
     conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
     body: Json<CreateInstanceBody>,
 ) -> CreatedAuthAppResult<InstanceRow> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
     db::instance::create(conn.as_ref(), body.into_inner())
         .await
         .map(CreatedJson)
 }
 
 #[api_v2_operation]
-async fn list_instances(conn: web::Data<Pool<Postgres>>) -> AuthAppResult<Vec<InstanceRow>> {
+async fn list_instances(
+    conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
+) -> AuthAppResult<Vec<InstanceRow>> {
+    authorities.require_any(&[
+        ADMIN,
+        ServiceScope::InstancesRead.into(),
+        ServiceScope::InstancesWrite.into(),
+    ])?;
     db::instance::list_all(conn.as_ref()).await.map(Json)
 }
 
+/// Issues a new introspection key for the instance. Existing keys stay valid until revoked, so keys can be rotated.
+#[api_v2_operation]
+#[post("/keys/{client_id}")]
+async fn create_instance_key(
+    conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> CreatedAuthAppResult<CreatedInstanceKey> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    let (key, key_hash) = instance_key::generate();
+    db::instance_key::create(
+        conn.as_ref(),
+        &clientid_path.client_id,
+        &key_hash,
+        INTROSPECTION_KEY_TYPE,
+    )
+    .await?;
+    Ok(CreatedJson(CreatedInstanceKey {
+        client_id: clientid_path.client_id.clone(),
+        key,
+    }))
+}
+
+#[api_v2_operation]
+#[delete("/keys/{client_id}")]
+async fn revoke_instance_keys(
+    conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<()> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    db::instance_key::deactivate_all(
+        conn.as_ref(),
+        &clientid_path.client_id,
+        INTROSPECTION_KEY_TYPE,
+    )
+    .await
+    .map(Json)
+}
+
+/// Lets the instance's users log in with SAML at the IdP described by the metadata, replacing any earlier setup.
+#[api_v2_operation]
+#[put("/saml/{client_id}")]
+async fn configure_saml(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+    body: Json<SamlConfigBody>,
+) -> AuthAppResult<SamlConfig> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    service::saml::configure(
+        conn.as_ref(),
+        &config,
+        &clientid_path.client_id,
+        body.into_inner(),
+    )
+    .await
+    .map(Json)
+}
+
+#[api_v2_operation]
+#[get("/saml/{client_id}")]
+async fn get_saml_config(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<SamlConfig> {
+    authorities.require_any(&[
+        ADMIN,
+        ServiceScope::InstancesRead.into(),
+        ServiceScope::InstancesWrite.into(),
+    ])?;
+    service::saml::get(conn.as_ref(), &config, &clientid_path.client_id)
+        .await
+        .map(Json)
+}
+
+/// Turns SAML login off for the instance. Users keep the access they were provisioned with.
+#[api_v2_operation]
+#[delete("/saml/{client_id}")]
+async fn delete_saml_config(
+    conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
+    clientid_path: web::Path<ClientIdPathInfo>,
+) -> AuthAppResult<()> {
+    authorities.require_any(&[ADMIN, ServiceScope::InstancesWrite.into()])?;
+    db::saml::delete_config(conn.as_ref(), &clientid_path.client_id)
+        .await
+        .map(Json)
+}
+
 pub fn configure_instances(cfg: &mut ServiceConfig) {
     cfg.service(
         web::resource("")
@@ -57,5 +286,15 @@ pub fn configure_instances(cfg: &mut ServiceConfig) {
             .route(web::post().to(create_instance)),
     )
     .service(get_instance_status)
-    .service(extend_trial);
+    .service(extend_trial)
+    .service(start_trial)
+    .service(activate_instance)
+    .service(expire_instance)
+    .service(churn_instance)
+    .service(reactivate_instance)
+    .service(create_instance_key)
+    .service(revoke_instance_keys)
+    .service(configure_saml)
+    .service(get_saml_config)
+    .service(delete_saml_config);
 }
@@ -0,0 +1,41 @@
+use actix_web::web::Json;
+use actix_web::HttpRequest;
+use paperclip::actix::{api_v2_operation, web};
+use paperclip_actix::web::ServiceConfig;
+use sqlx::{Pool, Postgres};
+
+use crate::auth::instance_key::{self, INTROSPECTION_KEY_TYPE};
+use crate::auth::keyring::Keyring;
+use crate::model::introspection::{IntrospectionRequest, IntrospectionResponse};
+use crate::model::AuthAppResult;
+use crate::{service, AppConfig};
+
+/// Lets an Unleash instance check a session token it was handed. The instance authenticates with its
+/// introspection key, and only learns the user's role on itself.
+#[api_v2_operation]
+async fn introspect(
+    req: HttpRequest,
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    body: web::Form<IntrospectionRequest>,
+) -> AuthAppResult<IntrospectionResponse> {
+    let client_id = instance_key::authenticate(conn.as_ref(), &req, INTROSPECTION_KEY_TYPE).await?;
+    let body = body.into_inner();
+    let session = service::session::validate(
+        conn.as_ref(),
+        config.as_ref().clone(),
+        keyring.as_ref(),
+        body.token,
+    )
+    .await;
+    let response = match session {
+        Ok(user) => IntrospectionResponse::for_instance(&user, &client_id),
+        Err(_) => IntrospectionResponse::inactive(),
+    };
+    Ok(Json(response))
+}
+
+pub fn configure_introspection(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("").route(web::post().to(introspect)));
+}
@@ -0,0 +1,29 @@
+use crate::auth::middleware::{ADMIN, Authorities};
+use crate::model::AuthAppResult;
+use crate::service;
+use paperclip::actix::web::{Json, ServiceConfig};
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct EmailPathInfo {
+    email: String,
+}
+
+/// Forgets the user's failed password logins, unlocking their account before the lockout runs out.
+#[api_v2_operation]
+async fn unlock_account(
+    conn: web::Data<Pool<Postgres>>,
+    authorities: Authorities,
+    email: web::Path<EmailPathInfo>,
+) -> AuthAppResult<()> {
+    authorities.require(ADMIN)?;
+    service::user::unlock(conn.as_ref(), &email.email)
+        .await
+        .map(|_| Json(()))
+}
+
+pub fn configure_lockouts(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/{email}").route(web::delete().to(unlock_account)));
+}
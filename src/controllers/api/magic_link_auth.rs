@@ -0,0 +1,68 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::controllers::api::sign_in::{LoginParams, sign_in_or_challenge};
+use crate::errors::AuthAppError;
+use crate::mail::Mailer;
+use crate::model::AuthAppResult;
+use crate::service;
+use actix_web::HttpResponse;
+use paperclip::actix::web::{Json, ServiceConfig};
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct MagicLinkRequest {
+    email: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct MagicLinkCallback {
+    token: String,
+}
+
+/// Emails the user a link that logs them in once. Always succeeds, so it can't be used to find out who can log in.
+#[api_v2_operation]
+async fn login(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    mailer: web::Data<Box<dyn Mailer>>,
+    params: web::Query<LoginParams>,
+    form: web::Form<MagicLinkRequest>,
+) -> AuthAppResult<()> {
+    let return_to = params.return_to(&config)?;
+    service::magic_link::request(
+        conn.as_ref(),
+        &config,
+        mailer.as_ref().as_ref(),
+        &form.email,
+        return_to,
+    )
+    .await
+    .map(Json)
+}
+
+/// Where the emailed link's page posts to: signs in the email it was sent to, like the provider callbacks do, unless it
+/// needs a second factor.
+#[api_v2_operation]
+async fn callback(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    form: web::Form<MagicLinkCallback>,
+) -> Result<HttpResponse, AuthAppError> {
+    let link = service::magic_link::take(conn.as_ref(), &form.token).await?;
+    sign_in_or_challenge(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        link.email,
+        link.return_to,
+    )
+    .await
+}
+
+pub fn configure_magic_link_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/login").route(web::post().to(login)));
+    cfg.service(web::resource("/callback").route(web::post().to(callback)));
+}
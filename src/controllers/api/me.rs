@@ -0,0 +1,168 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::token::AuthenticatedTokenUser;
+use crate::model::instance::AccessibleInstance;
+use crate::model::passkey::{NewPasskeyBody, Passkey, RegistrationOptions};
+use crate::model::two_factor::{RecoveryCodes, TotpCode, TotpEnrolment};
+use crate::model::{AuthAppResult, CreatedAuthAppResult};
+use crate::{AppConfig, AppState, service};
+use paperclip::actix::web::{Json, ServiceConfig};
+use paperclip::actix::{Apiv2Schema, CreatedJson, api_v2_operation, delete, get, post, put, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct SetPasswordBody {
+    /// Required when the user already has a password
+    current_password: Option<String>,
+    new_password: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct PasskeyPathInfo {
+    id: String,
+}
+
+/// The instances the logged in user has access to, with where to reach them.
+#[api_v2_operation]
+#[get("/instances")]
+async fn my_instances(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    user: AuthenticatedTokenUser,
+) -> AuthAppResult<Vec<AccessibleInstance>> {
+    service::instance::accessible_instances(conn.as_ref(), &config, &user.email)
+        .await
+        .map(Json)
+}
+
+/// Sets or changes the logged in user's password for email and password login, ending their other sessions.
+#[api_v2_operation]
+#[put("/password")]
+async fn set_my_password(
+    conn: web::Data<Pool<Postgres>>,
+    app_state: web::Data<AppState>,
+    keyring: web::Data<Keyring>,
+    user: AuthenticatedTokenUser,
+    body: Json<SetPasswordBody>,
+) -> AuthAppResult<()> {
+    service::user::set_password(
+        conn.as_ref(),
+        app_state.as_ref(),
+        keyring.as_ref(),
+        &user,
+        body.current_password.as_deref(),
+        &body.new_password,
+    )
+    .await
+    .map(Json)
+}
+
+/// Starts setting up an authenticator app. Logins only ask for it after it is confirmed with a code.
+#[api_v2_operation]
+#[post("/totp")]
+async fn enrol_totp(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    user: AuthenticatedTokenUser,
+) -> AuthAppResult<TotpEnrolment> {
+    service::two_factor::start_enrolment(conn.as_ref(), &config, keyring.as_ref(), &user.email)
+        .await
+        .map(Json)
+}
+
+/// Confirms the authenticator app with a code from it, turning on two-factor login. Returns the recovery codes.
+#[api_v2_operation]
+#[post("/totp/confirm")]
+async fn confirm_totp(
+    conn: web::Data<Pool<Postgres>>,
+    keyring: web::Data<Keyring>,
+    user: AuthenticatedTokenUser,
+    body: Json<TotpCode>,
+) -> AuthAppResult<RecoveryCodes> {
+    service::two_factor::confirm_enrolment(conn.as_ref(), keyring.as_ref(), &user.email, &body.code)
+        .await
+        .map(Json)
+}
+
+/// Replaces the recovery codes with new ones, given a code from the authenticator app.
+#[api_v2_operation]
+#[post("/totp/recovery-codes")]
+async fn regenerate_recovery_codes(
+    conn: web::Data<Pool<Postgres>>,
+    keyring: web::Data<Keyring>,
+    user: AuthenticatedTokenUser,
+    body: Json<TotpCode>,
+) -> AuthAppResult<RecoveryCodes> {
+    service::two_factor::regenerate_recovery_codes(
+        conn.as_ref(),
+        keyring.as_ref(),
+        &user.email,
+        &body.code,
+    )
+    .await
+    .map(Json)
+}
+
+/// Starts registering a passkey: pass the options to `navigator.credentials.create()` and post what it returns to
+/// `/api/me/passkeys`.
+#[api_v2_operation]
+#[post("/passkeys/registration")]
+async fn start_passkey_registration(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    user: AuthenticatedTokenUser,
+) -> AuthAppResult<RegistrationOptions> {
+    service::passkey::start_registration(conn.as_ref(), &config, &user.email)
+        .await
+        .map(Json)
+}
+
+/// Adds the passkey created for a registration, after which the user can log in with it.
+#[api_v2_operation]
+#[post("/passkeys")]
+async fn add_passkey(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    user: AuthenticatedTokenUser,
+    body: Json<NewPasskeyBody>,
+) -> CreatedAuthAppResult<Passkey> {
+    service::passkey::finish_registration(conn.as_ref(), &config, &user.email, body.into_inner())
+        .await
+        .map(CreatedJson)
+}
+
+#[api_v2_operation]
+#[get("/passkeys")]
+async fn my_passkeys(
+    conn: web::Data<Pool<Postgres>>,
+    user: AuthenticatedTokenUser,
+) -> AuthAppResult<Vec<Passkey>> {
+    service::passkey::list(conn.as_ref(), &user.email)
+        .await
+        .map(Json)
+}
+
+#[api_v2_operation]
+#[delete("/passkeys/{id}")]
+async fn delete_passkey(
+    conn: web::Data<Pool<Postgres>>,
+    user: AuthenticatedTokenUser,
+    path: web::Path<PasskeyPathInfo>,
+) -> AuthAppResult<()> {
+    service::passkey::delete(conn.as_ref(), &user.email, &path.id)
+        .await
+        .map(Json)
+}
+
+pub fn configure_me(cfg: &mut ServiceConfig) {
+    cfg.service(my_instances);
+    cfg.service(set_my_password);
+    cfg.service(enrol_totp);
+    cfg.service(confirm_totp);
+    cfg.service(regenerate_recovery_codes);
+    cfg.service(start_passkey_registration);
+    cfg.service(add_passkey);
+    cfg.service(my_passkeys);
+    cfg.service(delete_passkey);
+}
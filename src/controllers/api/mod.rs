@@ -1,10 +1,65 @@
+use crate::auth::middleware::extract_authorities;
+use actix_web_grants::GrantsMiddleware;
 use paperclip::actix::web;
+mod github_auth;
 mod google_auth;
 mod instances;
+mod introspection;
+mod lockouts;
+mod magic_link_auth;
+mod me;
+mod oidc_auth;
+mod passkey_auth;
+mod password_auth;
+mod refresh;
+mod saml_auth;
+mod service_tokens;
+mod session_cookies;
+mod sessions;
+mod sign_in;
+mod two_factor_auth;
 mod users;
 
 pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/auth/github").configure(github_auth::configure_github_auth));
     cfg.service(web::scope("/auth/google").configure(google_auth::configure_google_auth));
-    cfg.service(web::scope("/instances").configure(instances::configure_instances));
-    cfg.service(web::scope("/users").configure(users::configure_users));
+    cfg.service(web::scope("/auth/introspect").configure(introspection::configure_introspection));
+    cfg.service(
+        web::scope("/auth/magic-link").configure(magic_link_auth::configure_magic_link_auth),
+    );
+    cfg.service(web::scope("/auth/passkey").configure(passkey_auth::configure_passkey_auth));
+    cfg.service(web::scope("/auth/password").configure(password_auth::configure_password_auth));
+    cfg.service(web::scope("/auth/refresh").configure(refresh::configure_refresh));
+    cfg.service(web::scope("/auth/saml").configure(saml_auth::configure_saml_auth));
+    cfg.service(
+        web::scope("/auth/two-factor").configure(two_factor_auth::configure_two_factor_auth),
+    );
+    // Registered after the fixed /auth scopes so those keep precedence
+    cfg.service(web::scope("/auth/{provider_name}").configure(oidc_auth::configure_oidc_auth));
+    cfg.service(web::scope("/me").configure(me::configure_me));
+    cfg.service(
+        web::scope("/instances")
+            .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+            .configure(instances::configure_instances),
+    );
+    cfg.service(
+        web::scope("/lockouts")
+            .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+            .configure(lockouts::configure_lockouts),
+    );
+    cfg.service(
+        web::scope("/service-tokens")
+            .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+            .configure(service_tokens::configure_service_tokens),
+    );
+    cfg.service(
+        web::scope("/sessions")
+            .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+            .configure(sessions::configure_sessions),
+    );
+    cfg.service(
+        web::scope("/users")
+            .wrap(GrantsMiddleware::with_extractor(extract_authorities))
+            .configure(users::configure_users),
+    );
 }
@@ -0,0 +1,129 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::auth::login_state::{LoginState, LoginStateStore};
+use crate::auth::oidc::OidcProviders;
+use crate::controllers::api::sign_in::{LoginParams, sign_in};
+use crate::errors::AuthAppError;
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use log::info;
+use oauth2::reqwest::redirect::Policy;
+use oauth2::{AuthorizationCode, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, Scope, reqwest};
+use paperclip::actix::web::ServiceConfig;
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct OidcCallback {
+    code: String,
+    state: String,
+}
+
+/// Redirects to the named OpenID Connect provider, remembering the PKCE verifier and ID token nonce.
+#[api_v2_operation]
+async fn login(
+    config: web::Data<AppConfig>,
+    providers: web::Data<OidcProviders>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    provider_name: web::Path<String>,
+    params: web::Query<LoginParams>,
+) -> Result<HttpResponse, AuthAppError> {
+    let provider = providers
+        .get(&provider_name)
+        .ok_or(AuthAppError::UnknownProvider)?;
+    let return_to = params.return_to(&config)?;
+    let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+    let nonce = CsrfToken::new_random().into_secret();
+    let (authorize_url, csrf_state) = provider
+        .client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(provider.scopes.iter().cloned().map(Scope::new))
+        .add_extra_param("nonce", nonce.clone())
+        .set_pkce_challenge(pkce_code_challenge)
+        .url();
+    login_states
+        .save(
+            csrf_state.secret(),
+            LoginState {
+                pkce_verifier: pkce_code_verifier.into_secret(),
+                nonce: Some(nonce),
+                provider: Some(provider.name.clone()),
+                return_to,
+            },
+        )
+        .await?;
+    Ok(HttpResponse::Found()
+        .append_header((header::LOCATION, authorize_url.to_string()))
+        .finish())
+}
+
+/// Exchanges the code for an ID token and signs in the email address it was issued for.
+#[api_v2_operation]
+async fn callback(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    providers: web::Data<OidcProviders>,
+    login_states: web::Data<Box<dyn LoginStateStore>>,
+    provider_name: web::Path<String>,
+    params: web::Query<OidcCallback>,
+) -> Result<HttpResponse, AuthAppError> {
+    let provider = providers
+        .get(&provider_name)
+        .ok_or(AuthAppError::UnknownProvider)?;
+    let login_state = login_states
+        .take(&params.state)
+        .await?
+        .filter(|s| s.provider.as_deref() == Some(provider.name.as_str()))
+        .ok_or(AuthAppError::AccessNotAllowed)?;
+    let nonce = login_state.nonce.ok_or(AuthAppError::AccessNotAllowed)?;
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to build httpclient");
+    let token_response = provider
+        .client
+        .exchange_code(AuthorizationCode::new(params.code.clone()))
+        .set_pkce_verifier(PkceCodeVerifier::new(login_state.pkce_verifier))
+        .request_async(&http_client)
+        .await
+        .map_err(|e| {
+            info!("Token request to {} failed: {e}", provider.name);
+            AuthAppError::AccessNotAllowed
+        })?;
+    let claims = provider
+        .validate_id_token(
+            &http_client,
+            &token_response.extra_fields().id_token,
+            &nonce,
+        )
+        .await
+        .map_err(|e| {
+            info!("Rejected ID token from {}: {e}", provider.name);
+            AuthAppError::AccessNotAllowed
+        })?;
+    let email = claims.verified_email().ok_or_else(|| {
+        info!(
+            "Refusing login with unverified email from {}",
+            provider.name
+        );
+        AuthAppError::AccessNotAllowed
+    })?;
+    // Other providers' tokens can't be revoked through the Google client, so they aren't kept with the session
+    sign_in(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        email,
+        None,
+        None,
+        login_state.return_to,
+    )
+    .await
+}
+
+pub fn configure_oidc_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/login").route(web::get().to(login)));
+    cfg.service(web::resource("/callback").route(web::get().to(callback)));
+}
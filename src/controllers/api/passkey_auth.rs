@@ -0,0 +1,71 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::controllers::api::sign_in::{LoginParams, sign_in, sign_in_or_challenge};
+use crate::errors::AuthAppError;
+use crate::model::AuthAppResult;
+use crate::model::passkey::{AuthenticationOptions, AuthenticationResponse};
+use crate::service;
+use actix_web::HttpResponse;
+use paperclip::actix::web::{Json, ServiceConfig};
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct PasskeyLoginForm {
+    /// JSON of the `toJSON()` of the credential `navigator.credentials.get()` returned
+    credential: String,
+}
+
+/// Starts a passkey login: pass the options to `navigator.credentials.get()` and post what it returns to `login`.
+#[api_v2_operation]
+async fn options(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    params: web::Query<LoginParams>,
+) -> AuthAppResult<AuthenticationOptions> {
+    let return_to = params.return_to(&config)?;
+    service::passkey::start_login(conn.as_ref(), &config, return_to)
+        .await
+        .map(Json)
+}
+
+/// Signs in the owner of the passkey, like the provider callbacks do. Takes a form rather than JSON so the page can
+/// post it and follow the redirect. Passkeys without user verification still need the user's second factor.
+#[api_v2_operation]
+async fn login(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    form: web::Form<PasskeyLoginForm>,
+) -> Result<HttpResponse, AuthAppError> {
+    let credential: AuthenticationResponse =
+        serde_json::from_str(&form.credential).map_err(|_| AuthAppError::InvalidPasskey)?;
+    let login = service::passkey::finish_login(conn.as_ref(), &config, &credential).await?;
+    if login.user_verified {
+        sign_in(
+            conn.as_ref(),
+            &config,
+            keyring.as_ref(),
+            login.email,
+            None,
+            None,
+            login.return_to,
+        )
+        .await
+    } else {
+        sign_in_or_challenge(
+            conn.as_ref(),
+            &config,
+            keyring.as_ref(),
+            login.email,
+            login.return_to,
+        )
+        .await
+    }
+}
+
+pub fn configure_passkey_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/options").route(web::post().to(options)));
+    cfg.service(web::resource("/login").route(web::post().to(login)));
+}
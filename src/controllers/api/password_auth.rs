@@ -0,0 +1,109 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::login_throttle;
+use crate::controllers::api::sign_in::{LoginParams, sign_in_or_challenge};
+use crate::errors::AuthAppError;
+use crate::mail::Mailer;
+use crate::model::AuthAppResult;
+use crate::service;
+use crate::{AppConfig, AppState};
+use actix_web::{HttpRequest, HttpResponse};
+use paperclip::actix::web::{Json, ServiceConfig};
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct PasswordLogin {
+    email: String,
+    password: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct PasswordReset {
+    email: String,
+    /// From the link in the reset email
+    token: String,
+    password: String,
+}
+
+/// Signs in an existing user with their email and password. Unlike provider logins this never creates users, since
+/// nobody has vouched for the email address. Repeated failures get the account or IP locked out for a while.
+#[api_v2_operation]
+async fn login(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    params: web::Query<LoginParams>,
+    form: web::Form<PasswordLogin>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthAppError> {
+    let return_to = params.return_to(&config)?;
+    let ip = login_throttle::client_ip(&req, &config.trusted_proxies);
+    service::user::check_password(conn.as_ref(), &config, &form.email, &form.password, &ip).await?;
+    sign_in_or_challenge(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        form.email.clone(),
+        return_to,
+    )
+    .await
+}
+
+/// Emails the user a link to reset their password. Always succeeds, so it can't be used to find out who has an account.
+#[api_v2_operation]
+async fn request_reset(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    mailer: web::Data<Box<dyn Mailer>>,
+    form: web::Form<PasswordResetRequest>,
+) -> AuthAppResult<()> {
+    service::password_reset::request(
+        conn.as_ref(),
+        &config,
+        mailer.as_ref().as_ref(),
+        &form.email,
+    )
+    .await
+    .map(Json)
+}
+
+/// Sets a new password with the token from a reset email, ends the user's other sessions and signs them in, since the
+/// token proves they own the email address. Users who need a second factor are asked for it first.
+#[api_v2_operation]
+async fn reset(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    app_state: web::Data<AppState>,
+    keyring: web::Data<Keyring>,
+    form: web::Form<PasswordReset>,
+) -> Result<HttpResponse, AuthAppError> {
+    service::password_reset::reset(
+        conn.as_ref(),
+        app_state.as_ref(),
+        keyring.as_ref(),
+        &form.email,
+        &form.token,
+        &form.password,
+    )
+    .await?;
+    sign_in_or_challenge(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        form.email.clone(),
+        None,
+    )
+    .await
+}
+
+pub fn configure_password_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/login").route(web::post().to(login)));
+    cfg.service(web::resource("/reset-request").route(web::post().to(request_reset)));
+    cfg.service(web::resource("/reset").route(web::post().to(reset)));
+}
@@ -0,0 +1,46 @@
+use actix_web::{HttpRequest, HttpResponse};
+use paperclip::actix::{api_v2_operation, web};
+use paperclip_actix::web::ServiceConfig;
+use sqlx::{Pool, Postgres};
+
+use crate::auth::keyring::Keyring;
+use crate::controllers::api::session_cookies;
+use crate::errors::AuthAppError;
+use crate::model::session::{RefreshRequest, TokenPair};
+use crate::{service, AppConfig, AppState};
+
+/// Exchanges a refresh token, from the body or the refresh cookie, for a new access and refresh token.
+#[api_v2_operation]
+async fn refresh(
+    req: HttpRequest,
+    conn: web::Data<Pool<Postgres>>,
+    app_state: web::Data<AppState>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    body: Option<web::Json<RefreshRequest>>,
+) -> Result<HttpResponse, AuthAppError> {
+    let presented_token = body
+        .and_then(|b| b.into_inner().refresh_token)
+        .or_else(|| {
+            req.cookie(&session_cookies::refresh_cookie_name(&config))
+                .map(|c| c.value().to_string())
+        })
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    let tokens = service::session::refresh(
+        conn.as_ref(),
+        app_state.as_ref(),
+        config.as_ref().clone(),
+        keyring.as_ref(),
+        &presented_token,
+    )
+    .await?;
+    let mut response = HttpResponse::Ok();
+    for cookie in session_cookies::session_cookies(&config, &tokens) {
+        response.cookie(cookie);
+    }
+    Ok(response.json(TokenPair::from(tokens)))
+}
+
+pub fn configure_refresh(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("").route(web::post().to(refresh)));
+}
@@ -0,0 +1,84 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::controllers::api::sign_in::{LoginParams, sign_in};
+use crate::errors::AuthAppError;
+use crate::service;
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use paperclip::actix::web::ServiceConfig;
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct SamlPath {
+    client_id: String,
+}
+
+/// What the IdP posts back with the HTTP-POST binding. Its RelayState is ignored, where to return to is kept with our
+/// request instead.
+#[derive(Deserialize, Apiv2Schema)]
+pub struct SamlResponseForm {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+}
+
+/// Our service provider metadata for the instance, to set us up at its IdP.
+#[api_v2_operation]
+async fn metadata(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    path: web::Path<SamlPath>,
+) -> Result<HttpResponse, AuthAppError> {
+    let metadata = service::saml::metadata(conn.as_ref(), &config, &path.client_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/samlmetadata+xml")
+        .body(metadata))
+}
+
+/// Sends the user to the instance's IdP with an AuthnRequest.
+#[api_v2_operation]
+async fn login(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    path: web::Path<SamlPath>,
+    params: web::Query<LoginParams>,
+) -> Result<HttpResponse, AuthAppError> {
+    let return_to = params.return_to(&config)?;
+    let url =
+        service::saml::start_login(conn.as_ref(), &config, &path.client_id, return_to).await?;
+    Ok(HttpResponse::Found()
+        .append_header((header::LOCATION, url.to_string()))
+        .finish())
+}
+
+/// The assertion consumer service: signs in the user the IdP vouched for, like the provider callbacks do. Second
+/// factors are left to the IdP.
+#[api_v2_operation]
+async fn acs(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    path: web::Path<SamlPath>,
+    form: web::Form<SamlResponseForm>,
+) -> Result<HttpResponse, AuthAppError> {
+    let login =
+        service::saml::finish_login(conn.as_ref(), &config, &path.client_id, &form.saml_response)
+            .await?;
+    sign_in(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        login.email,
+        None,
+        None,
+        login.return_to,
+    )
+    .await
+}
+
+pub fn configure_saml_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/{client_id}/metadata").route(web::get().to(metadata)));
+    cfg.service(web::resource("/{client_id}/login").route(web::get().to(login)));
+    cfg.service(web::resource("/{client_id}/acs").route(web::post().to(acs)));
+}
@@ -0,0 +1,71 @@
+use actix_web::web::{Data, Json, Path};
+use paperclip::actix::{api_v2_operation, web, Apiv2Schema, CreatedJson};
+use paperclip_actix::web::ServiceConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::auth::middleware::{Authorities, ADMIN};
+use crate::auth::service_token;
+use crate::auth::token::AuthenticatedTokenUser;
+use crate::db;
+use crate::errors::AuthAppError;
+use crate::model::service_token::{CreateServiceTokenBody, CreatedServiceToken, ServiceTokenRow};
+use crate::model::{AuthAppResult, CreatedAuthAppResult};
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct NamePathInfo {
+    name: String,
+}
+
+#[api_v2_operation]
+async fn create_service_token(
+    conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
+    user: AuthenticatedTokenUser,
+    body: Json<CreateServiceTokenBody>,
+) -> CreatedAuthAppResult<CreatedServiceToken> {
+    authorities.require(ADMIN)?;
+    if !service_token::valid_name(&body.name) {
+        return Err(AuthAppError::InvalidServiceTokenName);
+    }
+    let scopes: Vec<String> = body.scopes.iter().map(|s| s.to_string()).collect();
+    let (token, token_hash) = service_token::generate(&body.name);
+    let created =
+        db::service_token::create(conn.as_ref(), &body.name, &token_hash, &scopes, &user.email)
+            .await?;
+    Ok(CreatedJson(CreatedServiceToken {
+        name: created.name,
+        scopes: created.scopes,
+        token,
+    }))
+}
+
+#[api_v2_operation]
+async fn list_service_tokens(
+    conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
+) -> AuthAppResult<Vec<ServiceTokenRow>> {
+    authorities.require(ADMIN)?;
+    db::service_token::list_all(conn.as_ref()).await.map(Json)
+}
+
+#[api_v2_operation]
+async fn delete_service_token(
+    conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
+    name: Path<NamePathInfo>,
+) -> AuthAppResult<()> {
+    authorities.require(ADMIN)?;
+    db::service_token::delete(conn.as_ref(), &name.name)
+        .await
+        .map(Json)
+}
+
+pub fn configure_service_tokens(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::resource("")
+            .route(web::get().to(list_service_tokens))
+            .route(web::post().to(create_service_token)),
+    )
+    .service(web::resource("/{name}").route(web::delete().to(delete_service_token)));
+}
@@ -0,0 +1,45 @@
+use crate::service::session::SessionTokens;
+use crate::AppConfig;
+use actix_web::cookie::time::Duration;
+use awc::cookie::{Cookie, SameSite};
+
+/// The refresh cookie is only sent to the refresh endpoint, keeping it out of every other request.
+const REFRESH_COOKIE_PATH: &str = "/api/auth/refresh";
+
+pub fn refresh_cookie_name(config: &AppConfig) -> String {
+    format!("{}_refresh", config.cookie_name)
+}
+
+pub fn session_cookies(config: &AppConfig, tokens: &SessionTokens) -> [Cookie<'static>; 2] {
+    let session_cookie = Cookie::build(config.cookie_name.clone(), tokens.access.token.clone())
+        .max_age(Duration::seconds(config.cookie_life_time_secs))
+        .domain(config.cookie_domain.clone())
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    let refresh_cookie = Cookie::build(refresh_cookie_name(config), tokens.refresh_token.clone())
+        .max_age(Duration::seconds(config.refresh_token_life_time_secs))
+        .domain(config.cookie_domain.clone())
+        .path(REFRESH_COOKIE_PATH)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    [session_cookie, refresh_cookie]
+}
+
+pub fn removal_cookies(config: &AppConfig) -> [Cookie<'static>; 2] {
+    let mut session_cookie = Cookie::build(config.cookie_name.clone(), "")
+        .domain(config.cookie_domain.clone())
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    session_cookie.make_removal();
+    let mut refresh_cookie = Cookie::build(refresh_cookie_name(config), "")
+        .domain(config.cookie_domain.clone())
+        .path(REFRESH_COOKIE_PATH)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    refresh_cookie.make_removal();
+    [session_cookie, refresh_cookie]
+}
@@ -0,0 +1,43 @@
+use actix_web::web::{Data, Json, Path};
+use paperclip::actix::{api_v2_operation, web, Apiv2Schema};
+use paperclip_actix::web::ServiceConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::auth::keyring::Keyring;
+use crate::auth::middleware::{Authorities, ADMIN};
+use crate::model::session::RevokedSessions;
+use crate::model::AuthAppResult;
+use crate::{service, AppState};
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct EmailPathInfo {
+    email: String,
+}
+
+/// Logs the user out everywhere, e.g. when their account is compromised or they leave.
+#[api_v2_operation]
+async fn revoke_sessions_for_email(
+    conn: Data<Pool<Postgres>>,
+    app_state: Data<AppState>,
+    keyring: Data<Keyring>,
+    authorities: Authorities,
+    email: Path<EmailPathInfo>,
+) -> AuthAppResult<RevokedSessions> {
+    authorities.require(ADMIN)?;
+    let revoked = service::session::end_all_for_email(
+        conn.as_ref(),
+        app_state.as_ref(),
+        keyring.as_ref(),
+        &email.email,
+    )
+    .await?;
+    Ok(Json(RevokedSessions {
+        email: email.email.clone(),
+        revoked,
+    }))
+}
+
+pub fn configure_sessions(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/{email}").route(web::delete().to(revoke_sessions_for_email)));
+}
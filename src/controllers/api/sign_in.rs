@@ -0,0 +1,129 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::return_to;
+use crate::controllers::api::session_cookies;
+use crate::controllers::pages;
+use crate::controllers::pages::TWO_FACTOR_PATH;
+use crate::errors::AuthAppError;
+use crate::model::two_factor::RecoveryCodes;
+use crate::{AppConfig, db, service};
+use actix_web::http::header;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use paperclip::actix::Apiv2Schema;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+/// Query parameters accepted by every provider's login endpoint.
+#[derive(Deserialize, Apiv2Schema)]
+pub struct LoginParams {
+    /// Path or URL of this app or one of our instances to return to after login
+    return_to: Option<String>,
+}
+
+impl LoginParams {
+    pub fn return_to(&self, config: &AppConfig) -> Result<Option<String>, AuthAppError> {
+        self.return_to
+            .as_deref()
+            .map(|r| return_to::validate(config, r))
+            .transpose()
+    }
+}
+
+/// Finishes a login once a provider has told us who the user is: starts a session and sends them back to where
+/// they came from.
+pub async fn sign_in(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    email: String,
+    hosted_domain: Option<&str>,
+    upstream_token: Option<&str>,
+    return_to: Option<String>,
+) -> Result<HttpResponse, AuthAppError> {
+    let mut response = HttpResponse::Found();
+    start_session(
+        conn,
+        config,
+        keyring,
+        email,
+        hosted_domain,
+        upstream_token,
+        &mut response,
+    )
+    .await?;
+    Ok(response
+        .append_header((
+            header::LOCATION,
+            return_to.unwrap_or_else(|| return_to::DEFAULT_RETURN_TO.to_string()),
+        ))
+        .finish())
+}
+
+/// Like [sign_in], but shows the user the recovery codes for the authenticator they just set up before they go on.
+pub async fn sign_in_showing_recovery_codes(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    email: String,
+    recovery_codes: &RecoveryCodes,
+    return_to: Option<String>,
+) -> Result<HttpResponse, AuthAppError> {
+    let mut response = HttpResponse::Ok();
+    start_session(conn, config, keyring, email, None, None, &mut response).await?;
+    let continue_to = return_to.unwrap_or_else(|| return_to::DEFAULT_RETURN_TO.to_string());
+    Ok(response
+        .content_type("text/html; charset=utf-8")
+        .body(pages::recovery_codes_page(recovery_codes, &continue_to)))
+}
+
+async fn start_session(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    email: String,
+    hosted_domain: Option<&str>,
+    upstream_token: Option<&str>,
+    response: &mut HttpResponseBuilder,
+) -> Result<(), AuthAppError> {
+    let auth_app_user = service::user::get_or_create_user(conn, email, hosted_domain).await?;
+    let access = db::user_access::get_access_for_email(conn, &auth_app_user.email).await?;
+    let session = service::session::start(
+        conn,
+        config.clone(),
+        keyring,
+        auth_app_user.email,
+        access,
+        upstream_token,
+    )
+    .await?;
+    for cookie in session_cookies::session_cookies(config, &session) {
+        response.cookie(cookie);
+    }
+    Ok(())
+}
+
+/// For logins with our own credentials, i.e. passwords and email links: users who need a second factor are sent on to
+/// enter it, everyone else is signed in right away and has their failed logins forgotten. Identity providers are trusted
+/// to enforce their own second factor.
+pub async fn sign_in_or_challenge(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    email: String,
+    return_to: Option<String>,
+) -> Result<HttpResponse, AuthAppError> {
+    // Creating the user first means an instance they join by domain can already require the second factor
+    let user = service::user::get_or_create_user(conn, email, None).await?;
+    if service::two_factor::needs_second_factor(conn, &user.email).await? {
+        let challenge =
+            service::two_factor::start_challenge(conn, config, &user.email, return_to.as_deref())
+                .await?;
+        return Ok(HttpResponse::Found()
+            .append_header((
+                header::LOCATION,
+                format!("{TWO_FACTOR_PATH}?challenge={challenge}"),
+            ))
+            .finish());
+    }
+    db::failed_login::clear_for_email(conn, &user.email).await?;
+    sign_in(conn, config, keyring, user.email, None, None, return_to).await
+}
@@ -0,0 +1,66 @@
+use crate::AppConfig;
+use crate::auth::keyring::Keyring;
+use crate::controllers::api::sign_in::{sign_in, sign_in_showing_recovery_codes};
+use crate::errors::AuthAppError;
+use crate::service;
+use actix_web::HttpResponse;
+use paperclip::actix::web::ServiceConfig;
+use paperclip::actix::{Apiv2Schema, api_v2_operation, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct TwoFactorVerification {
+    /// From the redirect after the first factor
+    challenge: String,
+    /// Code from the authenticator app, or a recovery code
+    code: String,
+}
+
+/// Second login step: signs the user in once they enter a code for the challenge their first factor started. Users who
+/// set up their authenticator in this step are shown their recovery codes before going on.
+#[api_v2_operation]
+async fn verify(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    form: web::Form<TwoFactorVerification>,
+) -> Result<HttpResponse, AuthAppError> {
+    let completed = service::two_factor::complete_challenge(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        &form.challenge,
+        &form.code,
+    )
+    .await?;
+    match completed.recovery_codes {
+        Some(recovery_codes) => {
+            sign_in_showing_recovery_codes(
+                conn.as_ref(),
+                &config,
+                keyring.as_ref(),
+                completed.email,
+                &recovery_codes,
+                completed.return_to,
+            )
+            .await
+        }
+        None => {
+            sign_in(
+                conn.as_ref(),
+                &config,
+                keyring.as_ref(),
+                completed.email,
+                None,
+                None,
+                completed.return_to,
+            )
+            .await
+        }
+    }
+}
+
+pub fn configure_two_factor_auth(cfg: &mut ServiceConfig) {
+    cfg.service(web::resource("/verify").route(web::post().to(verify)));
+}
@@ -5,9 +5,12 @@ use paperclip_actix::web::ServiceConfig;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 
+use crate::auth::middleware::{instance_authority, Authorities, ADMIN};
 use crate::db;
+use crate::errors::AuthAppError;
+use crate::model::service_token::ServiceScope;
 use crate::model::user::{
-    CreateUserBody, DeleteUserBody, DeleteUserRequest, MinimalAuthUser, SyncUserBody,
+    CreateUserBody, DeleteUserBody, DeleteUserRequest, MinimalAuthUser, Role, SyncUserBody,
 };
 use crate::model::{AuthAppResult, CreatedAuthAppResult};
 
@@ -16,11 +19,25 @@ pub struct ClientIdPathInfo {
     client_id: String,
 }
 
+fn require_instance_admin(
+    authorities: &Authorities,
+    client_id: &str,
+    scope: ServiceScope,
+) -> Result<(), AuthAppError> {
+    authorities.require_any(&[
+        ADMIN,
+        &instance_authority(client_id, &Role::ADMIN.to_string()),
+        scope.into(),
+    ])
+}
+
 #[api_v2_operation]
 async fn create_user(
     conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
     body: Json<CreateUserBody>,
 ) -> CreatedAuthAppResult<MinimalAuthUser> {
+    require_instance_admin(&authorities, &body.client_id, ServiceScope::UsersWrite)?;
     db::user::create(conn.as_ref(), body.into_inner())
         .await
         .map(CreatedJson)
@@ -29,9 +46,11 @@ async fn create_user(
 #[api_v2_operation]
 async fn remove_user(
     conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
     client_id: Path<ClientIdPathInfo>,
     body: Json<DeleteUserBody>,
 ) -> AuthAppResult<()> {
+    require_instance_admin(&authorities, &client_id.client_id, ServiceScope::UsersWrite)?;
     db::user::delete(
         conn.as_ref(),
         DeleteUserRequest {
@@ -46,9 +65,11 @@ async fn remove_user(
 #[api_v2_operation]
 async fn sync_users(
     conn: Data<Pool<Postgres>>,
+    authorities: Authorities,
     client_id: Path<ClientIdPathInfo>,
     body: Json<SyncUserBody>,
 ) -> AuthAppResult<()> {
+    require_instance_admin(&authorities, &client_id.client_id, ServiceScope::UsersSync)?;
     warn!("Entered sync operation");
     db::user::sync_users(
         conn.as_ref(),
@@ -1,2 +1,4 @@
 pub mod api;
 pub mod internalbackstage;
+pub mod pages;
+pub mod wellknown;
@@ -0,0 +1,67 @@
+use super::{INSTANCE_CHOOSER_PATH, escape_html, page};
+use crate::auth::token::AuthenticatedTokenUser;
+use crate::errors::AuthAppError;
+use crate::model::instance::AccessibleInstance;
+use crate::{AppConfig, service};
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use paperclip::actix::{api_v2_operation, get, web};
+use sqlx::{Pool, Postgres};
+
+/// Where logged in users land: straight on their instance if they have one, otherwise a list to pick from.
+#[api_v2_operation(skip)]
+#[get("/choose-instance")]
+pub async fn choose_instance(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    user: Option<AuthenticatedTokenUser>,
+) -> Result<HttpResponse, AuthAppError> {
+    let Some(user) = user else {
+        return Ok(redirect(&format!(
+            "/api/auth/google/login?return_to={INSTANCE_CHOOSER_PATH}"
+        )));
+    };
+    let instances =
+        service::instance::accessible_instances(conn.as_ref(), &config, &user.email).await?;
+    match instances.as_slice() {
+        [instance] => Ok(redirect(&instance.url)),
+        _ => Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render(&user.email, &instances))),
+    }
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .append_header((header::LOCATION, location))
+        .finish()
+}
+
+fn render(email: &str, instances: &[AccessibleInstance]) -> String {
+    let content = if instances.is_empty() {
+        "<p>You don't have access to any Unleash instances yet. Ask an admin of your instance to invite you.</p>"
+            .to_string()
+    } else {
+        let items: String = instances
+            .iter()
+            .map(|i| {
+                format!(
+                    r#"<li><a href="{}">{}</a></li>"#,
+                    escape_html(&i.url),
+                    escape_html(&i.display_name)
+                )
+            })
+            .collect();
+        format!(
+            "<p>You have access to multiple Unleash instances.<br /><br />Please select the instance you want to continue with:</p><ul>{items}</ul>"
+        )
+    };
+    page(
+        "Choose instance",
+        &format!(
+            r#"<div class="alert alert-success" role="alert">You are logged in as {}.</div>
+        {content}"#,
+            escape_html(email)
+        ),
+    )
+}
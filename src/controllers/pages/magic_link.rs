@@ -0,0 +1,40 @@
+use super::{escape_html, page};
+use crate::errors::AuthAppError;
+use crate::{MAGIC_LINK_CALLBACK_PATH, service};
+use actix_web::HttpResponse;
+use paperclip::actix::{api_v2_operation, get, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize)]
+pub struct MagicLink {
+    token: String,
+}
+
+/// Where magic link emails link to: a button that logs in, or a notice when the link is expired or used.
+#[api_v2_operation(skip)]
+#[get("/magic-link")]
+pub async fn magic_link(
+    conn: web::Data<Pool<Postgres>>,
+    link: web::Query<MagicLink>,
+) -> Result<HttpResponse, AuthAppError> {
+    let body = if service::magic_link::is_valid(conn.as_ref(), &link.token).await? {
+        render_form(&link.token)
+    } else {
+        r#"<div class="alert alert-warning" role="alert">Your login link has expired or was already used.</div>"#
+            .to_string()
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page("Log in to Unleash", &body)))
+}
+
+fn render_form(token: &str) -> String {
+    format!(
+        r#"<form method="post" action="{MAGIC_LINK_CALLBACK_PATH}">
+            <input name="token" type="hidden" value="{}">
+            <button type="submit" class="btn btn-primary btn-block">Log in</button>
+        </form>"#,
+        escape_html(token)
+    )
+}
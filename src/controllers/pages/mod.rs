@@ -0,0 +1,55 @@
+use paperclip::actix::web;
+
+mod instance_chooser;
+mod magic_link;
+mod reset_password;
+mod two_factor;
+
+pub use two_factor::recovery_codes_page;
+
+pub const INSTANCE_CHOOSER_PATH: &str = "/choose-instance";
+/// Linked to from magic link emails. Only its form uses up the link, since mail scanners may open links too.
+pub const MAGIC_LINK_PATH: &str = "/magic-link";
+/// Linked to from password reset emails.
+pub const RESET_PASSWORD_PATH: &str = "/reset-password";
+/// Where password and email link logins go when the user needs a second factor.
+pub const TWO_FACTOR_PATH: &str = "/two-factor";
+
+pub fn configure_pages(cfg: &mut web::ServiceConfig) {
+    cfg.service(instance_chooser::choose_instance);
+    cfg.service(magic_link::magic_link);
+    cfg.service(reset_password::reset_password);
+    cfg.service(two_factor::two_factor);
+}
+
+/// Wraps already escaped body HTML in our page layout.
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!doctype html>
+<html>
+    <head>
+        <meta name="viewport" content="width=device-width, initial-scale=1, shrink-to-fit=no">
+        <link rel="stylesheet" href="https://cdn.getunleash.io/unleash/auth/bootstrap.min.css" crossorigin="anonymous">
+        <title>{}</title>
+    </head>
+    <body class="container text-left">
+        {body}
+    </body>
+</html>"#,
+        escape_html(title)
+    )
+}
+
+/// Escapes text for use in HTML element content and quoted attribute values.
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
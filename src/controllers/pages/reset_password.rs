@@ -0,0 +1,49 @@
+use super::{escape_html, page};
+use crate::errors::AuthAppError;
+use crate::service;
+use actix_web::HttpResponse;
+use paperclip::actix::{api_v2_operation, get, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize)]
+pub struct ResetLink {
+    email: String,
+    token: String,
+}
+
+/// Where reset emails link to: a form for the new password, or a notice when the link is expired or used.
+#[api_v2_operation(skip)]
+#[get("/reset-password")]
+pub async fn reset_password(
+    conn: web::Data<Pool<Postgres>>,
+    link: web::Query<ResetLink>,
+) -> Result<HttpResponse, AuthAppError> {
+    let body = if service::password_reset::is_valid(conn.as_ref(), &link.email, &link.token).await?
+    {
+        render_form(&link.email, &link.token)
+    } else {
+        r#"<div class="alert alert-warning" role="alert">Your reset password token is not valid.</div>"#
+            .to_string()
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page("Reset password", &body)))
+}
+
+fn render_form(email: &str, token: &str) -> String {
+    format!(
+        r#"<p>Choose a new password for {email}.</p>
+        <form method="post" action="/api/auth/password/reset">
+            <input name="email" type="hidden" value="{email}">
+            <input name="token" type="hidden" value="{token}">
+            <div class="form-group">
+                <input name="password" class="form-control" placeholder="Password" type="password" minlength="{min}" required>
+            </div>
+            <button type="submit" class="btn btn-primary btn-block">Reset</button>
+        </form>"#,
+        email = escape_html(email),
+        token = escape_html(token),
+        min = crate::auth::password::MIN_PASSWORD_LENGTH,
+    )
+}
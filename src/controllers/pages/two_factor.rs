@@ -0,0 +1,82 @@
+use super::{escape_html, page};
+use crate::auth::keyring::Keyring;
+use crate::errors::AuthAppError;
+use crate::model::two_factor::RecoveryCodes;
+use crate::service::two_factor::ChallengeStep;
+use crate::{AppConfig, service};
+use actix_web::HttpResponse;
+use paperclip::actix::{api_v2_operation, get, web};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+
+#[derive(Deserialize)]
+pub struct Challenge {
+    challenge: String,
+}
+
+/// Asks for the second factor, first helping users set up an authenticator app if they have none yet.
+#[api_v2_operation(skip)]
+#[get("/two-factor")]
+pub async fn two_factor(
+    conn: web::Data<Pool<Postgres>>,
+    config: web::Data<AppConfig>,
+    keyring: web::Data<Keyring>,
+    params: web::Query<Challenge>,
+) -> Result<HttpResponse, AuthAppError> {
+    let step = service::two_factor::challenge_step(
+        conn.as_ref(),
+        &config,
+        keyring.as_ref(),
+        &params.challenge,
+    )
+    .await?;
+    let body = match step {
+        Some(ChallengeStep::Verify) => format!(
+            "<p>Enter the code from your authenticator app, or one of your recovery codes.</p>{}",
+            render_form(&params.challenge)
+        ),
+        Some(ChallengeStep::Enrol(enrolment)) => format!(
+            r#"<p>An instance you have access to requires two-factor authentication. Add this account to your
+            authenticator app with <a href="{}">this link</a> or the key <code>{}</code>, then enter the code it
+            shows.</p>{}"#,
+            escape_html(&enrolment.otpauth_uri),
+            escape_html(&enrolment.secret),
+            render_form(&params.challenge)
+        ),
+        None => r#"<div class="alert alert-warning" role="alert">Your login has expired, please log in again.</div>"#
+            .to_string(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page("Two-factor authentication", &body)))
+}
+
+fn render_form(challenge: &str) -> String {
+    format!(
+        r#"<form method="post" action="/api/auth/two-factor/verify">
+            <input name="challenge" type="hidden" value="{}">
+            <div class="form-group">
+                <input name="code" class="form-control" placeholder="Code" autocomplete="one-time-code" required>
+            </div>
+            <button type="submit" class="btn btn-primary btn-block">Continue</button>
+        </form>"#,
+        escape_html(challenge)
+    )
+}
+
+/// Shown once after setting up an authenticator while logging in, since we only keep the codes' hashes.
+pub fn recovery_codes_page(recovery_codes: &RecoveryCodes, continue_to: &str) -> String {
+    let codes: String = recovery_codes
+        .codes
+        .iter()
+        .map(|c| format!("<li><code>{}</code></li>", escape_html(c)))
+        .collect();
+    let body = format!(
+        r#"<p>Your authenticator app is set up. Keep these recovery codes somewhere safe, each of them lets you log in
+        once without the app. They won't be shown again.</p>
+        <ul class="list-unstyled">{codes}</ul>
+        <a href="{}" class="btn btn-primary btn-block">Continue</a>"#,
+        escape_html(continue_to)
+    );
+    page("Recovery codes", &body)
+}
@@ -0,0 +1,7 @@
+use paperclip::actix::web;
+
+mod paseto_keys;
+
+pub fn configure_well_known(cfg: &mut web::ServiceConfig) {
+    cfg.service(paseto_keys::paseto_keys);
+}
@@ -0,0 +1,17 @@
+use crate::auth::keyring::Keyring;
+use crate::model::public_key::{PublicKeySet, PublishedPublicKey};
+use paperclip::actix::web::{self, Json};
+use paperclip::actix::{api_v2_operation, get};
+
+/// Public keys for verifying v4.public session tokens offline, matched by the `kid` in the token footer.
+#[api_v2_operation]
+#[get("/paseto-keys")]
+pub async fn paseto_keys(keyring: web::Data<Keyring>) -> Json<PublicKeySet> {
+    Json(PublicKeySet {
+        keys: keyring
+            .public_keys()
+            .iter()
+            .map(PublishedPublicKey::from)
+            .collect(),
+    })
+}
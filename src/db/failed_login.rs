@@ -0,0 +1,112 @@
+use crate::auth::login_throttle::FailedLogins;
+use crate::errors::AuthAppError;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor};
+
+/// Makes other logins for the same account or IP wait until the transaction ends, so concurrent guesses can't all
+/// pass the throttle before any of their failures are counted. The account is always locked first.
+pub async fn lock(tx: &mut PgConnection, email: &str, ip: &str) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"SELECT 1 AS "locked!" FROM pg_advisory_xact_lock(1, hashtext($1))"#,
+        email
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    sqlx::query!(
+        r#"SELECT 1 AS "locked!" FROM pg_advisory_xact_lock(2, hashtext($1))"#,
+        ip
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn for_email(conn: impl PgExecutor<'_>, email: &str) -> Result<Option<FailedLogins>, AuthAppError> {
+    sqlx::query_as!(
+        FailedLogins,
+        r#"
+        SELECT login_attempts AS "attempts!", last_failed_login_at AS "last_failed_at!" FROM auth_users
+        WHERE email = $1 AND login_attempts IS NOT NULL AND last_failed_login_at IS NOT NULL
+    "#,
+        email
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Counts a failed login for the account, starting over when the last failure was before `forget_before`.
+pub async fn record_for_email(
+    conn: impl PgExecutor<'_>,
+    email: &str,
+    forget_before: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE auth_users SET
+            login_attempts = CASE WHEN last_failed_login_at > $2 THEN COALESCE(login_attempts, 0) + 1 ELSE 1 END,
+            last_failed_login_at = now()
+        WHERE email = $1
+    "#,
+        email,
+        forget_before
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+/// Returns whether the account had any failures to clear.
+pub async fn clear_for_email(conn: impl PgExecutor<'_>, email: &str) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE auth_users SET login_attempts = NULL, last_failed_login_at = NULL
+        WHERE email = $1 AND login_attempts IS NOT NULL
+    "#,
+        email
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn for_ip(conn: impl PgExecutor<'_>, ip: &str) -> Result<Option<FailedLogins>, AuthAppError> {
+    sqlx::query_as!(
+        FailedLogins,
+        "SELECT attempts, last_failed_at FROM failed_logins_by_ip WHERE ip = $1",
+        ip
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Counts a failed login from the IP, starting over when the last failure was before `forget_before`, and drops
+/// other IPs that haven't failed since then.
+pub async fn record_for_ip(
+    conn: impl PgExecutor<'_>,
+    ip: &str,
+    forget_before: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        WITH forgotten AS (
+            DELETE FROM failed_logins_by_ip WHERE last_failed_at <= $2 AND ip <> $1
+        )
+        INSERT INTO failed_logins_by_ip(ip, attempts, last_failed_at) VALUES ($1, 1, now())
+        ON CONFLICT (ip) DO UPDATE SET
+            attempts = CASE WHEN failed_logins_by_ip.last_failed_at > $2 THEN failed_logins_by_ip.attempts + 1 ELSE 1 END,
+            last_failed_at = now()
+    "#,
+        ip,
+        forget_before
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
@@ -1,5 +1,5 @@
 use crate::errors::AuthAppError;
-use crate::model::instance::{CreateInstanceBody, InstanceRow, InstanceState};
+use crate::model::instance::{CreateInstanceBody, InstanceRow, InstanceState, UserInstanceRow};
 use sqlx::{Pool, Postgres};
 
 pub async fn create(
@@ -10,18 +10,22 @@ pub async fn create(
     let display_name = create_request.display_name.clone();
     let email_domain = create_request.email_domain.clone();
     let plan = create_request.plan.clone();
-    let instance_state = InstanceState::Unassigned.to_string();
+    let instance_state = InstanceState::Unassigned;
     let region = create_request.region.clone();
     let seats = 5;
     let trial_extended = 0;
     let billing_center = create_request.billing_center.clone();
+    let require_hosted_domain = create_request.require_hosted_domain;
+    let require_two_factor = create_request.require_two_factor;
     sqlx::query_as!(InstanceRow, r#"
         INSERT INTO 
-            instances(client_id, display_name, email_domain, instance_state, plan, region, seats, billing_center, trial_extended)
+            instances(client_id, display_name, email_domain, instance_state, plan, region, seats, billing_center, trial_extended, require_hosted_domain, require_two_factor)
         VALUES 
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9) 
-        RETURNING *;
-    "#, client_id, display_name, email_domain, instance_state, plan, region, seats, billing_center, trial_extended)
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) 
+        RETURNING client_id, plan, display_name, created_at, stripe_customer_id, email_domain, region, trial_expiry, trial_start,
+            instance_state AS "instance_state: InstanceState", trial_extended, billing_center, seats, updated_at,
+            require_hosted_domain, require_two_factor, activated_at, expired_at, churned_at;
+    "#, client_id, display_name, email_domain, instance_state as InstanceState, plan, region, seats, billing_center, trial_extended, require_hosted_domain, require_two_factor)
         .fetch_one(conn)
         .await
         .map_err(AuthAppError::SqlError)
@@ -34,7 +38,10 @@ pub async fn get_instance_for_domain(
     sqlx::query_as!(
         InstanceRow,
         r#"
-        SELECT * FROM instances WHERE email_domain = $1
+        SELECT client_id, plan, display_name, created_at, stripe_customer_id, email_domain, region, trial_expiry, trial_start,
+            instance_state AS "instance_state: InstanceState", trial_extended, billing_center, seats, updated_at,
+            require_hosted_domain, require_two_factor, activated_at, expired_at, churned_at
+        FROM instances WHERE email_domain = $1
     "#,
         domain
     )
@@ -47,10 +54,48 @@ pub(crate) async fn list_all(conn: &Pool<Postgres>) -> Result<Vec<InstanceRow>,
     sqlx::query_as!(
         InstanceRow,
         r#"
-        SELECT * FROM instances;
+        SELECT client_id, plan, display_name, created_at, stripe_customer_id, email_domain, region, trial_expiry, trial_start,
+            instance_state AS "instance_state: InstanceState", trial_extended, billing_center, seats, updated_at,
+            require_hosted_domain, require_two_factor, activated_at, expired_at, churned_at
+        FROM instances;
     "#
     )
     .fetch_all(conn)
     .await
     .map_err(AuthAppError::SqlError)
 }
+
+pub async fn list_for_email(
+    conn: &Pool<Postgres>,
+    email: &str,
+) -> Result<Vec<UserInstanceRow>, AuthAppError> {
+    sqlx::query_as!(
+        UserInstanceRow,
+        r#"
+        SELECT i.client_id, i.display_name, i.region, ua.role
+        FROM user_access ua JOIN instances i ON i.client_id = ua.client_id
+        WHERE ua.email = $1
+        ORDER BY COALESCE(i.display_name, i.client_id);
+    "#,
+        email
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(AuthAppError::SqlError)
+}
+
+pub async fn get(conn: &Pool<Postgres>, client_id: &str) -> Result<InstanceRow, AuthAppError> {
+    sqlx::query_as!(
+        InstanceRow,
+        r#"
+        SELECT client_id, plan, display_name, created_at, stripe_customer_id, email_domain, region, trial_expiry, trial_start,
+            instance_state AS "instance_state: InstanceState", trial_extended, billing_center, seats, updated_at,
+            require_hosted_domain, require_two_factor, activated_at, expired_at, churned_at
+        FROM instances WHERE client_id = $1
+    "#,
+        client_id
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(AuthAppError::SqlError)
+}
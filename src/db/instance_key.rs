@@ -0,0 +1,67 @@
+use crate::errors::AuthAppError;
+use crate::model::Exists;
+use sqlx::PgPool;
+
+pub async fn create(
+    conn: &PgPool,
+    client_id: &str,
+    key_hash: &str,
+    key_type: &str,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO instance_keys(key, client_id, active, key_type) VALUES ($1, $2, true, $3)
+    "#,
+        key_hash,
+        client_id,
+        key_type
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn is_active(
+    conn: &PgPool,
+    client_id: &str,
+    key_hash: &str,
+    key_type: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        Exists,
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM instance_keys
+            WHERE key = $1 AND client_id = $2 AND key_type = $3 AND active
+        ) AS exists
+    "#,
+        key_hash,
+        client_id,
+        key_type
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Deactivates every key of `key_type` for the instance, returns [sqlx::Error::RowNotFound] if it had none.
+pub async fn deactivate_all(
+    conn: &PgPool,
+    client_id: &str,
+    key_type: &str,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "UPDATE instance_keys SET active = false WHERE client_id = $1 AND key_type = $2 AND active",
+        client_id,
+        key_type
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .and_then(|r| match r.rows_affected() {
+            0 => Err(AuthAppError::SqlError(sqlx::Error::RowNotFound)),
+            _ => Ok(()),
+        })
+}
@@ -1,5 +1,5 @@
 use crate::errors::AuthAppError;
-use crate::model::instance::InstanceStatus;
+use crate::model::instance::{InstanceState, InstanceStatus};
 use paperclip_actix::web;
 use sqlx::{Pool, Postgres};
 
@@ -15,9 +15,12 @@ pub async fn get_instance_status(
          trial_expiry, 
          trial_start, 
          trial_extended,
-         instance_state, 
+         instance_state AS "instance_state: InstanceState",
          billing_center, 
-         region 
+         region,
+         activated_at,
+         expired_at,
+         churned_at
          FROM instances 
          WHERE client_id = $1;
     "#,
@@ -28,18 +31,62 @@ pub async fn get_instance_status(
     .map_err(AuthAppError::SqlError)
 }
 
+/// Adds `extension_days` to a trial that has been extended fewer than `max_extensions` times. `None` when the
+/// instance isn't in a trial or can't be extended anymore.
 pub async fn extend_trial(
-    conn: web::Data<Pool<Postgres>>,
-    client_id: String,
-) -> Result<InstanceStatus, AuthAppError> {
+    conn: &Pool<Postgres>,
+    client_id: &str,
+    extension_days: i32,
+    max_extensions: i32,
+) -> Result<Option<InstanceStatus>, AuthAppError> {
     sqlx::query_as!(
         InstanceStatus,
         r#"
         UPDATE instances SET trial_extended = trial_extended + 1,
-                trial_expiry = trial_expiry + INTERVAL '5 DAYS'
-                 WHERE client_id = $1 AND instance_state = 'Trial'
-        RETURNING plan, trial_expiry, trial_start, trial_extended, instance_state, billing_center, region 
-    "#, client_id
-    ).fetch_one(conn.as_ref())
-        .await.map_err(AuthAppError::SqlError)
+                trial_expiry = trial_expiry + make_interval(days => $2),
+                updated_at = now()
+                 WHERE client_id = $1 AND instance_state = 'Trial' AND trial_extended < $3
+        RETURNING plan, trial_expiry, trial_start, trial_extended, instance_state AS "instance_state: InstanceState",
+                  billing_center, region, activated_at, expired_at, churned_at
+    "#,
+        client_id,
+        extension_days,
+        max_extensions
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Moves the instance from `from` to `to`, stamping when it entered `to`. Starting a trial also sets when it ends.
+/// `None` when the instance isn't in `from` (anymore).
+pub async fn transition(
+    conn: &Pool<Postgres>,
+    client_id: &str,
+    from: InstanceState,
+    to: InstanceState,
+    trial_length_days: i32,
+) -> Result<Option<InstanceStatus>, AuthAppError> {
+    sqlx::query_as!(
+        InstanceStatus,
+        r#"
+        UPDATE instances SET instance_state = $3,
+                updated_at = now(),
+                trial_start = CASE WHEN $3 = 'Trial' THEN now() ELSE trial_start END,
+                trial_expiry = CASE WHEN $3 = 'Trial' THEN now() + make_interval(days => $4) ELSE trial_expiry END,
+                activated_at = CASE WHEN $3 = 'Active' THEN now() ELSE activated_at END,
+                expired_at = CASE WHEN $3 = 'Expired' THEN now() ELSE expired_at END,
+                churned_at = CASE WHEN $3 = 'Churned' THEN now() ELSE churned_at END
+                 WHERE client_id = $1 AND instance_state = $2
+        RETURNING plan, trial_expiry, trial_start, trial_extended, instance_state AS "instance_state: InstanceState",
+                  billing_center, region, activated_at, expired_at, churned_at
+    "#,
+        client_id,
+        from as InstanceState,
+        to as InstanceState,
+        trial_length_days
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
 }
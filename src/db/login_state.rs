@@ -0,0 +1,49 @@
+use crate::auth::login_state::LoginState;
+use crate::errors::AuthAppError;
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+pub async fn create(
+    conn: &PgPool,
+    csrf_state: &str,
+    login_state: Json<LoginState>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_states(csrf_state, data, expires_at) VALUES ($1, $2, $3)
+    "#,
+        csrf_state,
+        login_state as _,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn take(
+    conn: &PgPool,
+    csrf_state: &str,
+) -> Result<Option<Json<LoginState>>, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        DELETE FROM login_states WHERE csrf_state = $1 AND expires_at > now()
+        RETURNING data AS "data: Json<LoginState>"
+    "#,
+        csrf_state
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete_expired(conn: &PgPool) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM login_states WHERE expires_at < now()")
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
@@ -0,0 +1,104 @@
+use crate::errors::AuthAppError;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor, PgPool};
+
+pub struct MagicLinkRow {
+    pub email: String,
+    pub return_to: Option<String>,
+}
+
+/// Makes other requests for a link to the same email wait until the transaction ends, so concurrent ones can't all
+/// find that no link was sent yet. Lock class 3, after the failed login locks.
+pub async fn lock(tx: &mut PgConnection, email: &str) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"SELECT 1 AS "locked!" FROM pg_advisory_xact_lock(3, hashtext($1))"#,
+        email
+    )
+        .fetch_one(tx)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn create(
+    conn: impl PgExecutor<'_>,
+    token_hash: &str,
+    email: &str,
+    return_to: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO magic_links(token_hash, email, return_to, expires_at) VALUES ($1, $2, $3, $4)
+    "#,
+        token_hash,
+        email,
+        return_to,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn sent_since(
+    conn: impl PgExecutor<'_>,
+    email: &str,
+    since: DateTime<Utc>,
+) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        crate::model::Exists,
+        r#"
+        SELECT EXISTS (SELECT 1 FROM magic_links WHERE email = $1 AND created_at > $2) AS exists
+    "#,
+        email,
+        since
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn valid(conn: &PgPool, token_hash: &str) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        crate::model::Exists,
+        r#"
+        SELECT EXISTS (SELECT 1 FROM magic_links WHERE token_hash = $1 AND expires_at > now()) AS exists
+    "#,
+        token_hash
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn take(conn: &PgPool, token_hash: &str) -> Result<Option<MagicLinkRow>, AuthAppError> {
+    sqlx::query_as!(
+        MagicLinkRow,
+        r#"
+        DELETE FROM magic_links WHERE token_hash = $1 AND expires_at > now() RETURNING email, return_to
+    "#,
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Links created after `created_before` are kept even when expired, since they still count for rate limiting.
+pub async fn delete_expired(
+    conn: &PgPool,
+    created_before: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM magic_links WHERE expires_at < now() AND created_at < $1",
+        created_before
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
@@ -1,4 +1,14 @@
+pub mod failed_login;
 pub mod instance;
+pub mod instance_key;
 pub mod instance_status;
+pub mod login_state;
+pub mod magic_link;
+pub mod passkey;
+pub mod refresh_token;
+pub mod saml;
+pub mod service_token;
+pub mod session;
+pub mod two_factor;
 pub mod user;
 pub mod user_access;
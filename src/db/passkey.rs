@@ -0,0 +1,162 @@
+use crate::errors::AuthAppError;
+use crate::model::passkey::{Passkey, PasskeyCredentialRow, WebAuthnChallengeRow};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// The user's WebAuthn user handle, storing `new_handle` for them if they don't have one yet.
+pub async fn user_handle(conn: &PgPool, email: &str, new_handle: &str) -> Result<String, AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE auth_users SET webauthn_user_handle = COALESCE(webauthn_user_handle, $2) WHERE email = $1
+        RETURNING webauthn_user_handle AS "handle!"
+    "#,
+        email,
+        new_handle
+    )
+        .fetch_one(conn)
+        .await
+        .map(|r| r.handle)
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Registrations are started by a logged in user and have their `email`, logins have none.
+pub async fn create_challenge(
+    conn: &PgPool,
+    challenge_hash: &str,
+    email: Option<&str>,
+    return_to: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO webauthn_challenges(challenge_hash, email, return_to, expires_at) VALUES ($1, $2, $3, $4)
+    "#,
+        challenge_hash,
+        email,
+        return_to,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn take_challenge(
+    conn: &PgPool,
+    challenge_hash: &str,
+    email: Option<&str>,
+) -> Result<Option<WebAuthnChallengeRow>, AuthAppError> {
+    sqlx::query_as!(
+        WebAuthnChallengeRow,
+        r#"
+        DELETE FROM webauthn_challenges
+        WHERE challenge_hash = $1 AND email IS NOT DISTINCT FROM $2 AND expires_at > now()
+        RETURNING return_to
+    "#,
+        challenge_hash,
+        email
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete_expired_challenges(conn: &PgPool) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM webauthn_challenges WHERE expires_at < now()")
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn create(
+    conn: &PgPool,
+    credential_id: &str,
+    email: &str,
+    name: &str,
+    cose_key: &str,
+    sign_count: i64,
+) -> Result<Passkey, AuthAppError> {
+    sqlx::query_as!(
+        Passkey,
+        r#"
+        INSERT INTO passkeys(credential_id, email, name, cose_key, sign_count) VALUES ($1, $2, $3, $4, $5)
+        RETURNING credential_id AS id, name, created_at, last_used_at
+    "#,
+        credential_id,
+        email,
+        name,
+        cose_key,
+        sign_count
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn list_for_email(conn: &PgPool, email: &str) -> Result<Vec<Passkey>, AuthAppError> {
+    sqlx::query_as!(
+        Passkey,
+        r#"
+        SELECT credential_id AS id, name, created_at, last_used_at FROM passkeys WHERE email = $1 ORDER BY created_at
+    "#,
+        email
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn get(
+    conn: &PgPool,
+    credential_id: &str,
+) -> Result<Option<PasskeyCredentialRow>, AuthAppError> {
+    sqlx::query_as!(
+        PasskeyCredentialRow,
+        r#"
+        SELECT p.email, p.cose_key, p.sign_count, u.webauthn_user_handle AS user_handle
+        FROM passkeys p JOIN auth_users u ON u.email = p.email WHERE p.credential_id = $1
+    "#,
+        credential_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Stores the new signature counter, returning false if another login changed it since we read `previous_count`.
+pub async fn record_use(
+    conn: &PgPool,
+    credential_id: &str,
+    previous_count: i64,
+    sign_count: i64,
+) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE passkeys SET sign_count = $3, last_used_at = now() WHERE credential_id = $1 AND sign_count = $2
+    "#,
+        credential_id,
+        previous_count,
+        sign_count
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn delete(conn: &PgPool, email: &str, credential_id: &str) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM passkeys WHERE email = $1 AND credential_id = $2",
+        email,
+        credential_id
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .and_then(|r| match r.rows_affected() {
+            0 => Err(AuthAppError::SqlError(sqlx::Error::RowNotFound)),
+            _ => Ok(()),
+        })
+}
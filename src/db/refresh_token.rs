@@ -0,0 +1,152 @@
+use crate::errors::AuthAppError;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+pub struct ConsumedRefreshToken {
+    pub family_id: String,
+    pub email: String,
+}
+
+pub struct SpentRefreshToken {
+    pub family_id: String,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+pub async fn create(
+    conn: &PgPool,
+    token_hash: &str,
+    family_id: &str,
+    email: &str,
+    session_jti: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens(token_hash, family_id, email, session_jti, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+    "#,
+        token_hash,
+        family_id,
+        email,
+        session_jti,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+/// Marks the token as used if it is still live. Doing this in a single statement means two concurrent
+/// refreshes with the same token can't both succeed.
+pub async fn consume(
+    conn: &PgPool,
+    token_hash: &str,
+) -> Result<Option<ConsumedRefreshToken>, AuthAppError> {
+    sqlx::query_as!(
+        ConsumedRefreshToken,
+        r#"
+        UPDATE refresh_tokens SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        RETURNING family_id, email
+    "#,
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Looks up a token that couldn't be consumed, to tell reuse apart from expired or unknown tokens.
+pub async fn get_spent(
+    conn: &PgPool,
+    token_hash: &str,
+) -> Result<Option<SpentRefreshToken>, AuthAppError> {
+    sqlx::query_as!(
+        SpentRefreshToken,
+        "SELECT family_id, used_at FROM refresh_tokens WHERE token_hash = $1",
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn family_for_session(
+    conn: &PgPool,
+    session_jti: &str,
+) -> Result<Option<String>, AuthAppError> {
+    sqlx::query_scalar!(
+        "SELECT family_id FROM refresh_tokens WHERE session_jti = $1",
+        session_jti
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Revokes every refresh token in the family and the sessions they were issued with, returning the upstream
+/// tokens those sessions held.
+pub async fn revoke_family(
+    conn: &PgPool,
+    family_id: &str,
+) -> Result<Vec<Option<String>>, AuthAppError> {
+    let mut tx = conn.begin().await?;
+    let upstream_tokens = sqlx::query_scalar!(
+        r#"
+        WITH live AS (
+            SELECT jti, upstream_token FROM sessions
+            WHERE revoked_at IS NULL
+            AND jti IN (SELECT session_jti FROM refresh_tokens WHERE family_id = $1)
+            FOR UPDATE
+        )
+        UPDATE sessions SET revoked_at = now(), upstream_token = NULL
+        FROM live WHERE sessions.jti = live.jti
+        RETURNING live.upstream_token
+    "#,
+        family_id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE family_id = $1 AND revoked_at IS NULL",
+        family_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    tx.commit().await.map_err(AuthAppError::SqlError)?;
+    Ok(upstream_tokens)
+}
+
+/// Revokes every refresh token for `email` outside the `keep_family` family.
+pub async fn revoke_all_for_email(
+    conn: &PgPool,
+    email: &str,
+    keep_family: Option<&str>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens SET revoked_at = now()
+        WHERE email = $1 AND revoked_at IS NULL AND family_id IS DISTINCT FROM $2
+    "#,
+        email,
+        keep_family
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn delete_expired_for_email(conn: &PgPool, email: &str) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE email = $1 AND expires_at < now()",
+        email
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
@@ -0,0 +1,115 @@
+use crate::errors::AuthAppError;
+use crate::model::saml::{SamlConfigRow, SamlRequestRow};
+use crate::model::user::Role;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use sqlx::types::Json;
+use std::collections::HashMap;
+
+pub async fn upsert_config(conn: &PgPool, config: &SamlConfigRow) -> Result<SamlConfigRow, AuthAppError> {
+    sqlx::query_as!(
+        SamlConfigRow,
+        r#"
+        INSERT INTO instance_saml_configs(client_id, idp_entity_id, idp_sso_url, idp_certificate, email_attribute, role_attribute, role_mapping, default_role)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (client_id) DO UPDATE SET
+            idp_entity_id = EXCLUDED.idp_entity_id,
+            idp_sso_url = EXCLUDED.idp_sso_url,
+            idp_certificate = EXCLUDED.idp_certificate,
+            email_attribute = EXCLUDED.email_attribute,
+            role_attribute = EXCLUDED.role_attribute,
+            role_mapping = EXCLUDED.role_mapping,
+            default_role = EXCLUDED.default_role,
+            updated_at = now()
+        RETURNING client_id, idp_entity_id, idp_sso_url, idp_certificate, email_attribute, role_attribute,
+            role_mapping AS "role_mapping: Json<HashMap<String, Role>>", default_role, updated_at
+    "#,
+        config.client_id,
+        config.idp_entity_id,
+        config.idp_sso_url,
+        config.idp_certificate,
+        config.email_attribute,
+        config.role_attribute,
+        &config.role_mapping as _,
+        config.default_role
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn get_config(conn: &PgPool, client_id: &str) -> Result<SamlConfigRow, AuthAppError> {
+    sqlx::query_as!(
+        SamlConfigRow,
+        r#"
+        SELECT client_id, idp_entity_id, idp_sso_url, idp_certificate, email_attribute, role_attribute,
+            role_mapping AS "role_mapping: Json<HashMap<String, Role>>", default_role, updated_at
+        FROM instance_saml_configs WHERE client_id = $1
+    "#,
+        client_id
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete_config(conn: &PgPool, client_id: &str) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM instance_saml_configs WHERE client_id = $1", client_id)
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .and_then(|r| match r.rows_affected() {
+            0 => Err(AuthAppError::SqlError(sqlx::Error::RowNotFound)),
+            _ => Ok(()),
+        })
+}
+
+pub async fn create_request(
+    conn: &PgPool,
+    request_id: &str,
+    client_id: &str,
+    return_to: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO saml_requests(request_id, client_id, return_to, expires_at) VALUES ($1, $2, $3, $4)
+    "#,
+        request_id,
+        client_id,
+        return_to,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+/// Each request can be answered once, and only by the IdP of the instance it was sent for.
+pub async fn take_request(
+    conn: &PgPool,
+    request_id: &str,
+    client_id: &str,
+) -> Result<Option<SamlRequestRow>, AuthAppError> {
+    sqlx::query_as!(
+        SamlRequestRow,
+        r#"
+        DELETE FROM saml_requests WHERE request_id = $1 AND client_id = $2 AND expires_at > now()
+        RETURNING return_to
+    "#,
+        request_id,
+        client_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete_expired_requests(conn: &PgPool) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM saml_requests WHERE expires_at < now()")
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
@@ -0,0 +1,66 @@
+use crate::errors::AuthAppError;
+use crate::model::service_token::ServiceTokenRow;
+use sqlx::PgPool;
+
+pub struct StoredServiceToken {
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+}
+
+pub async fn create(
+    conn: &PgPool,
+    name: &str,
+    token_hash: &str,
+    scopes: &[String],
+    created_by: &str,
+) -> Result<ServiceTokenRow, AuthAppError> {
+    sqlx::query_as!(
+        ServiceTokenRow,
+        r#"
+        INSERT INTO service_tokens(name, token_hash, scopes, created_by) VALUES ($1, $2, $3, $4)
+        RETURNING name, scopes, created_by, created_at
+    "#,
+        name,
+        token_hash,
+        scopes,
+        created_by
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn get_by_name(
+    conn: &PgPool,
+    name: &str,
+) -> Result<Option<StoredServiceToken>, AuthAppError> {
+    sqlx::query_as!(
+        StoredServiceToken,
+        "SELECT token_hash, scopes FROM service_tokens WHERE name = $1",
+        name
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn list_all(conn: &PgPool) -> Result<Vec<ServiceTokenRow>, AuthAppError> {
+    sqlx::query_as!(
+        ServiceTokenRow,
+        "SELECT name, scopes, created_by, created_at FROM service_tokens ORDER BY name"
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete(conn: &PgPool, name: &str) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM service_tokens WHERE name = $1", name)
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .and_then(|r| match r.rows_affected() {
+            0 => Err(AuthAppError::SqlError(sqlx::Error::RowNotFound)),
+            _ => Ok(()),
+        })
+}
@@ -0,0 +1,108 @@
+use crate::errors::AuthAppError;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// `upstream_token` is stored as given, so callers encrypt it first.
+pub async fn create(
+    conn: &PgPool,
+    jti: &str,
+    email: &str,
+    upstream_token: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions(jti, email, upstream_token, expires_at) VALUES ($1, $2, $3, $4)
+    "#,
+        jti,
+        email,
+        upstream_token,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn is_revoked(conn: &PgPool, jti: &str) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        crate::model::Exists,
+        r#"
+        SELECT EXISTS (SELECT 1 FROM sessions WHERE jti = $1 AND revoked_at IS NOT NULL) AS exists
+    "#,
+        jti
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Revokes the session, recording it if it was never stored, and hands back its upstream token to revoke.
+pub async fn revoke(
+    conn: &PgPool,
+    jti: &str,
+    email: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<Option<String>, AuthAppError> {
+    let mut tx = conn.begin().await?;
+    let upstream_token = sqlx::query_scalar!(
+        "SELECT upstream_token FROM sessions WHERE jti = $1 FOR UPDATE",
+        jti
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?
+        .flatten();
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions(jti, email, expires_at, revoked_at) VALUES ($1, $2, $3, now())
+        ON CONFLICT (jti) DO UPDATE SET revoked_at = COALESCE(sessions.revoked_at, now()), upstream_token = NULL
+    "#,
+        jti,
+        email,
+        expires_at
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    tx.commit().await.map_err(AuthAppError::SqlError)?;
+    Ok(upstream_token)
+}
+
+/// Revokes every live session for `email` except `keep_jti`, returning the upstream tokens they held.
+pub async fn revoke_all_for_email(
+    conn: &PgPool,
+    email: &str,
+    keep_jti: Option<&str>,
+) -> Result<Vec<Option<String>>, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        WITH live AS (
+            SELECT jti, upstream_token FROM sessions
+            WHERE email = $1 AND revoked_at IS NULL AND expires_at > now() AND jti IS DISTINCT FROM $2
+            FOR UPDATE
+        )
+        UPDATE sessions SET revoked_at = now(), upstream_token = NULL
+        FROM live WHERE sessions.jti = live.jti
+        RETURNING live.upstream_token
+    "#,
+        email,
+        keep_jti
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn delete_expired_for_email(conn: &PgPool, email: &str) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM sessions WHERE email = $1 AND expires_at < now()",
+        email
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
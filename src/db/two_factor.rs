@@ -0,0 +1,242 @@
+use crate::errors::AuthAppError;
+use crate::model::two_factor::{TotpCredentialRow, TwoFactorChallengeRow};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+pub async fn get_totp(conn: &PgPool, email: &str) -> Result<Option<TotpCredentialRow>, AuthAppError> {
+    sqlx::query_as!(
+        TotpCredentialRow,
+        r#"
+        SELECT secret, confirmed_at IS NOT NULL AS "confirmed!", last_used_step FROM totp_credentials WHERE email = $1
+    "#,
+        email
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Stores a secret awaiting confirmation, replacing any earlier unconfirmed one. Returns false when the user already
+/// has a confirmed secret, which is left alone.
+pub async fn save_unconfirmed_totp(
+    conn: &PgPool,
+    email: &str,
+    secret: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_credentials(email, secret) VALUES ($1, $2)
+        ON CONFLICT (email) DO UPDATE SET secret = $2, created_at = now(), last_used_step = NULL
+        WHERE totp_credentials.confirmed_at IS NULL
+    "#,
+        email,
+        secret
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+/// Stores a secret confirmed with the code for `step`, replacing any unconfirmed one. Returns false when the user
+/// already has a confirmed secret, which is left alone.
+pub async fn save_confirmed_totp(
+    conn: &PgPool,
+    email: &str,
+    secret: &str,
+    step: i64,
+) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_credentials(email, secret, confirmed_at, last_used_step) VALUES ($1, $2, now(), $3)
+        ON CONFLICT (email) DO UPDATE SET secret = $2, created_at = now(), confirmed_at = now(), last_used_step = $3
+        WHERE totp_credentials.confirmed_at IS NULL
+    "#,
+        email,
+        secret,
+        step
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+/// Marks `step` as used and the secret as confirmed, storing it as `secret`, sealed with the current key. Returns false
+/// when that step or a later one was already used, so concurrent requests can't both use the same code.
+pub async fn use_totp_step(
+    conn: &PgPool,
+    email: &str,
+    step: i64,
+    secret: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        r#"
+        UPDATE totp_credentials SET last_used_step = $2, secret = $3, confirmed_at = COALESCE(confirmed_at, now())
+        WHERE email = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+    "#,
+        email,
+        step,
+        secret
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn replace_recovery_codes(
+    conn: &PgPool,
+    email: &str,
+    code_hashes: Vec<String>,
+) -> Result<(), AuthAppError> {
+    let emails = vec![email; code_hashes.len()];
+    let mut tx = conn.begin().await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE email = $1", email)
+        .execute(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes(email, code_hash) SELECT * FROM UNNEST($1, $2)
+    "#,
+    )
+        .bind(&emails)
+        .bind(&code_hashes)
+        .execute(&mut *tx)
+        .await
+        .map_err(AuthAppError::SqlError)?;
+    tx.commit().await.map_err(AuthAppError::SqlError)
+}
+
+/// Deletes the recovery code, returning whether the user had it.
+pub async fn use_recovery_code(
+    conn: &PgPool,
+    email: &str,
+    code_hash: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM recovery_codes WHERE email = $1 AND code_hash = $2",
+        email,
+        code_hash
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn create_challenge(
+    conn: &PgPool,
+    token_hash: &str,
+    email: &str,
+    return_to: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO two_factor_challenges(token_hash, email, return_to, expires_at) VALUES ($1, $2, $3, $4)
+    "#,
+        token_hash,
+        email,
+        return_to,
+        expires_at
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+pub async fn get_challenge(
+    conn: &PgPool,
+    token_hash: &str,
+) -> Result<Option<TwoFactorChallengeRow>, AuthAppError> {
+    sqlx::query_as!(
+        TwoFactorChallengeRow,
+        r#"
+        SELECT email, return_to, enrolment_secret FROM two_factor_challenges
+        WHERE token_hash = $1 AND expires_at > now()
+    "#,
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Offers `secret` for enrolment during the challenge unless it already has one. Returns the secret the challenge ends
+/// up with, or `None` when it's gone.
+pub async fn set_enrolment_secret(
+    conn: &PgPool,
+    token_hash: &str,
+    secret: &str,
+) -> Result<Option<String>, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE two_factor_challenges SET enrolment_secret = COALESCE(enrolment_secret, $2) WHERE token_hash = $1
+        RETURNING enrolment_secret AS "enrolment_secret!"
+    "#,
+        token_hash,
+        secret
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Counts an attempt at the challenge, returning how many there have been or `None` when it's gone. Successful
+/// attempts delete the challenge, so all counted ones but the last have failed.
+pub async fn record_challenge_attempt(
+    conn: &PgPool,
+    token_hash: &str,
+) -> Result<Option<i32>, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE two_factor_challenges SET failed_attempts = failed_attempts + 1 WHERE token_hash = $1
+        RETURNING failed_attempts
+    "#,
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Returns whether the challenge was still there, so only one request can complete it.
+pub async fn delete_challenge(conn: &PgPool, token_hash: &str) -> Result<bool, AuthAppError> {
+    sqlx::query!(
+        "DELETE FROM two_factor_challenges WHERE token_hash = $1",
+        token_hash
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn delete_expired_challenges(conn: &PgPool) -> Result<(), AuthAppError> {
+    sqlx::query!("DELETE FROM two_factor_challenges WHERE expires_at < now()")
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+        .map(|_| ())
+}
+
+/// Whether any instance the user has access to requires a second factor.
+pub async fn required_for_email(conn: &PgPool, email: &str) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        crate::model::Exists,
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM user_access ua JOIN instances i ON i.client_id = ua.client_id
+            WHERE ua.email = $1 AND i.require_two_factor
+        ) AS exists
+    "#,
+        email
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
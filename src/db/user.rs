@@ -1,36 +1,9 @@
+use crate::auth::password::UNUSABLE_PASSWORD;
 use crate::errors::AuthAppError;
 use crate::model::user::{CreateUserBody, DeleteUserRequest, MinimalAuthUser};
-use passwords::PasswordGenerator;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Pool, Postgres};
 
-fn generate_password() -> String {
-    let generator = PasswordGenerator {
-        length: 32,
-        numbers: true,
-        lowercase_letters: true,
-        uppercase_letters: true,
-        symbols: true,
-        exclude_similar_characters: true,
-        strict: true,
-        spaces: false,
-    };
-    generator.generate_one().unwrap()
-}
-
-fn generate_passwords(number_to_generate: usize) -> Vec<String> {
-    let generator = PasswordGenerator {
-        length: 32,
-        numbers: true,
-        lowercase_letters: true,
-        uppercase_letters: true,
-        symbols: true,
-        exclude_similar_characters: true,
-        strict: true,
-        spaces: false,
-    };
-    generator.generate(number_to_generate).unwrap()
-}
-
 pub async fn create_user(
     conn: &PgPool,
     email: &str,
@@ -41,7 +14,7 @@ pub async fn create_user(
         INSERT INTO auth_users(email, password_hash) VALUES ($1, $2) ON CONFLICT(email) DO NOTHING RETURNING email, name 
     "#,
         email,
-        &generate_password()
+        UNUSABLE_PASSWORD
     )
         .fetch_one(conn)
         .await
@@ -149,16 +122,16 @@ pub async fn sync_users(
     emails: Vec<String>,
 ) -> Result<(), AuthAppError> {
     let client_ids = vec![client_id; emails.len()];
-    let passwords = generate_passwords(emails.len());
+    let passwords = vec![UNUSABLE_PASSWORD; emails.len()];
     let mut tx = conn.begin().await?;
     sqlx::query(
         r#"
         INSERT INTO auth_users(email, password_hash)
-            SELECT client_id, password_hash FROM UNNEST($1, $2) AS a(client_id, password_hash)
+            SELECT email, password_hash FROM UNNEST($1, $2) AS a(email, password_hash)
             ON CONFLICT DO NOTHING;
     "#,
     )
-        .bind(&client_ids)
+        .bind(&emails)
         .bind(&passwords)
         .execute(&mut *tx)
         .await
@@ -167,12 +140,111 @@ pub async fn sync_users(
         r#"
         INSERT INTO user_access(client_id, email, role)
         SELECT client_id, email, 'writer' FROM
-        UNNEST($1, $2) AS a(client_id, email)"#,
+        UNNEST($1, $2) AS a(client_id, email)
+        ON CONFLICT (client_id, email) DO NOTHING"#,
     )
         .bind(&client_ids)
         .bind(&emails)
         .execute(&mut *tx)
         .await
-        .map_err(|e| AuthAppError::SqlError(e))
+        .map_err(AuthAppError::SqlError)?;
+    tx.commit().await.map_err(AuthAppError::SqlError)
+}
+
+pub async fn get_password_hash(
+    conn: &PgPool,
+    email: &str,
+) -> Result<Option<String>, AuthAppError> {
+    sqlx::query_scalar!(
+        "SELECT password_hash FROM auth_users WHERE email = $1",
+        email
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn set_password_hash(
+    conn: &PgPool,
+    email: &str,
+    password_hash: &str,
+) -> Result<(), AuthAppError> {
+    sqlx::query!(
+        "UPDATE auth_users SET password_hash = $2 WHERE email = $1",
+        email,
+        password_hash
+    )
+        .execute(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
         .map(|_| ())
 }
+
+/// Stores a new reset token unless one was requested after `requested_before`. Returns whether it was stored, which
+/// is also false for unknown emails.
+pub async fn start_password_reset(
+    conn: &PgPool,
+    email: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+    requested_before: DateTime<Utc>,
+) -> Result<bool, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE auth_users
+        SET password_reset_token = $2, password_reset_expires = $3, password_reset_requested_at = now()
+        WHERE email = $1 AND (password_reset_requested_at IS NULL OR password_reset_requested_at <= $4)
+        RETURNING email
+    "#,
+        email,
+        token_hash,
+        expires_at,
+        requested_before
+    )
+        .fetch_optional(conn)
+        .await
+        .map(|e| e.is_some())
+        .map_err(AuthAppError::SqlError)
+}
+
+pub async fn password_reset_valid(
+    conn: &PgPool,
+    email: &str,
+    token_hash: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query_as!(
+        crate::model::Exists,
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM auth_users WHERE email = $1 AND password_reset_token = $2 AND password_reset_expires > now()
+        ) AS exists
+    "#,
+        email,
+        token_hash
+    )
+        .fetch_one(conn)
+        .await
+        .map(|e| e.exists.unwrap_or(false))
+        .map_err(AuthAppError::SqlError)
+}
+
+/// Clears a valid reset token, returning whether there was one. Clearing it first means a token resets at most once.
+pub async fn take_password_reset(
+    conn: &PgPool,
+    email: &str,
+    token_hash: &str,
+) -> Result<bool, AuthAppError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE auth_users SET password_reset_token = NULL, password_reset_expires = NULL
+        WHERE email = $1 AND password_reset_token = $2 AND password_reset_expires > now()
+        RETURNING email
+    "#,
+        email,
+        token_hash
+    )
+        .fetch_optional(conn)
+        .await
+        .map(|e| e.is_some())
+        .map_err(AuthAppError::SqlError)
+}
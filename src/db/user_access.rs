@@ -1,5 +1,5 @@
 use crate::errors::AuthAppError;
-use crate::model::user::Role;
+use crate::model::user::{Role, UserAccess};
 use log::warn;
 use sqlx::PgPool;
 
@@ -25,3 +25,19 @@ pub async fn add_access(
     warn!("Managed to add access {:#?}", d);
     Ok(())
 }
+
+pub async fn get_access_for_email(
+    conn: &PgPool,
+    email: &str,
+) -> Result<Vec<UserAccess>, AuthAppError> {
+    sqlx::query_as!(
+        UserAccess,
+        r#"
+        SELECT client_id, role FROM user_access WHERE email = $1 ORDER BY client_id;
+    "#,
+        email
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(AuthAppError::SqlError)
+}
@@ -20,6 +20,8 @@ use paperclip::actix::api_v2_errors;
     description = "Request timeout",
     code = 409,
     description = "This id already exists",
+    code = 429,
+    description = "Too many failed logins, try again later",
     500,
     description = "Internal Server Error",
 )]
@@ -32,6 +34,27 @@ pub enum AuthAppError {
     InvalidCookieDuration,
     TokenExpired,
     RenderError,
+    NotAuthenticated,
+    InsufficientPermissions,
+    InvalidServiceTokenName,
+    SessionRevoked,
+    InvalidConfiguration(String),
+    UnknownProvider,
+    InvalidReturnTo,
+    InvalidPassword,
+    MailDeliveryFailed,
+    AccountLocked,
+    TwoFactorAlreadyEnabled,
+    InvalidPasskey,
+    #[from(skip)]
+    InvalidSaml(String),
+    /// The instance isn't set up in a way SAML logins can work with
+    #[from(skip)]
+    InvalidSamlSetup(String),
+    #[from(skip)]
+    IllegalStateTransition(String),
+    #[from(skip)]
+    TrialNotExtendable(String),
 }
 impl std::error::Error for AuthAppError {}
 
@@ -62,11 +85,35 @@ impl ResponseError for AuthAppError {
             AuthAppError::InvalidCookieDuration => StatusCode::BAD_REQUEST,
             AuthAppError::TokenExpired => StatusCode::UNAUTHORIZED,
             AuthAppError::RenderError => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthAppError::NotAuthenticated => StatusCode::UNAUTHORIZED,
+            AuthAppError::InsufficientPermissions => StatusCode::FORBIDDEN,
+            AuthAppError::InvalidServiceTokenName => StatusCode::BAD_REQUEST,
+            AuthAppError::SessionRevoked => StatusCode::UNAUTHORIZED,
+            AuthAppError::InvalidConfiguration(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthAppError::UnknownProvider => StatusCode::NOT_FOUND,
+            AuthAppError::InvalidReturnTo => StatusCode::BAD_REQUEST,
+            AuthAppError::InvalidPassword => StatusCode::BAD_REQUEST,
+            AuthAppError::MailDeliveryFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthAppError::AccountLocked => StatusCode::TOO_MANY_REQUESTS,
+            AuthAppError::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+            AuthAppError::InvalidPasskey => StatusCode::BAD_REQUEST,
+            AuthAppError::InvalidSaml(_) => StatusCode::BAD_REQUEST,
+            AuthAppError::InvalidSamlSetup(_) => StatusCode::BAD_REQUEST,
+            AuthAppError::IllegalStateTransition(_) => StatusCode::CONFLICT,
+            AuthAppError::TrialNotExtendable(_) => StatusCode::CONFLICT,
         }
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
         warn!("status code for error {}", self.status_code());
-        HttpResponse::build(self.status_code()).finish()
+        match self {
+            // Only admins manage instance lifecycles, trials and SAML, and they need to know why they couldn't
+            AuthAppError::IllegalStateTransition(reason)
+            | AuthAppError::TrialNotExtendable(reason)
+            | AuthAppError::InvalidSamlSetup(reason) => {
+                HttpResponse::build(self.status_code()).body(reason.clone())
+            }
+            _ => HttpResponse::build(self.status_code()).finish(),
+        }
     }
 }
@@ -2,15 +2,21 @@ pub mod auth;
 pub mod controllers;
 pub(crate) mod db;
 pub mod errors;
+pub mod mail;
 pub mod model;
 pub mod service;
 pub mod version;
+use crate::auth::login_state::LoginStateStoreKind;
+use crate::auth::token::TokenPurpose;
+use crate::errors::AuthAppError;
+use crate::mail::MailerKind;
 use clap::Parser;
-use oauth2::basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenResponse};
+use oauth2::basic::{BasicClient, BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenResponse};
 use oauth2::url::Url;
-use oauth2::{Client, EndpointMaybeSet, EndpointNotSet, EndpointSet, StandardRevocableToken};
+use oauth2::{AuthUrl, Client, ClientId, ClientSecret, EndpointMaybeSet, EndpointNotSet, EndpointSet, RedirectUrl, RevocationUrl, StandardRevocableToken, TokenUrl};
 use serde::Deserialize;
 use sqlx::{Pool, Postgres};
+use std::net::IpAddr;
 
 #[derive(Debug, Default, Deserialize, PartialEq, Parser, Clone)]
 #[clap(author, version, about, long_about = None)]
@@ -27,8 +33,16 @@ pub struct AppConfig {
     #[clap(short, long, env)]
     pub secret: String,
 
-    #[clap(long)]
-    pub shared_secret: String,
+    #[clap(long, env, default_value_t = String::from("primary"))]
+    pub secret_key_id: String,
+
+    /// Retired secrets still accepted for validation, given as <id>:<secret>
+    #[clap(long, env, value_delimiter = ',')]
+    pub previous_secrets: Vec<String>,
+
+    /// JSON file with a current key and previous keys, used instead of the secret arguments when set
+    #[clap(long, env)]
+    pub keyring_file: Option<String>,
 
     #[clap(long, env, default_value_t = String::from("eu-central-1"))]
     pub aws_region: String,
@@ -42,6 +56,11 @@ pub struct AppConfig {
     #[clap(short, long, env, default_value_t = String::from("app.unleash-hosted.com"))]
     pub base_url: String,
 
+    /// Extra hosts users may be sent back to after login, besides this app and instances under base_url.
+    /// A leading `*.` allows all subdomains
+    #[clap(long, env, value_delimiter = ',')]
+    pub return_to_hosts: Vec<String>,
+
     #[clap(long, env)]
     pub stripe_key: Option<String>,
 
@@ -57,19 +76,250 @@ pub struct AppConfig {
     #[clap(long, env)]
     pub google_client_secret: String,
 
+    /// Where users reach this app, used to build the OAuth redirect URL
+    #[clap(long, env, default_value_t = String::from("http://localhost:1500"))]
+    pub public_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://accounts.google.com/o/oauth2/v2/auth"))]
+    pub google_auth_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://www.googleapis.com/oauth2/v3/token"))]
+    pub google_token_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://oauth2.googleapis.com/revoke"))]
+    pub google_revocation_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://www.googleapis.com/oauth2/v1/userinfo"))]
+    pub google_userinfo_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://www.googleapis.com/auth/userinfo.email"))]
+    pub google_scope_url: String,
+
+    /// Enables GitHub login when set together with github_client_secret
+    #[clap(long, env)]
+    pub github_client_id: Option<String>,
+
+    #[clap(long, env)]
+    pub github_client_secret: Option<String>,
+
+    #[clap(long, env, default_value_t = String::from("https://github.com/login/oauth/authorize"))]
+    pub github_auth_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://github.com/login/oauth/access_token"))]
+    pub github_token_url: String,
+
+    #[clap(long, env, default_value_t = String::from("https://api.github.com"))]
+    pub github_api_url: String,
+
+    /// JSON file listing OpenID Connect providers, each served under /api/auth/<name>
+    #[clap(long, env)]
+    pub oidc_providers_file: Option<String>,
+
     #[clap(long, short, env, default_value_t = String::from("auth_app_rs_auth"))]
     pub cookie_name: String,
 
+    /// Lifetime of the session cookie and the access token in it
     #[clap(long, short = 'l', env, default_value_t = 3600)]
     pub cookie_life_time_secs: i64,
 
+    /// Lifetime of a refresh token, renewed every time it is exchanged for a new token pair
+    #[clap(long, env, default_value_t = 2_592_000)]
+    pub refresh_token_life_time_secs: i64,
+
     #[clap(long, env, default_value_t = String::from("localhost"))]
     pub cookie_domain: String,
+
+    /// Where login state is kept between the login redirect and the callback, use postgres with several replicas
+    #[clap(long, env, value_enum, default_value_t = LoginStateStoreKind::Memory)]
+    pub login_state_store: LoginStateStoreKind,
+
+    /// How long a user has to complete a login before its state is discarded
+    #[clap(long, env, default_value_t = 600)]
+    pub login_state_ttl_secs: i64,
+
+    /// How emails such as password reset links are delivered
+    #[clap(long, env, value_enum, default_value_t = MailerKind::Log)]
+    pub mailer: MailerKind,
+
+    /// Directory the file mailer writes emails to
+    #[clap(long, env)]
+    pub mailer_dir: Option<String>,
+
+    #[clap(long, env, default_value_t = String::from("https://api.sendinblue.com/v3"))]
+    pub sendinblue_url: String,
+
+    #[clap(long, env, default_value_t = String::from("noreply@getunleash.io"))]
+    pub mail_from: String,
+
+    /// How long a password reset link stays valid
+    #[clap(long, env, default_value_t = 3600)]
+    pub password_reset_ttl_secs: i64,
+
+    /// Minimum time between password reset emails to the same address
+    #[clap(long, env, default_value_t = 300)]
+    pub password_reset_interval_secs: i64,
+
+    /// How long a magic login link stays valid
+    #[clap(long, env, default_value_t = 900)]
+    pub magic_link_ttl_secs: i64,
+
+    /// Minimum time between magic login links sent to the same address
+    #[clap(long, env, default_value_t = 60)]
+    pub magic_link_interval_secs: i64,
+
+    /// Shown next to the account in authenticator apps and passkey managers
+    #[clap(long, env, default_value_t = String::from("Unleash"))]
+    pub totp_issuer: String,
+
+    /// How long a user has to enter their second factor after the first one
+    #[clap(long, env, default_value_t = 300)]
+    pub two_factor_challenge_ttl_secs: i64,
+
+    /// Domain passkeys are bound to, defaults to the host of public_url. Set it to a parent domain to share passkeys
+    /// with other apps under it
+    #[clap(long, env)]
+    pub webauthn_rp_id: Option<String>,
+
+    /// Failed password logins an account or IP gets before every further attempt has to wait twice as long
+    #[clap(long, env, default_value_t = 3)]
+    pub login_delay_after_attempts: i32,
+
+    /// Failed password logins after which an account is locked out
+    #[clap(long, env, default_value_t = 10)]
+    pub login_max_attempts: i32,
+
+    /// Failed password logins after which an IP is locked out, higher since users can share an IP
+    #[clap(long, env, default_value_t = 100)]
+    pub login_max_attempts_per_ip: i32,
+
+    /// How long a lockout lasts, and how long failed logins are remembered
+    #[clap(long, env, default_value_t = 900)]
+    pub login_lockout_secs: i64,
+
+    /// Load balancers in front of this app. Clients are told apart by X-Forwarded-For only on requests from these,
+    /// everyone else could put any address there
+    #[clap(long, env, value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// How long a trial lasts from when it starts
+    #[clap(long, env, default_value_t = 14)]
+    pub trial_length_days: i32,
+
+    /// How much each extension adds to a trial
+    #[clap(long, env, default_value_t = 5)]
+    pub trial_extension_days: i32,
+
+    /// How many times a trial can be extended
+    #[clap(long, env, default_value_t = 2)]
+    pub trial_max_extensions: i32,
+
+    /// Users allowed to manage all instances
+    #[clap(long, env, value_delimiter = ',')]
+    pub admin_emails: Vec<String>,
+
+    #[clap(long, env, default_value_t = String::from("auth-app"))]
+    pub token_issuer: String,
+
+    #[clap(long, env, default_value_t = String::from("unleash"))]
+    pub token_audience: String,
+
+    #[clap(long, env, default_value_t = 30)]
+    pub token_leeway_secs: i64,
+
+    /// Issue v4.local tokens only we can read, or v4.public tokens anyone can verify with our public keys
+    #[clap(long, env, value_enum, default_value_t = TokenPurpose::Local)]
+    pub token_purpose: TokenPurpose,
+
+    /// Hex encoded Ed25519 seed used to sign public tokens
+    #[clap(long, env)]
+    pub signing_key: Option<String>,
+
+    #[clap(long, env, default_value_t = String::from("signing"))]
+    pub signing_key_id: String,
+
+    /// Public keys of retired signing keys, still published until their tokens expire, given as <id>:<hex key>
+    #[clap(long, env, value_delimiter = ',')]
+    pub previous_public_keys: Vec<String>,
 }
 
 pub struct AppState {
     pub oauth: Client<BasicErrorResponse, BasicTokenResponse, BasicTokenIntrospectionResponse, StandardRevocableToken, BasicRevocationErrorResponse, EndpointMaybeSet, EndpointNotSet, EndpointNotSet, EndpointSet, EndpointMaybeSet>,
     pub scope_url: Url,
+    pub userinfo_url: Url,
+    pub github: Option<GithubOAuth>,
+}
+
+/// GitHub isn't an OpenID Connect provider, so it gets its own client and we ask its API for the user's emails.
+pub struct GithubOAuth {
+    pub oauth: BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>,
+    pub emails_url: Url,
+}
+
+pub const GOOGLE_CALLBACK_PATH: &str = "/api/auth/google/callback";
+pub const GITHUB_CALLBACK_PATH: &str = "/api/auth/github/callback";
+pub const MAGIC_LINK_CALLBACK_PATH: &str = "/api/auth/magic-link/callback";
+
+impl AppState {
+    /// Builds the OAuth client from config, failing on any URL that isn't an absolute http(s) URL.
+    pub fn from_config(config: &AppConfig) -> Result<AppState, AuthAppError> {
+        let redirect_url = parse_url("public_url", &config.public_url)?
+            .join(GOOGLE_CALLBACK_PATH)
+            .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+        let auth_url = parse_url("google_auth_url", &config.google_auth_url)?;
+        let token_url = parse_url("google_token_url", &config.google_token_url)?;
+        let revocation_url = parse_url("google_revocation_url", &config.google_revocation_url)?;
+        let oauth = BasicClient::new(ClientId::new(config.google_client_id.clone()))
+            .set_client_secret(ClientSecret::new(config.google_client_secret.clone()))
+            .set_auth_uri_option(Some(AuthUrl::from_url(auth_url)))
+            .set_token_uri_option(Some(TokenUrl::from_url(token_url)))
+            .set_redirect_uri(RedirectUrl::from_url(redirect_url))
+            .set_revocation_url(RevocationUrl::from_url(revocation_url));
+        Ok(AppState {
+            oauth,
+            scope_url: parse_url("google_scope_url", &config.google_scope_url)?,
+            userinfo_url: parse_url("google_userinfo_url", &config.google_userinfo_url)?,
+            github: GithubOAuth::from_config(config)?,
+        })
+    }
+}
+
+impl GithubOAuth {
+    /// GitHub login is optional, so this is `None` unless both client id and secret are configured.
+    pub fn from_config(config: &AppConfig) -> Result<Option<GithubOAuth>, AuthAppError> {
+        let (Some(client_id), Some(client_secret)) =
+            (&config.github_client_id, &config.github_client_secret)
+        else {
+            return Ok(None);
+        };
+        let redirect_url = parse_url("public_url", &config.public_url)?
+            .join(GITHUB_CALLBACK_PATH)
+            .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+        // Joined relative to the base, so a path prefix like GitHub Enterprise's /api/v3 is kept
+        let mut api_url = parse_url("github_api_url", &config.github_api_url)?;
+        if !api_url.path().ends_with('/') {
+            api_url.set_path(&format!("{}/", api_url.path()));
+        }
+        let emails_url = api_url
+            .join("user/emails")
+            .map_err(|e| AuthAppError::InvalidConfiguration(format!("github_api_url: {e}")))?;
+        let oauth = BasicClient::new(ClientId::new(client_id.clone()))
+            .set_client_secret(ClientSecret::new(client_secret.clone()))
+            .set_auth_uri(AuthUrl::from_url(parse_url("github_auth_url", &config.github_auth_url)?))
+            .set_token_uri(TokenUrl::from_url(parse_url("github_token_url", &config.github_token_url)?))
+            .set_redirect_uri(RedirectUrl::from_url(redirect_url));
+        Ok(Some(GithubOAuth { oauth, emails_url }))
+    }
+}
+
+fn parse_url(name: &str, value: &str) -> Result<Url, AuthAppError> {
+    let url = Url::parse(value)
+        .map_err(|e| AuthAppError::InvalidConfiguration(format!("{name}: {e}")))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(AuthAppError::InvalidConfiguration(format!(
+            "{name}: unsupported scheme {scheme}"
+        ))),
+    }
 }
 
 pub async fn migrate_db(pool: Pool<Postgres>) {
@@ -78,3 +328,80 @@ pub async fn migrate_db(pool: Pool<Postgres>) {
         .await
         .expect("Failed to migrate");
 }
+
+#[cfg(test)]
+fn oauth_config() -> AppConfig {
+    AppConfig {
+        public_url: "https://auth.example.com".to_string(),
+        google_auth_url: "http://localhost:8080/auth".to_string(),
+        google_token_url: "http://localhost:8080/token".to_string(),
+        google_revocation_url: "http://localhost:8080/revoke".to_string(),
+        google_userinfo_url: "http://localhost:8080/userinfo".to_string(),
+        google_scope_url: "https://www.googleapis.com/auth/userinfo.email".to_string(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn oauth_urls_come_from_config() {
+    let state = AppState::from_config(&oauth_config()).unwrap();
+    assert_eq!(
+        state.oauth.redirect_uri().unwrap().as_str(),
+        "https://auth.example.com/api/auth/google/callback"
+    );
+    assert_eq!(state.oauth.token_uri().unwrap().as_str(), "http://localhost:8080/token");
+    assert_eq!(state.userinfo_url.as_str(), "http://localhost:8080/userinfo");
+}
+
+#[cfg(test)]
+#[test]
+fn invalid_oauth_urls_are_rejected() {
+    let relative = AppConfig {
+        google_token_url: "/token".to_string(),
+        ..oauth_config()
+    };
+    assert!(AppState::from_config(&relative).is_err());
+    let not_http = AppConfig {
+        public_url: "ftp://auth.example.com".to_string(),
+        ..oauth_config()
+    };
+    assert!(AppState::from_config(&not_http).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn github_is_only_enabled_with_client_credentials() {
+    assert!(AppState::from_config(&oauth_config()).unwrap().github.is_none());
+    let config = AppConfig {
+        github_client_id: Some("github-client".to_string()),
+        github_client_secret: Some("github-secret".to_string()),
+        github_auth_url: "http://localhost:8080/login/oauth/authorize".to_string(),
+        github_token_url: "http://localhost:8080/login/oauth/access_token".to_string(),
+        github_api_url: "http://localhost:8080".to_string(),
+        ..oauth_config()
+    };
+    let github = AppState::from_config(&config).unwrap().github.unwrap();
+    assert_eq!(github.emails_url.as_str(), "http://localhost:8080/user/emails");
+    assert_eq!(
+        github.oauth.redirect_uri().unwrap().as_str(),
+        "https://auth.example.com/api/auth/github/callback"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn github_api_path_prefixes_are_kept() {
+    for api_url in ["https://ghe.example/api/v3", "https://ghe.example/api/v3/"] {
+        let config = AppConfig {
+            github_client_id: Some("github-client".to_string()),
+            github_client_secret: Some("github-secret".to_string()),
+            github_auth_url: "https://ghe.example/login/oauth/authorize".to_string(),
+            github_token_url: "https://ghe.example/login/oauth/access_token".to_string(),
+            github_api_url: api_url.to_string(),
+            ..oauth_config()
+        };
+        let github = AppState::from_config(&config).unwrap().github.unwrap();
+        assert_eq!(github.emails_url.as_str(), "https://ghe.example/api/v3/user/emails");
+    }
+}
@@ -0,0 +1,159 @@
+use crate::AppConfig;
+use crate::errors::AuthAppError;
+use async_trait::async_trait;
+use chrono::Utc;
+use clap::ValueEnum;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+/// Delivers the emails we send users, like password reset links.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AuthAppError>;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailerKind {
+    /// Only logs emails, for local development
+    #[default]
+    Log,
+    /// Writes each email as a JSON file into mailer_dir
+    File,
+    Sendinblue,
+}
+
+pub fn mailer_for(config: &AppConfig) -> Result<Box<dyn Mailer>, AuthAppError> {
+    match config.mailer {
+        MailerKind::Log => Ok(Box::new(LogMailer)),
+        MailerKind::File => {
+            let dir = config.mailer_dir.clone().ok_or_else(|| {
+                AuthAppError::InvalidConfiguration(
+                    "mailer_dir is required for the file mailer".into(),
+                )
+            })?;
+            Ok(Box::new(FileMailer::new(dir)))
+        }
+        MailerKind::Sendinblue => {
+            let api_key = config.sendinblue_key.clone().ok_or_else(|| {
+                AuthAppError::InvalidConfiguration(
+                    "sendinblue_key is required for the sendinblue mailer".into(),
+                )
+            })?;
+            Ok(Box::new(SendinblueMailer {
+                http_client: oauth2::reqwest::Client::new(),
+                url: format!("{}/smtp/email", config.sendinblue_url.trim_end_matches('/')),
+                api_key,
+                from: config.mail_from.clone(),
+            }))
+        }
+    }
+}
+
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthAppError> {
+        info!(
+            "Email to {} with subject {:?}:\n{}",
+            email.to, email.subject, email.text
+        );
+        Ok(())
+    }
+}
+
+/// Keeps emails in memory, so tests can read what would have been sent. Clones share the captured emails.
+#[derive(Clone, Default)]
+pub struct CaptureMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl CaptureMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent
+            .lock()
+            .expect("Mail capture lock poisoned")
+            .clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for CaptureMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthAppError> {
+        self.sent
+            .lock()
+            .expect("Mail capture lock poisoned")
+            .push(email);
+        Ok(())
+    }
+}
+
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> FileMailer {
+        FileMailer { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthAppError> {
+        let name = format!(
+            "{}-{}.json",
+            Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            crate::auth::secret::random_secret(8)
+        );
+        let json = serde_json::to_vec_pretty(&email).expect("Emails serialize");
+        fs::write(self.dir.join(name), json).map_err(|e| {
+            warn!("Couldn't write email to {}: {e}", self.dir.display());
+            AuthAppError::MailDeliveryFailed
+        })
+    }
+}
+
+pub struct SendinblueMailer {
+    http_client: oauth2::reqwest::Client,
+    url: String,
+    api_key: String,
+    from: String,
+}
+
+#[async_trait]
+impl Mailer for SendinblueMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthAppError> {
+        let body = serde_json::json!({
+            "sender": { "email": self.from },
+            "to": [{ "email": email.to }],
+            "subject": email.subject,
+            "textContent": email.text,
+        });
+        self.http_client
+            .post(&self.url)
+            .header("api-key", &self.api_key)
+            .header("content-type", "application/json")
+            .header("accept", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| {
+                warn!("Sendinblue refused email: {e}");
+                AuthAppError::MailDeliveryFailed
+            })
+    }
+}
@@ -1,11 +1,7 @@
 use actix_web::{middleware, App, HttpServer};
 use actix_web_prom::PrometheusMetricsBuilder;
 use clap::Parser;
-use dashmap::DashMap;
 use middleware::NormalizePath;
-use oauth2::basic::{BasicClient, BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenResponse};
-use oauth2::url::Url;
-use oauth2::{AuthUrl, Client, ClientId, ClientSecret, EndpointMaybeSet, EndpointNotSet, EndpointSet, RedirectUrl, RevocationUrl, StandardRevocableToken, TokenUrl};
 use paperclip::actix::{
     web::{self},
     OpenApiExt,
@@ -13,8 +9,11 @@ use paperclip::actix::{
 use paperclip::v2::models::{DefaultApiRaw, Info, Tag};
 use sqlx::postgres::PgPoolOptions;
 use std::collections::HashMap;
-use std::sync::Arc;
 
+use auth_app_rs::auth::keyring::Keyring;
+use auth_app_rs::auth::login_state;
+use auth_app_rs::auth::oidc::OidcProviders;
+use auth_app_rs::mail;
 use auth_app_rs::version::get_version_info;
 use auth_app_rs::{controllers, AppConfig, AppState};
 
@@ -57,48 +56,32 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Couldn't connect to database");
 
-    let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string());
-    let token_url = TokenUrl::new("https://www.googleapis.com/oauth2/v3/token".to_string());
-    let google_client_id = ClientId::new(init_config.google_client_id);
-    let google_client_secret = ClientSecret::new(init_config.google_client_secret);
-    let oauth_client: Client<BasicErrorResponse, BasicTokenResponse, BasicTokenIntrospectionResponse, StandardRevocableToken, BasicRevocationErrorResponse, EndpointMaybeSet, EndpointNotSet, EndpointNotSet, EndpointSet, EndpointMaybeSet> = BasicClient::new(
-        google_client_id.clone())
-        .set_client_secret(google_client_secret)
-        .set_auth_uri_option(auth_url.ok())
-        .set_token_uri_option(token_url.ok())
-        .set_redirect_uri(
-            RedirectUrl::new("http://localhost:1500/api/auth/google/callback".to_string())
-                .expect("Your redirect URL is bullshit"),
-        ).set_revocation_url(
-        RevocationUrl::new("https://oauth2.googleapis.com/revoke".to_string())
-            .expect("Invalid revocation endpoint URL"),
+    let keyring = web::Data::new(Keyring::from_config(&init_config).expect("Couldn't load token signing keys"));
+
+    let app_state = web::Data::new(AppState::from_config(&init_config).expect("Invalid OAuth configuration"));
+    let oidc_http_client = oauth2::reqwest::Client::new();
+    let oidc_providers = web::Data::new(
+        OidcProviders::from_config(&oidc_http_client, &init_config)
+            .await
+            .expect("Couldn't set up OIDC providers"),
     );
-    let code_verifiers: Arc<DashMap<String, String>> = Arc::new(DashMap::default());
+    let login_states = web::Data::new(login_state::store_for(
+        init_config.login_state_store,
+        chrono::Duration::seconds(init_config.login_state_ttl_secs),
+        pool.clone(),
+    ));
+    let mailer = web::Data::new(mail::mailer_for(&init_config).expect("Invalid mailer configuration"));
 
     HttpServer::new(move || {
-        /*        let shared_config = app_config.clone();
-        let shared_s = shared_config.shared_secret;
-        let shared_secret_guard =
-            guard::fn_guard(move |ctx| match ctx.head().headers().get("Authorization") {
-                Some(hv) => {
-                    debug!("Header {:#?}", hv);
-                    hv.to_str()
-                        .map(|f| f.to_string().eq(&shared_s))
-                        .unwrap_or(false)
-                }
-                None => false,
-            });*/
         App::new()
             .wrap(NormalizePath::trim())
-            .app_data(web::Data::new(AppState {
-                oauth: oauth_client.clone(),
-                scope_url: Url::options()
-                    .parse("https://www.googleapis.com/auth/userinfo.email")
-                    .expect("Your constants are constantly wrong"),
-            }))
+            .app_data(app_state.clone())
             .app_data(web::Data::new(app_config.clone()))
             .app_data(web::Data::new(pool.clone()))
-            .app_data(web::Data::from(code_verifiers.clone()))
+            .app_data(login_states.clone())
+            .app_data(mailer.clone())
+            .app_data(oidc_providers.clone())
+            .app_data(keyring.clone())
             .wrap_api_with_spec(spec.clone())
             .with_json_spec_at("/api/spec/v2")
             .with_json_spec_v3_at("/api/spec/v3")
@@ -108,7 +91,11 @@ async fn main() -> std::io::Result<()> {
                 web::scope("/internal-backstage")
                     .configure(controllers::internalbackstage::configure_internal_backstage),
             )
+            .service(
+                web::scope("/.well-known").configure(controllers::wellknown::configure_well_known),
+            )
             .service(web::scope("/api").configure(controllers::api::configure_api))
+            .configure(controllers::pages::configure_pages)
             .build()
     })
         .bind(("0.0.0.0", port_config.port))?
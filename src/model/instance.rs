@@ -11,8 +11,11 @@ pub struct InstanceStatus {
     pub trial_start: Option<DateTime<Utc>>,
     pub trial_extended: i32,
     pub billing_center: String,
-    pub instance_state: String,
+    pub instance_state: InstanceState,
     pub region: String,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+    pub churned_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Apiv2Schema)]
@@ -24,6 +27,12 @@ pub struct CreateInstanceBody {
     pub display_name: Option<String>,
     pub email_domain: Option<String>,
     pub stripe_customer_id: Option<String>,
+    /// Only let Google Workspace accounts of `email_domain` join automatically, not any address ending in it
+    #[serde(default)]
+    pub require_hosted_domain: bool,
+    /// Make everyone with access to the instance use a second factor when logging in with a password or email link
+    #[serde(default)]
+    pub require_two_factor: bool,
 }
 
 #[derive(Serialize, Deserialize, Apiv2Schema, FromRow, Debug)]
@@ -34,7 +43,7 @@ pub struct InstanceRow {
     pub updated_at: Option<DateTime<Utc>>,
     pub display_name: Option<String>,
     pub email_domain: Option<String>,
-    pub instance_state: String,
+    pub instance_state: InstanceState,
     pub plan: String,
     pub region: String,
     pub seats: i32,
@@ -42,11 +51,55 @@ pub struct InstanceRow {
     pub trial_expiry: Option<DateTime<Utc>>,
     pub trial_extended: i32,
     pub trial_start: Option<DateTime<Utc>>,
+    pub require_hosted_domain: bool,
+    pub require_two_factor: bool,
+    /// When the instance last became Active
+    pub activated_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+    pub churned_at: Option<DateTime<Utc>>,
+}
+
+/// An instance a user has access to, as stored.
+#[derive(FromRow, Debug)]
+pub struct UserInstanceRow {
+    pub client_id: String,
+    pub display_name: Option<String>,
+    pub region: String,
+    pub role: String,
+}
+
+/// An instance a user can go to, as offered by the instance chooser.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug, PartialEq)]
+pub struct AccessibleInstance {
+    pub client_id: String,
+    pub display_name: String,
+    pub url: String,
+    pub role: String,
+}
+
+/// Returned once on creation, only the hash of `key` is stored.
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct CreatedInstanceKey {
+    pub client_id: String,
+    pub key: String,
 }
 
 #[derive(
-    Display, Debug, Serialize, Deserialize, IntoStaticStr, EnumIter, EnumString, Apiv2Schema,
+    Display,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    IntoStaticStr,
+    EnumIter,
+    EnumString,
+    Apiv2Schema,
+    sqlx::Type,
 )]
+/// Stored as the variant's name in the `instance_state` TEXT column.
+#[sqlx(type_name = "text", rename_all = "PascalCase")]
 pub enum InstanceState {
     Unassigned,
     Trial,
@@ -54,3 +107,57 @@ pub enum InstanceState {
     Expired,
     Churned,
 }
+
+/// The ways an instance moves between states.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumIter, Apiv2Schema)]
+pub enum InstanceTransition {
+    StartTrial,
+    /// The customer pays, whether after a trial or without one
+    Activate,
+    /// The trial ran out without the customer paying
+    Expire,
+    Churn,
+    /// A churned customer comes back
+    Reactivate,
+}
+
+impl InstanceState {
+    /// Where `transition` leads from this state, `None` if it isn't allowed here.
+    pub fn next(self, transition: InstanceTransition) -> Option<InstanceState> {
+        use InstanceState::*;
+        use InstanceTransition::*;
+        match (self, transition) {
+            (Unassigned, StartTrial) => Some(Trial),
+            (Unassigned | Trial | Expired, Activate) => Some(Active),
+            (Trial, Expire) => Some(Expired),
+            (Active | Expired, Churn) => Some(Churned),
+            (Churned, Reactivate) => Some(Active),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn only_listed_transitions_are_allowed() {
+    use strum::IntoEnumIterator;
+    let allowed: Vec<_> = InstanceState::iter()
+        .flat_map(|from| {
+            InstanceTransition::iter()
+                .filter_map(move |t| from.next(t).map(|to| format!("{from} -{t}-> {to}")))
+        })
+        .collect();
+    assert_eq!(
+        allowed,
+        [
+            "Unassigned -StartTrial-> Trial",
+            "Unassigned -Activate-> Active",
+            "Trial -Activate-> Active",
+            "Trial -Expire-> Expired",
+            "Active -Churn-> Churned",
+            "Expired -Activate-> Active",
+            "Expired -Churn-> Churned",
+            "Churned -Reactivate-> Active",
+        ]
+    );
+}
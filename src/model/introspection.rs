@@ -0,0 +1,83 @@
+use crate::auth::token::AuthenticatedTokenUser;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+/// RFC 7662 introspection request, sent form encoded by the instance.
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+}
+
+/// RFC 7662 introspection response. Inactive tokens only carry `active: false`.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug, Default, PartialEq)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_access: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> IntrospectionResponse {
+        IntrospectionResponse::default()
+    }
+
+    /// Describes `user` as seen from the instance `client_id`.
+    pub fn for_instance(user: &AuthenticatedTokenUser, client_id: &str) -> IntrospectionResponse {
+        let role = user.role_for(client_id).map(str::to_string);
+        IntrospectionResponse {
+            active: true,
+            email: Some(user.email.clone()),
+            client_id: Some(client_id.to_string()),
+            has_access: Some(role.is_some()),
+            role,
+            exp: Some(user.expires_at.timestamp()),
+            jti: Some(user.token_id.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn responses_only_carry_the_role_for_the_asking_instance() {
+    use crate::model::user::UserAccess;
+    use chrono::{TimeZone, Utc};
+    let user = AuthenticatedTokenUser {
+        email: "user@getunleash.io".to_string(),
+        client_ids: vec!["demo".to_string(), "other".to_string()],
+        access: vec![
+            UserAccess {
+                client_id: "demo".to_string(),
+                role: "admin".to_string(),
+            },
+            UserAccess {
+                client_id: "other".to_string(),
+                role: "writer".to_string(),
+            },
+        ],
+        token_id: "jti".to_string(),
+        expires_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+    };
+    let demo = IntrospectionResponse::for_instance(&user, "demo");
+    assert_eq!(demo.role.as_deref(), Some("admin"));
+    assert_eq!(demo.has_access, Some(true));
+    assert_eq!(demo.exp, Some(1_700_000_000));
+    let unknown = IntrospectionResponse::for_instance(&user, "unknown");
+    assert!(unknown.active);
+    assert_eq!(unknown.has_access, Some(false));
+    assert_eq!(unknown.role, None);
+    assert_eq!(
+        serde_json::to_string(&IntrospectionResponse::inactive()).unwrap(),
+        r#"{"active":false}"#
+    );
+}
@@ -5,6 +5,13 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 pub mod health;
 pub mod instance;
+pub mod introspection;
+pub mod passkey;
+pub mod public_key;
+pub mod saml;
+pub mod service_token;
+pub mod session;
+pub mod two_factor;
 pub mod user;
 pub mod version_info;
 
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// A passkey registered to the logged in user.
+#[derive(Serialize, Deserialize, Apiv2Schema, FromRow, Debug)]
+pub struct Passkey {
+    /// The credential id, base64url encoded
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+pub struct PasskeyCredentialRow {
+    pub email: String,
+    pub cose_key: String,
+    pub sign_count: i64,
+    pub user_handle: Option<String>,
+}
+
+pub struct WebAuthnChallengeRow {
+    pub return_to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    /// Base64url encoded user handle, which doesn't reveal the email
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    /// COSE algorithm identifier
+    pub alg: i64,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    /// Base64url encoded credential id
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// Options for `navigator.credentials.create()`, in the JSON form `PublicKeyCredential.parseCreationOptionsFromJSON`
+/// takes.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    /// Milliseconds
+    pub timeout: i64,
+    pub attestation: String,
+    pub authenticator_selection: AuthenticatorSelection,
+    /// The user's existing passkeys, so an authenticator isn't registered twice
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+/// Options for `navigator.credentials.get()`, in the JSON form `PublicKeyCredential.parseRequestOptionsFromJSON`
+/// takes. No credentials are listed, the user picks one of the passkeys their authenticator has for us.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    /// Milliseconds
+    pub timeout: i64,
+    pub user_verification: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// The `toJSON()` of the credential `navigator.credentials.create()` returned. Fields we don't use are ignored.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct NewPasskeyBody {
+    /// Helps the user tell their passkeys apart, e.g. the device it is on
+    pub name: Option<String>,
+    pub credential: RegistrationResponse,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// The `toJSON()` of the credential `navigator.credentials.get()` returned. Fields we don't use are ignored.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct AuthenticationResponse {
+    pub id: String,
+    pub response: AssertionResponse,
+}
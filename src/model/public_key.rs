@@ -0,0 +1,28 @@
+use crate::auth::keyring::PublicKey;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct PublishedPublicKey {
+    pub kid: String,
+    pub version: String,
+    pub purpose: String,
+    /// The key as a PASERK, `k4.public.<base64url>`
+    pub paserk: String,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct PublicKeySet {
+    pub keys: Vec<PublishedPublicKey>,
+}
+
+impl From<&PublicKey> for PublishedPublicKey {
+    fn from(key: &PublicKey) -> Self {
+        PublishedPublicKey {
+            kid: key.id.clone(),
+            version: "v4".to_string(),
+            purpose: "public".to_string(),
+            paserk: key.paserk(),
+        }
+    }
+}
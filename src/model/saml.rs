@@ -0,0 +1,56 @@
+use crate::model::user::Role;
+use chrono::{DateTime, Utc};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use std::collections::HashMap;
+
+/// Sets up SAML login for an instance.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct SamlConfigBody {
+    /// The metadata XML of the customer's IdP
+    pub idp_metadata: String,
+    /// Attribute holding the user's email, the assertion's NameID when unset
+    pub email_attribute: Option<String>,
+    /// Attribute whose values are looked up in `role_mapping`, e.g. the user's groups
+    pub role_attribute: Option<String>,
+    /// Role given for a value of the role attribute, the first value that is mapped wins
+    #[serde(default)]
+    pub role_mapping: HashMap<String, Role>,
+    /// Role of users none of whose values are mapped. Without it, those users are refused
+    pub default_role: Option<Role>,
+}
+
+/// An instance's SAML setup, and what to register us as at the IdP.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct SamlConfig {
+    pub client_id: String,
+    pub idp_entity_id: String,
+    pub idp_sso_url: String,
+    pub email_attribute: Option<String>,
+    pub role_attribute: Option<String>,
+    pub role_mapping: HashMap<String, Role>,
+    pub default_role: Option<Role>,
+    /// Our entity id for this instance, which is also where our metadata is served
+    pub sp_entity_id: String,
+    /// Where the IdP posts its responses to
+    pub acs_url: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct SamlConfigRow {
+    pub client_id: String,
+    pub idp_entity_id: String,
+    pub idp_sso_url: String,
+    /// Base64 encoded DER
+    pub idp_certificate: String,
+    pub email_attribute: Option<String>,
+    pub role_attribute: Option<String>,
+    pub role_mapping: Json<HashMap<String, Role>>,
+    pub default_role: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct SamlRequestRow {
+    pub return_to: Option<String>,
+}
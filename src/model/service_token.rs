@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use strum::{Display, EnumIter, EnumString, IntoStaticStr};
+
+#[derive(
+    Display, Debug, Clone, PartialEq, Serialize, Deserialize, IntoStaticStr, EnumIter, EnumString, Apiv2Schema,
+)]
+pub enum ServiceScope {
+    #[strum(serialize = "instances:read")]
+    #[serde(rename = "instances:read")]
+    InstancesRead,
+    #[strum(serialize = "instances:write")]
+    #[serde(rename = "instances:write")]
+    InstancesWrite,
+    #[strum(serialize = "users:write")]
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    #[strum(serialize = "users:sync")]
+    #[serde(rename = "users:sync")]
+    UsersSync,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, FromRow, Debug)]
+pub struct ServiceTokenRow {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct CreateServiceTokenBody {
+    pub name: String,
+    pub scopes: Vec<ServiceScope>,
+}
+
+/// Returned once on creation, the secret part of `token` is only stored hashed.
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct CreatedServiceToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub token: String,
+}
@@ -0,0 +1,34 @@
+use crate::service::session::SessionTokens;
+use chrono::{DateTime, Utc};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct RevokedSessions {
+    pub email: String,
+    pub revoked: usize,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct RefreshRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+impl From<SessionTokens> for TokenPair {
+    fn from(tokens: SessionTokens) -> Self {
+        TokenPair {
+            access_token: tokens.access.token,
+            expires_at: tokens.access.expires_at,
+            refresh_token: tokens.refresh_token,
+            refresh_expires_at: tokens.refresh_expires_at,
+        }
+    }
+}
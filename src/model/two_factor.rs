@@ -0,0 +1,34 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+/// A new TOTP secret to add to an authenticator app. It only protects logins once confirmed with a code from it.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct TotpEnrolment {
+    /// Base32, for typing into the app
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Apiv2Schema)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Single use codes for logging in without the authenticator app. Only shown once, we keep their hashes.
+#[derive(Serialize, Deserialize, Apiv2Schema, Debug)]
+pub struct RecoveryCodes {
+    pub codes: Vec<String>,
+}
+
+pub struct TotpCredentialRow {
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_used_step: Option<i64>,
+}
+
+pub struct TwoFactorChallengeRow {
+    pub email: String,
+    pub return_to: Option<String>,
+    /// Hex encoded TOTP secret offered to a user without an authenticator, kept until they confirm it
+    pub enrolment_secret: Option<String>,
+}
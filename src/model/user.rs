@@ -19,6 +19,12 @@ pub struct MinimalAuthUser {
     pub name: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, FromRow, Apiv2Schema, Clone, Debug, PartialEq)]
+pub struct UserAccess {
+    pub client_id: String,
+    pub role: String,
+}
+
 #[derive(Serialize, Deserialize, Apiv2Schema)]
 pub struct DeleteUserRequest {
     pub client_id: String,
@@ -0,0 +1,84 @@
+use crate::AppConfig;
+use crate::db;
+use crate::errors::AuthAppError;
+use crate::model::instance::{
+    AccessibleInstance, InstanceState, InstanceStatus, InstanceTransition,
+};
+use sqlx::{Pool, Postgres};
+
+/// Hosted instances are served per region under `base_url`, e.g. https://eu.app.unleash-hosted.com/demo
+pub fn instance_url(config: &AppConfig, region: &str, client_id: &str) -> String {
+    format!("https://{region}.{}/{client_id}", config.base_url)
+}
+
+pub async fn accessible_instances(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    email: &str,
+) -> Result<Vec<AccessibleInstance>, AuthAppError> {
+    let rows = db::instance::list_for_email(conn, email).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| AccessibleInstance {
+            url: instance_url(config, &row.region, &row.client_id),
+            display_name: row.display_name.unwrap_or_else(|| row.client_id.clone()),
+            client_id: row.client_id,
+            role: row.role,
+        })
+        .collect())
+}
+
+/// Applies `transition` to the instance if its current state allows it.
+pub async fn transition(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+    transition: InstanceTransition,
+) -> Result<InstanceStatus, AuthAppError> {
+    let instance = db::instance::get(conn, client_id).await?;
+    let from = instance.instance_state;
+    let to = from.next(transition).ok_or_else(|| {
+        AuthAppError::IllegalStateTransition(format!(
+            "{transition} is not allowed for instance {client_id} in state {from}"
+        ))
+    })?;
+    db::instance_status::transition(conn, client_id, from, to, config.trial_length_days)
+        .await?
+        .ok_or_else(|| {
+            AuthAppError::IllegalStateTransition(format!(
+                "Instance {client_id} left state {from} before {transition} could be applied"
+            ))
+        })
+}
+
+/// Pushes back the end of the instance's trial by `trial_extension_days`, at most `trial_max_extensions` times.
+pub async fn extend_trial(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+) -> Result<InstanceStatus, AuthAppError> {
+    let extended = db::instance_status::extend_trial(
+        conn,
+        client_id,
+        config.trial_extension_days,
+        config.trial_max_extensions,
+    )
+    .await?;
+    if let Some(status) = extended {
+        return Ok(status);
+    }
+    let instance = db::instance::get(conn, client_id).await?;
+    Err(AuthAppError::TrialNotExtendable(
+        if instance.instance_state != InstanceState::Trial {
+            format!(
+                "Instance {client_id} is in state {}, only trials can be extended",
+                instance.instance_state
+            )
+        } else {
+            format!(
+                "The trial of instance {client_id} was already extended {} times",
+                instance.trial_extended
+            )
+        },
+    ))
+}
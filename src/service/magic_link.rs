@@ -0,0 +1,76 @@
+use crate::auth::secret;
+use crate::controllers::pages::MAGIC_LINK_PATH;
+use crate::db::magic_link::MagicLinkRow;
+use crate::errors::AuthAppError;
+use crate::mail::{Email, Mailer};
+use crate::{AppConfig, db, service};
+use chrono::{Duration, Utc};
+use log::{info, warn};
+use oauth2::url::Url;
+use sqlx::{Pool, Postgres};
+
+/// Emails a one-time login link. Links are only sent to emails that could sign in, and at most once per
+/// `magic_link_interval_secs`, but the caller can't tell whether one was sent.
+pub async fn request(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    mailer: &dyn Mailer,
+    email: &str,
+    return_to: Option<String>,
+) -> Result<(), AuthAppError> {
+    let now = Utc::now();
+    let interval = Duration::seconds(config.magic_link_interval_secs);
+    db::magic_link::delete_expired(conn, now - interval).await?;
+    if !service::user::may_sign_in(conn, email, None).await? {
+        info!("Not sending a magic link to {email}, who can't sign in");
+        return Ok(());
+    }
+    // Held until the link is saved, so concurrent requests can't all get in before the interval starts
+    let mut tx = conn.begin().await?;
+    db::magic_link::lock(&mut tx, email).await?;
+    if db::magic_link::sent_since(&mut *tx, email, now - interval).await? {
+        info!("Not sending another magic link to {email} yet");
+        return Ok(());
+    }
+    let token = secret::random_secret(32);
+    let ttl = Duration::seconds(config.magic_link_ttl_secs);
+    db::magic_link::create(
+        &mut *tx,
+        &secret::hash_secret(&token),
+        email,
+        return_to.as_deref(),
+        now + ttl,
+    )
+    .await?;
+    tx.commit().await.map_err(AuthAppError::SqlError)?;
+    let mut link = Url::parse(&config.public_url)
+        .and_then(|u| u.join(MAGIC_LINK_PATH))
+        .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+    link.query_pairs_mut().append_pair("token", &token);
+    let login_email = Email {
+        to: email.to_string(),
+        subject: "Your Unleash login link".to_string(),
+        text: format!(
+            "Follow this link within {} minutes to log in to Unleash as {email}:\n\n{link}\n\nThe link works once. \
+             If you didn't ask for it, you can ignore this email.",
+            ttl.num_minutes()
+        ),
+    };
+    // The response can't differ for emails we didn't send to, so failed deliveries only show up in our logs
+    if let Err(e) = mailer.send(login_email).await {
+        warn!("Couldn't send magic link to {email}: {e}");
+    }
+    Ok(())
+}
+
+/// Whether the link's token can still be used, without using it up.
+pub async fn is_valid(conn: &Pool<Postgres>, token: &str) -> Result<bool, AuthAppError> {
+    db::magic_link::valid(conn, &secret::hash_secret(token)).await
+}
+
+/// Uses up the link's token, returning who it was sent to and where they wanted to go.
+pub async fn take(conn: &Pool<Postgres>, token: &str) -> Result<MagicLinkRow, AuthAppError> {
+    db::magic_link::take(conn, &secret::hash_secret(token))
+        .await?
+        .ok_or(AuthAppError::InvalidToken)
+}
@@ -1 +1,8 @@
+pub mod instance;
+pub mod magic_link;
+pub mod passkey;
+pub mod password_reset;
+pub mod saml;
+pub mod session;
+pub mod two_factor;
 pub mod user;
@@ -0,0 +1,209 @@
+use crate::auth::secret;
+use crate::auth::webauthn::{self, ES256, RS256, RelyingParty};
+use crate::errors::AuthAppError;
+use crate::model::passkey::{
+    AuthenticationOptions, AuthenticationResponse, AuthenticatorSelection, CredentialDescriptor,
+    CredentialParameters, NewPasskeyBody, Passkey, RegistrationOptions, RelyingPartyEntity,
+    UserEntity,
+};
+use crate::{AppConfig, db};
+use chrono::{Duration, Utc};
+use log::warn;
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+/// How long the browser, and so the user, has to complete a registration or login.
+const CEREMONY_TIMEOUT_SECS: i64 = 300;
+const PUBLIC_KEY: &str = "public-key";
+
+/// A user who proved they hold one of their passkeys.
+pub struct PasskeyLogin {
+    pub email: String,
+    pub return_to: Option<String>,
+    /// Whether the authenticator also checked a PIN or biometric, making the passkey a second factor by itself
+    pub user_verified: bool,
+}
+
+/// 32 random bytes, base64url encoded like all binary values in WebAuthn's JSON.
+fn random_id() -> String {
+    webauthn::encode(&rand::rng().random::<[u8; 32]>())
+}
+
+async fn store_challenge(
+    conn: &Pool<Postgres>,
+    email: Option<&str>,
+    return_to: Option<&str>,
+) -> Result<String, AuthAppError> {
+    db::passkey::delete_expired_challenges(conn).await?;
+    let challenge = random_id();
+    let expires_at = Utc::now() + Duration::seconds(CEREMONY_TIMEOUT_SECS);
+    db::passkey::create_challenge(
+        conn,
+        &secret::hash_secret(&challenge),
+        email,
+        return_to,
+        expires_at,
+    )
+    .await?;
+    Ok(challenge)
+}
+
+pub async fn start_registration(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    email: &str,
+) -> Result<RegistrationOptions, AuthAppError> {
+    let rp = RelyingParty::from_config(config)?;
+    let user_handle = db::passkey::user_handle(conn, email, &random_id()).await?;
+    let challenge = store_challenge(conn, Some(email), None).await?;
+    let existing = db::passkey::list_for_email(conn, email).await?;
+    Ok(RegistrationOptions {
+        challenge,
+        rp: RelyingPartyEntity {
+            id: rp.id,
+            name: config.totp_issuer.clone(),
+        },
+        user: UserEntity {
+            id: user_handle,
+            name: email.to_string(),
+            display_name: email.to_string(),
+        },
+        pub_key_cred_params: [ES256, RS256]
+            .into_iter()
+            .map(|alg| CredentialParameters {
+                credential_type: PUBLIC_KEY.to_string(),
+                alg,
+            })
+            .collect(),
+        timeout: CEREMONY_TIMEOUT_SECS * 1000,
+        attestation: "none".to_string(),
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "required".to_string(),
+            user_verification: "preferred".to_string(),
+        },
+        exclude_credentials: existing
+            .into_iter()
+            .map(|p| CredentialDescriptor {
+                credential_type: PUBLIC_KEY.to_string(),
+                id: p.id,
+            })
+            .collect(),
+    })
+}
+
+/// Stores the passkey the browser created for a registration the same user started.
+pub async fn finish_registration(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    email: &str,
+    body: NewPasskeyBody,
+) -> Result<Passkey, AuthAppError> {
+    let rp = RelyingParty::from_config(config)?;
+    let response = &body.credential.response;
+    let credential = webauthn::verify_registration(
+        &rp,
+        &response.client_data_json,
+        &response.attestation_object,
+    )?;
+    db::passkey::take_challenge(
+        conn,
+        &secret::hash_secret(&credential.challenge),
+        Some(email),
+    )
+    .await?
+    .ok_or(AuthAppError::InvalidToken)?;
+    let name = body
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .unwrap_or("Passkey");
+    db::passkey::create(
+        conn,
+        &credential.credential_id,
+        email,
+        name,
+        &hex::encode(&credential.cose_key),
+        i64::from(credential.sign_count),
+    )
+    .await
+}
+
+pub async fn list(conn: &Pool<Postgres>, email: &str) -> Result<Vec<Passkey>, AuthAppError> {
+    db::passkey::list_for_email(conn, email).await
+}
+
+pub async fn delete(
+    conn: &Pool<Postgres>,
+    email: &str,
+    credential_id: &str,
+) -> Result<(), AuthAppError> {
+    db::passkey::delete(conn, email, credential_id).await
+}
+
+/// Starts a login, remembering where to send the user once it succeeds.
+pub async fn start_login(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    return_to: Option<String>,
+) -> Result<AuthenticationOptions, AuthAppError> {
+    let rp = RelyingParty::from_config(config)?;
+    let challenge = store_challenge(conn, None, return_to.as_deref()).await?;
+    Ok(AuthenticationOptions {
+        challenge,
+        rp_id: rp.id,
+        timeout: CEREMONY_TIMEOUT_SECS * 1000,
+        user_verification: "preferred".to_string(),
+        allow_credentials: vec![],
+    })
+}
+
+/// Checks the passkey's signature over a challenge from [start_login], using up the challenge.
+pub async fn finish_login(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    credential: &AuthenticationResponse,
+) -> Result<PasskeyLogin, AuthAppError> {
+    let rp = RelyingParty::from_config(config)?;
+    let stored = db::passkey::get(conn, &credential.id)
+        .await?
+        .ok_or(AuthAppError::AccessNotAllowed)?;
+    let response = &credential.response;
+    if response
+        .user_handle
+        .as_ref()
+        .is_some_and(|handle| Some(handle) != stored.user_handle.as_ref())
+    {
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    let cose_key = hex::decode(&stored.cose_key).map_err(|_| AuthAppError::InvalidPasskey)?;
+    let assertion = webauthn::verify_assertion(
+        &rp,
+        &cose_key,
+        &response.client_data_json,
+        &response.authenticator_data,
+        &response.signature,
+    )?;
+    let challenge =
+        db::passkey::take_challenge(conn, &secret::hash_secret(&assertion.challenge), None)
+            .await?
+            .ok_or(AuthAppError::InvalidToken)?;
+    // Authenticators that count signatures never go back, so a lower count means the key was copied. Synced passkeys
+    // always send 0.
+    let sign_count = i64::from(assertion.sign_count);
+    if (sign_count != 0 || stored.sign_count != 0) && sign_count <= stored.sign_count {
+        warn!(
+            "Passkey {} of {} went back from {} to {sign_count} signatures, it may be cloned",
+            credential.id, stored.email, stored.sign_count
+        );
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    if !db::passkey::record_use(conn, &credential.id, stored.sign_count, sign_count).await? {
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    Ok(PasskeyLogin {
+        email: stored.email,
+        return_to: challenge.return_to,
+        user_verified: assertion.user_verified,
+    })
+}
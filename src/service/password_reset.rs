@@ -0,0 +1,86 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::{password, secret};
+use crate::controllers::pages::RESET_PASSWORD_PATH;
+use crate::errors::AuthAppError;
+use crate::mail::{Email, Mailer};
+use crate::{AppConfig, AppState, db, service};
+use chrono::{Duration, Utc};
+use log::{info, warn};
+use oauth2::url::Url;
+use sqlx::{Pool, Postgres};
+
+/// Emails a reset link to the user. Nothing tells the caller whether the email belongs to a user, or whether it was
+/// skipped because a link was sent to it less than `password_reset_interval_secs` ago.
+pub async fn request(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    mailer: &dyn Mailer,
+    email: &str,
+) -> Result<(), AuthAppError> {
+    let token = secret::random_secret(32);
+    let now = Utc::now();
+    let ttl = Duration::seconds(config.password_reset_ttl_secs);
+    let started = db::user::start_password_reset(
+        conn,
+        email,
+        &secret::hash_secret(&token),
+        now + ttl,
+        now - Duration::seconds(config.password_reset_interval_secs),
+    )
+    .await?;
+    if !started {
+        info!("Not sending a password reset email to {email}");
+        return Ok(());
+    }
+    let mut link = Url::parse(&config.public_url)
+        .and_then(|u| u.join(RESET_PASSWORD_PATH))
+        .map_err(|e| AuthAppError::InvalidConfiguration(format!("public_url: {e}")))?;
+    link.query_pairs_mut()
+        .append_pair("email", email)
+        .append_pair("token", &token);
+    let reset_email = Email {
+        to: email.to_string(),
+        subject: "Reset your Unleash password".to_string(),
+        text: format!(
+            "Someone asked to reset the password of your Unleash account {email}. Follow this link within {} minutes \
+             to choose a new password:\n\n{link}\n\nIf that wasn't you, you can ignore this email.",
+            ttl.num_minutes()
+        ),
+    };
+    // The response can't differ for known users, so failed deliveries only show up in our logs
+    if let Err(e) = mailer.send(reset_email).await {
+        warn!("Couldn't send password reset email to {email}: {e}");
+    }
+    Ok(())
+}
+
+pub async fn is_valid(
+    conn: &Pool<Postgres>,
+    email: &str,
+    token: &str,
+) -> Result<bool, AuthAppError> {
+    db::user::password_reset_valid(conn, email, &secret::hash_secret(token)).await
+}
+
+/// Sets a new password with a token from a reset email, using up the token. Every session of the user ends, since
+/// the reset may be because someone else got hold of the old password.
+pub async fn reset(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    email: &str,
+    token: &str,
+    new_password: &str,
+) -> Result<(), AuthAppError> {
+    password::validate_new(new_password)?;
+    if !db::user::take_password_reset(conn, email, &secret::hash_secret(token)).await? {
+        return Err(AuthAppError::InvalidToken);
+    }
+    let new_hash = password::hash(new_password).await;
+    db::user::set_password_hash(conn, email, &new_hash).await?;
+    service::session::end_all_for_email(conn, app_state, keyring, email).await?;
+    // Whoever reset the password owns the email address, so earlier failed logins no longer matter
+    db::failed_login::clear_for_email(conn, email)
+        .await
+        .map(|_| ())
+}
@@ -0,0 +1,203 @@
+use crate::auth::saml::{self, IdentityProvider, SamlAssertion, ServiceProvider};
+use crate::errors::AuthAppError;
+use crate::model::saml::{SamlConfig, SamlConfigBody, SamlConfigRow};
+use crate::model::user::Role;
+use crate::{AppConfig, db};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{Duration, Utc};
+use log::info;
+use oauth2::url::Url;
+use rand::Rng;
+use sqlx::types::Json;
+use sqlx::{Pool, Postgres};
+
+/// A user the instance's IdP vouched for, with their access to the instance already provisioned.
+pub struct SamlLogin {
+    pub email: String,
+    pub return_to: Option<String>,
+}
+
+fn identity_provider(row: &SamlConfigRow) -> Result<IdentityProvider, AuthAppError> {
+    Ok(IdentityProvider {
+        entity_id: row.idp_entity_id.clone(),
+        sso_url: row.idp_sso_url.clone(),
+        certificate: STANDARD
+            .decode(&row.idp_certificate)
+            .map_err(|e| AuthAppError::InvalidSaml(e.to_string()))?,
+    })
+}
+
+fn default_role(row: &SamlConfigRow) -> Result<Option<Role>, AuthAppError> {
+    row.default_role
+        .as_deref()
+        .map(|r| r.parse())
+        .transpose()
+        .map_err(|_| {
+            AuthAppError::InvalidSaml(format!("Unknown default role {:?}", row.default_role))
+        })
+}
+
+fn to_config(config: &AppConfig, row: SamlConfigRow) -> Result<SamlConfig, AuthAppError> {
+    let sp = ServiceProvider::for_instance(config, &row.client_id)?;
+    let default_role = default_role(&row)?;
+    Ok(SamlConfig {
+        client_id: row.client_id,
+        idp_entity_id: row.idp_entity_id,
+        idp_sso_url: row.idp_sso_url,
+        email_attribute: row.email_attribute,
+        role_attribute: row.role_attribute,
+        role_mapping: row.role_mapping.0,
+        default_role,
+        sp_entity_id: sp.entity_id,
+        acs_url: sp.acs_url,
+        updated_at: row.updated_at,
+    })
+}
+
+/// Sets up, or replaces, the IdP of an instance from its metadata. The instance needs an email domain, since its IdP
+/// can only log in users of that domain.
+pub async fn configure(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+    body: SamlConfigBody,
+) -> Result<SamlConfig, AuthAppError> {
+    let instance = db::instance::get(conn, client_id).await?;
+    if instance.email_domain.is_none() {
+        return Err(AuthAppError::InvalidSamlSetup(format!(
+            "Instance {client_id} needs an email domain for SAML, only users in it can log in that way"
+        )));
+    }
+    let idp = saml::parse_idp_metadata(&body.idp_metadata)?;
+    let row = SamlConfigRow {
+        client_id: client_id.to_string(),
+        idp_entity_id: idp.entity_id,
+        idp_sso_url: idp.sso_url,
+        idp_certificate: STANDARD.encode(idp.certificate),
+        email_attribute: body.email_attribute,
+        role_attribute: body.role_attribute,
+        role_mapping: Json(body.role_mapping),
+        default_role: body.default_role.map(|r| r.to_string()),
+        updated_at: Utc::now(),
+    };
+    let row = db::saml::upsert_config(conn, &row).await?;
+    to_config(config, row)
+}
+
+pub async fn get(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+) -> Result<SamlConfig, AuthAppError> {
+    let row = db::saml::get_config(conn, client_id).await?;
+    to_config(config, row)
+}
+
+/// Our metadata for the instance, only served once the instance has an IdP.
+pub async fn metadata(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+) -> Result<String, AuthAppError> {
+    db::saml::get_config(conn, client_id).await?;
+    ServiceProvider::for_instance(config, client_id).map(|sp| sp.metadata())
+}
+
+/// Remembers a new AuthnRequest and returns the IdP URL to send the user to with it.
+pub async fn start_login(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+    return_to: Option<String>,
+) -> Result<Url, AuthAppError> {
+    let row = db::saml::get_config(conn, client_id).await?;
+    let sp = ServiceProvider::for_instance(config, client_id)?;
+    db::saml::delete_expired_requests(conn).await?;
+    // IDs must not start with a digit, as they are XML IDs
+    let request_id = format!("_{}", hex::encode(rand::rng().random::<[u8; 20]>()));
+    let now = Utc::now();
+    db::saml::create_request(
+        conn,
+        &request_id,
+        client_id,
+        return_to.as_deref(),
+        now + Duration::seconds(config.login_state_ttl_secs),
+    )
+    .await?;
+    sp.login_url(&identity_provider(&row)?, &request_id, now)
+}
+
+/// Role from the first value of the role attribute that is mapped, or the default role.
+fn role_for(row: &SamlConfigRow, assertion: &SamlAssertion) -> Result<Role, AuthAppError> {
+    let values = row
+        .role_attribute
+        .as_deref()
+        .map(|a| assertion.attribute_values(a))
+        .unwrap_or_default();
+    let mapped = values.iter().find_map(|v| row.role_mapping.get(v));
+    match (mapped, default_role(row)?) {
+        (Some(role), _) => Ok(role.clone()),
+        (None, Some(role)) => Ok(role),
+        (None, None) => {
+            info!(
+                "No role for SAML user of {} with values {values:?}",
+                row.client_id
+            );
+            Err(AuthAppError::AccessNotAllowed)
+        }
+    }
+}
+
+/// Validates a response from the instance's IdP and provisions the user it names: they are created if they are
+/// new, and given the role the IdP's attributes map to if they have no access to the instance yet. Roles of users who
+/// already have access are left alone, so changes made by hand stick. Responses nobody asked for, i.e. IdP initiated
+/// logins, are refused.
+pub async fn finish_login(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    client_id: &str,
+    saml_response: &str,
+) -> Result<SamlLogin, AuthAppError> {
+    let row = db::saml::get_config(conn, client_id).await?;
+    let sp = ServiceProvider::for_instance(config, client_id)?;
+    let assertion = sp.validate_response(&identity_provider(&row)?, saml_response, Utc::now())?;
+    let Some(request) = db::saml::take_request(conn, &assertion.in_response_to, client_id).await?
+    else {
+        info!("SAML response for {client_id} answers an unknown or used request");
+        return Err(AuthAppError::AccessNotAllowed);
+    };
+    let email = match &row.email_attribute {
+        Some(attribute) => assertion.attribute_values(attribute).first().cloned(),
+        None => assertion.name_id.clone(),
+    }
+    .ok_or_else(|| {
+        info!("SAML response for {client_id} has no email");
+        AuthAppError::AccessNotAllowed
+    })?;
+    // Otherwise a customer's IdP could log its users in as anyone, including users of other instances
+    let instance = db::instance::get(conn, client_id).await?;
+    let in_domain = match (email.rsplit_once('@'), instance.email_domain.as_deref()) {
+        (Some((_, domain)), Some(instance_domain)) => domain.eq_ignore_ascii_case(instance_domain),
+        _ => false,
+    };
+    if !in_domain {
+        info!("SAML user {email} is outside the email domain of {client_id}");
+        return Err(AuthAppError::DomainNotAllowed);
+    }
+    let has_access = db::user_access::get_access_for_email(conn, &email)
+        .await?
+        .iter()
+        .any(|access| access.client_id == client_id);
+    if !has_access {
+        let role = role_for(&row, &assertion)?;
+        if !db::user::user_exists(conn, &email).await? {
+            db::user::create_user(conn, &email).await?;
+        }
+        db::user_access::add_access(conn, client_id, &email, role).await?;
+    }
+    Ok(SamlLogin {
+        email,
+        return_to: request.return_to,
+    })
+}
@@ -0,0 +1,226 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::refresh_token;
+use crate::auth::sealed::Sealed;
+use crate::auth::token::{self, AuthenticatedTokenUser, IssuedToken};
+use crate::db;
+use crate::errors::AuthAppError;
+use crate::model::user::UserAccess;
+use crate::{AppConfig, AppState};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use oauth2::reqwest::redirect::Policy;
+use oauth2::{reqwest, AccessToken, StandardRevocableToken};
+use sqlx::{Pool, Postgres};
+
+/// A short lived access token, plus the single use refresh token to get the next one with.
+pub struct SessionTokens {
+    pub access: IssuedToken,
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+/// Issues tokens for a fresh login and records the session, so it can be revoked before it expires.
+pub async fn start(
+    conn: &Pool<Postgres>,
+    config: AppConfig,
+    keyring: &Keyring,
+    email: String,
+    access: Vec<UserAccess>,
+    upstream_token: Option<&str>,
+) -> Result<SessionTokens, AuthAppError> {
+    db::session::delete_expired_for_email(conn, &email).await?;
+    db::refresh_token::delete_expired_for_email(conn, &email).await?;
+    let family_id = refresh_token::new_family_id();
+    issue(
+        conn,
+        config,
+        keyring,
+        email,
+        access,
+        upstream_token,
+        &family_id,
+    )
+    .await
+}
+
+/// Exchanges a refresh token for a new token pair in the same family. Access is looked up again, so role
+/// changes show up on the next refresh. Presenting a token that was already used revokes the whole family,
+/// as either the user or whoever copied the token is no longer who we think.
+pub async fn refresh(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    config: AppConfig,
+    keyring: &Keyring,
+    presented_token: &str,
+) -> Result<SessionTokens, AuthAppError> {
+    let token_hash = refresh_token::hash(presented_token);
+    match db::refresh_token::consume(conn, &token_hash).await? {
+        Some(consumed) => {
+            let access = db::user_access::get_access_for_email(conn, &consumed.email).await?;
+            issue(
+                conn,
+                config,
+                keyring,
+                consumed.email,
+                access,
+                None,
+                &consumed.family_id,
+            )
+            .await
+        }
+        None => {
+            if let Some(spent) = db::refresh_token::get_spent(conn, &token_hash).await?
+                && spent.used_at.is_some()
+            {
+                warn!("Refresh token was reused, revoking its family");
+                let upstream_tokens =
+                    db::refresh_token::revoke_family(conn, &spent.family_id).await?;
+                for t in upstream_tokens.into_iter().flatten() {
+                    revoke_upstream(app_state, keyring, t).await;
+                }
+            }
+            Err(AuthAppError::NotAuthenticated)
+        }
+    }
+}
+
+async fn issue(
+    conn: &Pool<Postgres>,
+    config: AppConfig,
+    keyring: &Keyring,
+    email: String,
+    access: Vec<UserAccess>,
+    upstream_token: Option<&str>,
+    family_id: &str,
+) -> Result<SessionTokens, AuthAppError> {
+    if config.refresh_token_life_time_secs <= 0 {
+        return Err(AuthAppError::InvalidCookieDuration);
+    }
+    let refresh_expires_at = Utc::now() + Duration::seconds(config.refresh_token_life_time_secs);
+    let issued = token::issue_token(config, keyring, email.clone(), access)?;
+    let sealed_upstream_token = upstream_token
+        .map(|t| Sealed::UpstreamToken.seal(keyring, t))
+        .transpose()?;
+    db::session::create(
+        conn,
+        &issued.token_id,
+        &email,
+        sealed_upstream_token.as_deref(),
+        issued.expires_at,
+    )
+    .await?;
+    let refresh = refresh_token::generate();
+    db::refresh_token::create(
+        conn,
+        &refresh.token_hash,
+        family_id,
+        &email,
+        &issued.token_id,
+        refresh_expires_at,
+    )
+    .await?;
+    Ok(SessionTokens {
+        access: issued,
+        refresh_token: refresh.token,
+        refresh_expires_at,
+    })
+}
+
+/// Validates the token and checks that its session hasn't been revoked.
+pub async fn validate(
+    conn: &Pool<Postgres>,
+    config: AppConfig,
+    keyring: &Keyring,
+    session_token: String,
+) -> Result<AuthenticatedTokenUser, AuthAppError> {
+    let user = token::validate_token(config, keyring, session_token)?;
+    if db::session::is_revoked(conn, &user.token_id).await? {
+        Err(AuthAppError::SessionRevoked)
+    } else {
+        Ok(user)
+    }
+}
+
+/// Revokes the user's current session, the refresh token family it belongs to, and the upstream token it was
+/// started with.
+pub async fn end(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    user: &AuthenticatedTokenUser,
+) -> Result<(), AuthAppError> {
+    let mut upstream_tokens =
+        vec![db::session::revoke(conn, &user.token_id, &user.email, user.expires_at).await?];
+    if let Some(family_id) = db::refresh_token::family_for_session(conn, &user.token_id).await? {
+        upstream_tokens.extend(db::refresh_token::revoke_family(conn, &family_id).await?);
+    }
+    for t in upstream_tokens.into_iter().flatten() {
+        revoke_upstream(app_state, keyring, t).await;
+    }
+    Ok(())
+}
+
+/// Revokes every live session for `email`, returning how many were revoked.
+pub async fn end_all_for_email(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    email: &str,
+) -> Result<usize, AuthAppError> {
+    end_all_except(conn, app_state, keyring, email, None).await
+}
+
+/// Revokes every live session of the user but the current one, e.g. after they change their password.
+pub async fn end_others(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    user: &AuthenticatedTokenUser,
+) -> Result<usize, AuthAppError> {
+    end_all_except(conn, app_state, keyring, &user.email, Some(&user.token_id)).await
+}
+
+async fn end_all_except(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    email: &str,
+    keep_jti: Option<&str>,
+) -> Result<usize, AuthAppError> {
+    let keep_family = match keep_jti {
+        Some(jti) => db::refresh_token::family_for_session(conn, jti).await?,
+        None => None,
+    };
+    let upstream_tokens = db::session::revoke_all_for_email(conn, email, keep_jti).await?;
+    db::refresh_token::revoke_all_for_email(conn, email, keep_family.as_deref()).await?;
+    let revoked = upstream_tokens.len();
+    for t in upstream_tokens.into_iter().flatten() {
+        revoke_upstream(app_state, keyring, t).await;
+    }
+    Ok(revoked)
+}
+
+/// Our own session is already gone at this point, so a failed upstream revocation is only logged.
+async fn revoke_upstream(app_state: &AppState, keyring: &Keyring, sealed_upstream_token: String) {
+    let Some(upstream_token) = Sealed::UpstreamToken.open(keyring, &sealed_upstream_token) else {
+        warn!("Couldn't decrypt upstream token, it won't be revoked");
+        return;
+    };
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to build httpclient");
+    let revocation = app_state
+        .oauth
+        .revoke_token(StandardRevocableToken::AccessToken(AccessToken::new(
+            upstream_token,
+        )));
+    match revocation {
+        Ok(request) => {
+            if let Err(e) = request.request_async(&http_client).await {
+                warn!("Failed to revoke upstream token: {e}");
+            }
+        }
+        Err(e) => warn!("Upstream token revocation isn't configured: {e}"),
+    }
+}
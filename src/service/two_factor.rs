@@ -0,0 +1,290 @@
+use crate::auth::keyring::Keyring;
+use crate::auth::login_throttle::LoginThrottle;
+use crate::auth::sealed::Sealed;
+use crate::auth::{secret, totp};
+use crate::errors::AuthAppError;
+use crate::model::two_factor::{RecoveryCodes, TotpCredentialRow, TotpEnrolment};
+use crate::{AppConfig, db};
+use chrono::{Duration, Utc};
+use log::info;
+use sqlx::{Pool, Postgres};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Codes allowed per challenge. Wrong codes also count towards locking out the account, so getting new challenges
+/// doesn't give more guesses.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// What the second login step asks of the user.
+pub enum ChallengeStep {
+    Verify,
+    /// The user has no authenticator set up yet, but one of their instances requires it
+    Enrol(TotpEnrolment),
+}
+
+/// Generates a new TOTP secret for the user. It replaces any earlier unconfirmed one, but not a confirmed one.
+pub async fn start_enrolment(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    email: &str,
+) -> Result<TotpEnrolment, AuthAppError> {
+    let secret = totp::generate_secret();
+    let sealed_secret = seal_secret(keyring, &secret)?;
+    if !db::two_factor::save_unconfirmed_totp(conn, email, &sealed_secret).await? {
+        return Err(AuthAppError::TwoFactorAlreadyEnabled);
+    }
+    Ok(enrolment(config, email, &secret))
+}
+
+/// TOTP secrets are stored hex encoded and sealed with the keyring, so a copy of the database doesn't give away
+/// anyone's second factor.
+fn seal_secret(keyring: &Keyring, secret: &[u8]) -> Result<String, AuthAppError> {
+    Sealed::TotpSecret.seal(keyring, &hex::encode(secret))
+}
+
+fn open_secret(keyring: &Keyring, sealed_secret: &str) -> Option<Vec<u8>> {
+    Sealed::TotpSecret
+        .open(keyring, sealed_secret)
+        .and_then(|secret| hex::decode(secret).ok())
+}
+
+fn enrolment(config: &AppConfig, email: &str, secret: &[u8]) -> TotpEnrolment {
+    TotpEnrolment {
+        secret: totp::base32(secret),
+        otpauth_uri: totp::otpauth_uri(&config.totp_issuer, email, secret),
+    }
+}
+
+/// Turns on two-factor login once the user shows they can produce codes for the new secret.
+pub async fn confirm_enrolment(
+    conn: &Pool<Postgres>,
+    keyring: &Keyring,
+    email: &str,
+    code: &str,
+) -> Result<RecoveryCodes, AuthAppError> {
+    let credential = db::two_factor::get_totp(conn, email)
+        .await?
+        .ok_or(AuthAppError::AccessNotAllowed)?;
+    if credential.confirmed {
+        return Err(AuthAppError::TwoFactorAlreadyEnabled);
+    }
+    if !verify_totp(conn, keyring, email, &credential, code).await? {
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    new_recovery_codes(conn, email).await
+}
+
+/// Replaces the user's recovery codes, e.g. when they've used up some or enrolled while logging in.
+pub async fn regenerate_recovery_codes(
+    conn: &Pool<Postgres>,
+    keyring: &Keyring,
+    email: &str,
+    code: &str,
+) -> Result<RecoveryCodes, AuthAppError> {
+    let credential = db::two_factor::get_totp(conn, email)
+        .await?
+        .filter(|c| c.confirmed)
+        .ok_or(AuthAppError::AccessNotAllowed)?;
+    if !verify_totp(conn, keyring, email, &credential, code).await? {
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    new_recovery_codes(conn, email).await
+}
+
+/// Users who set up an authenticator always need it, and so does everyone with access to an instance requiring it.
+pub async fn needs_second_factor(conn: &Pool<Postgres>, email: &str) -> Result<bool, AuthAppError> {
+    let confirmed = db::two_factor::get_totp(conn, email)
+        .await?
+        .is_some_and(|c| c.confirmed);
+    Ok(confirmed || db::two_factor::required_for_email(conn, email).await?)
+}
+
+/// Remembers that the user passed the first factor, returning the token that lets them take the second step.
+pub async fn start_challenge(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    email: &str,
+    return_to: Option<&str>,
+) -> Result<String, AuthAppError> {
+    db::two_factor::delete_expired_challenges(conn).await?;
+    let token = secret::random_secret(32);
+    let expires_at = Utc::now() + Duration::seconds(config.two_factor_challenge_ttl_secs);
+    db::two_factor::create_challenge(
+        conn,
+        &secret::hash_secret(&token),
+        email,
+        return_to,
+        expires_at,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// What to show for a challenge, or `None` when it has expired or been used. Users without an authenticator are
+/// offered the same secret every time they look at the challenge, so reloading the page doesn't change it.
+pub async fn challenge_step(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    token: &str,
+) -> Result<Option<ChallengeStep>, AuthAppError> {
+    let token_hash = secret::hash_secret(token);
+    let Some(challenge) = db::two_factor::get_challenge(conn, &token_hash).await? else {
+        return Ok(None);
+    };
+    let confirmed = db::two_factor::get_totp(conn, &challenge.email)
+        .await?
+        .is_some_and(|c| c.confirmed);
+    if confirmed {
+        return Ok(Some(ChallengeStep::Verify));
+    }
+    let enrolment_secret = match challenge.enrolment_secret {
+        Some(secret) => Some(secret),
+        None => {
+            let secret = seal_secret(keyring, &totp::generate_secret())?;
+            db::two_factor::set_enrolment_secret(conn, &token_hash, &secret).await?
+        }
+    };
+    match enrolment_secret.and_then(|secret| open_secret(keyring, &secret)) {
+        Some(secret) => Ok(Some(ChallengeStep::Enrol(enrolment(
+            config,
+            &challenge.email,
+            &secret,
+        )))),
+        _ => Ok(None),
+    }
+}
+
+/// A passed challenge. Users who set up their authenticator during it get their first recovery codes.
+pub struct CompletedChallenge {
+    pub email: String,
+    pub return_to: Option<String>,
+    pub recovery_codes: Option<RecoveryCodes>,
+}
+
+/// Checks a TOTP or recovery code against the challenge, using it up on success. For users without an authenticator
+/// a code from the secret the challenge offered them confirms that secret. Wrong codes are failed logins for the
+/// account, and the account's earlier failures are only forgotten once the code is right.
+pub async fn complete_challenge(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    keyring: &Keyring,
+    token: &str,
+    code: &str,
+) -> Result<CompletedChallenge, AuthAppError> {
+    let token_hash = secret::hash_secret(token);
+    let challenge = db::two_factor::get_challenge(conn, &token_hash)
+        .await?
+        .ok_or(AuthAppError::InvalidToken)?;
+    let now = Utc::now();
+    let account_throttle = LoginThrottle::for_account(config);
+    account_throttle.check(
+        db::failed_login::for_email(conn, &challenge.email)
+            .await?
+            .as_ref(),
+        now,
+    )?;
+    // Counted before checking the code, so concurrent guesses can't all get in under the limit
+    let attempts = db::two_factor::record_challenge_attempt(conn, &token_hash)
+        .await?
+        .ok_or(AuthAppError::InvalidToken)?;
+    if attempts > MAX_CHALLENGE_ATTEMPTS {
+        db::two_factor::delete_challenge(conn, &token_hash).await?;
+        return Err(AuthAppError::AccountLocked);
+    }
+    let credential = db::two_factor::get_totp(conn, &challenge.email)
+        .await?
+        .filter(|c| c.confirmed);
+    let passed = match (&credential, &challenge.enrolment_secret) {
+        (Some(credential), _) => {
+            verify_totp(conn, keyring, &challenge.email, credential, code).await?
+                || db::two_factor::use_recovery_code(
+                    conn,
+                    &challenge.email,
+                    &secret::hash_secret(code.trim()),
+                )
+                .await?
+        }
+        (None, Some(enrolment_secret)) => {
+            confirm_with_code(conn, keyring, &challenge.email, enrolment_secret, code).await?
+        }
+        // The user hasn't been shown a secret to enrol yet
+        (None, None) => return Err(AuthAppError::InvalidToken),
+    };
+    if !passed {
+        info!("Wrong second factor for {}", challenge.email);
+        db::failed_login::record_for_email(conn, &challenge.email, now - account_throttle.lockout)
+            .await?;
+        return Err(AuthAppError::AccessNotAllowed);
+    }
+    if !db::two_factor::delete_challenge(conn, &token_hash).await? {
+        return Err(AuthAppError::InvalidToken);
+    }
+    db::failed_login::clear_for_email(conn, &challenge.email).await?;
+    let recovery_codes = match credential {
+        Some(_) => None,
+        None => Some(new_recovery_codes(conn, &challenge.email).await?),
+    };
+    Ok(CompletedChallenge {
+        email: challenge.email,
+        return_to: challenge.return_to,
+        recovery_codes,
+    })
+}
+
+/// Saves the secret offered during a challenge as the user's authenticator if `code` is from it.
+async fn confirm_with_code(
+    conn: &Pool<Postgres>,
+    keyring: &Keyring,
+    email: &str,
+    enrolment_secret: &str,
+    code: &str,
+) -> Result<bool, AuthAppError> {
+    let Some(secret) = open_secret(keyring, enrolment_secret) else {
+        return Ok(false);
+    };
+    match totp::verify(&secret, code, Utc::now().timestamp(), None) {
+        Some(step) => {
+            db::two_factor::save_confirmed_totp(conn, email, enrolment_secret, step).await
+        }
+        None => Ok(false),
+    }
+}
+
+/// Secrets are sealed again with the current key whenever they're used, so retiring an old key from the keyring only
+/// loses the authenticators of users who haven't logged in since it was rotated out.
+async fn verify_totp(
+    conn: &Pool<Postgres>,
+    keyring: &Keyring,
+    email: &str,
+    credential: &TotpCredentialRow,
+    code: &str,
+) -> Result<bool, AuthAppError> {
+    let Some(secret) = open_secret(keyring, &credential.secret) else {
+        return Ok(false);
+    };
+    match totp::verify(
+        &secret,
+        code,
+        Utc::now().timestamp(),
+        credential.last_used_step,
+    ) {
+        Some(step) => {
+            let resealed_secret = seal_secret(keyring, &secret)?;
+            db::two_factor::use_totp_step(conn, email, step, &resealed_secret).await
+        }
+        None => Ok(false),
+    }
+}
+
+async fn new_recovery_codes(
+    conn: &Pool<Postgres>,
+    email: &str,
+) -> Result<RecoveryCodes, AuthAppError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| secret::random_secret(12))
+        .collect();
+    let code_hashes = codes.iter().map(|c| secret::hash_secret(c)).collect();
+    db::two_factor::replace_recovery_codes(conn, email, code_hashes).await?;
+    Ok(RecoveryCodes { codes })
+}
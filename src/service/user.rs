@@ -1,31 +1,138 @@
-use crate::db;
+use crate::auth::keyring::Keyring;
+use crate::auth::login_throttle::LoginThrottle;
+use crate::auth::password;
+use crate::auth::token::AuthenticatedTokenUser;
 use crate::errors::AuthAppError;
+use crate::model::instance::InstanceRow;
 use crate::model::user::{MinimalAuthUser, Role};
+use crate::{AppConfig, AppState, db, service};
+use chrono::Utc;
 use log::{info, warn};
 use sqlx::{Pool, Postgres};
 
+/// New users join the instance claiming their email domain. `hosted_domain` is the Google Workspace domain the
+/// provider vouched for, which instances requiring it must match.
 pub async fn get_or_create_user(
     conn: &Pool<Postgres>,
     email: String,
+    hosted_domain: Option<&str>,
 ) -> Result<MinimalAuthUser, AuthAppError> {
     let user_exists = db::user::user_exists(conn, &email).await?;
     if !user_exists {
-        let domain = email.split('@').last().unwrap();
-        warn!("Getting instances for {:#?}", domain);
-        match db::instance::get_instance_for_domain(conn, domain.to_string()).await {
-            Ok(instance) => {
-                warn!("Instance was fine: {:#?}", instance);
-                let user = db::user::create_user(conn, &email).await?;
-                db::user_access::add_access(conn, &instance.client_id, &email, Role::WRITE)
-                    .await?;
-                Ok(user)
-            }
-            Err(_) => {
-                info!("Something was wrong with instance for domain {domain}");
-                Err(AuthAppError::DomainNotAllowed)
-            }
-        }
+        let instance = instance_to_join(conn, &email, hosted_domain).await?;
+        let user = db::user::create_user(conn, &email).await?;
+        db::user_access::add_access(conn, &instance.client_id, &email, Role::WRITE).await?;
+        Ok(user)
     } else {
         db::user::get_user(conn, &email).await
     }
 }
+
+/// Whether [get_or_create_user] would let this email in, without creating anything.
+pub async fn may_sign_in(
+    conn: &Pool<Postgres>,
+    email: &str,
+    hosted_domain: Option<&str>,
+) -> Result<bool, AuthAppError> {
+    if db::user::user_exists(conn, email).await? {
+        return Ok(true);
+    }
+    match instance_to_join(conn, email, hosted_domain).await {
+        Ok(_) => Ok(true),
+        Err(AuthAppError::DomainNotAllowed) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+async fn instance_to_join(
+    conn: &Pool<Postgres>,
+    email: &str,
+    hosted_domain: Option<&str>,
+) -> Result<InstanceRow, AuthAppError> {
+    let domain = email.split('@').next_back().unwrap();
+    warn!("Getting instances for {:#?}", domain);
+    match db::instance::get_instance_for_domain(conn, domain.to_string()).await {
+        Ok(instance) if instance.require_hosted_domain && hosted_domain != Some(domain) => {
+            info!("Instance for domain {domain} requires logging in with its Google Workspace");
+            Err(AuthAppError::DomainNotAllowed)
+        }
+        Ok(instance) => {
+            warn!("Instance was fine: {:#?}", instance);
+            Ok(instance)
+        }
+        Err(_) => {
+            info!("Something was wrong with instance for domain {domain}");
+            Err(AuthAppError::DomainNotAllowed)
+        }
+    }
+}
+
+/// Succeeds when `password` matches the user's argon2id hash. Unknown users and users without a password are
+/// refused the same way, so the response doesn't tell which emails have accounts. Failures count towards locking out
+/// both the account and the IP the login came from. A right password doesn't clear the account's failures, since the
+/// login may still need a second factor: they're forgotten once the whole login succeeds.
+pub async fn check_password(
+    conn: &Pool<Postgres>,
+    config: &AppConfig,
+    email: &str,
+    password: &str,
+    ip: &str,
+) -> Result<(), AuthAppError> {
+    let password_hash = db::user::get_password_hash(conn, email).await?;
+    let mut tx = conn.begin().await?;
+    db::failed_login::lock(&mut tx, email, ip).await?;
+    let now = Utc::now();
+    let account_throttle = LoginThrottle::for_account(config);
+    let ip_throttle = LoginThrottle::for_ip(config);
+    account_throttle.check(
+        db::failed_login::for_email(&mut *tx, email).await?.as_ref(),
+        now,
+    )?;
+    ip_throttle.check(db::failed_login::for_ip(&mut *tx, ip).await?.as_ref(), now)?;
+    let valid = password::verify(password, password_hash).await;
+    if !valid {
+        info!("Failed password login for {email} from {ip}");
+        db::failed_login::record_for_email(&mut *tx, email, now - account_throttle.lockout).await?;
+        db::failed_login::record_for_ip(&mut *tx, ip, now - ip_throttle.lockout).await?;
+    }
+    tx.commit().await.map_err(AuthAppError::SqlError)?;
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthAppError::AccessNotAllowed)
+    }
+}
+
+/// Lets a locked out user try again right away. Returns whether there was anything to unlock.
+pub async fn unlock(conn: &Pool<Postgres>, email: &str) -> Result<bool, AuthAppError> {
+    db::failed_login::clear_for_email(conn, email).await
+}
+
+/// Users replacing a password have to confirm the current one. Users who only ever logged in through a provider
+/// can set their first password with just their session. The user's other sessions end, so a changed password also
+/// locks out whoever may have used the old one.
+pub async fn set_password(
+    conn: &Pool<Postgres>,
+    app_state: &AppState,
+    keyring: &Keyring,
+    user: &AuthenticatedTokenUser,
+    current_password: Option<&str>,
+    new_password: &str,
+) -> Result<(), AuthAppError> {
+    let email = user.email.as_str();
+    password::validate_new(new_password)?;
+    let current_hash = db::user::get_password_hash(conn, email)
+        .await?
+        .ok_or(AuthAppError::NotAuthenticated)?;
+    if password::is_usable(&current_hash) {
+        let current_password = current_password.ok_or(AuthAppError::AccessNotAllowed)?;
+        if !password::verify(current_password, Some(current_hash)).await {
+            return Err(AuthAppError::AccessNotAllowed);
+        }
+    }
+    let new_hash = password::hash(new_password).await;
+    db::user::set_password_hash(conn, email, &new_hash).await?;
+    service::session::end_others(conn, app_state, keyring, user)
+        .await
+        .map(|_| ())
+}
@@ -1,3 +1,20 @@
 #[cfg(test)]
+pub mod github_auth_test;
+pub mod google_auth_test;
+pub mod instance_chooser_test;
 pub mod instance_test;
+pub mod introspection_test;
+pub mod login_state_test;
+pub mod login_throttle_test;
+pub mod magic_link_test;
+pub mod oidc_auth_test;
+pub mod passkey_test;
+pub mod password_auth_test;
+pub mod password_reset_test;
+pub mod saml_test;
+pub mod service_tokens_test;
+pub mod sessions_test;
+pub mod support;
+pub mod two_factor_test;
 pub mod users_test;
+pub mod well_known_test;
@@ -0,0 +1,260 @@
+use actix_web::http::header;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use auth_app_rs::auth::keyring::{Keyring, TokenKey};
+use auth_app_rs::auth::token;
+use auth_app_rs::model::user::UserAccess;
+use auth_app_rs::{AppConfig, AppState};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use oauth2::basic::BasicClient;
+use oauth2::url::Url;
+use oauth2::{AuthUrl, ClientId, RedirectUrl, RevocationUrl, TokenUrl};
+use ring::rand::SystemRandom;
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+pub const ADMIN_EMAIL: &str = "admin@getunleash.io";
+const SECRET: &str = "abc123abc123abc123abc123abc12323";
+
+pub fn config() -> AppConfig {
+    AppConfig {
+        secret: SECRET.to_string(),
+        cookie_name: "auth_app_rs_auth".to_string(),
+        cookie_life_time_secs: 3600,
+        refresh_token_life_time_secs: 86400,
+        admin_emails: vec![ADMIN_EMAIL.to_string()],
+        base_url: "app.unleash-hosted.com".to_string(),
+        public_url: "http://localhost:1500".to_string(),
+        login_delay_after_attempts: 3,
+        login_max_attempts: 10,
+        login_max_attempts_per_ip: 100,
+        login_lockout_secs: 900,
+        totp_issuer: "Unleash".to_string(),
+        two_factor_challenge_ttl_secs: 300,
+        trial_length_days: 14,
+        trial_extension_days: 5,
+        trial_max_extensions: 2,
+        ..Default::default()
+    }
+}
+
+pub fn keyring() -> Keyring {
+    Keyring::new(TokenKey::new("primary", SECRET).unwrap(), vec![]).unwrap()
+}
+
+pub fn admin_auth_header() -> (header::HeaderName, String) {
+    let token = token::create_token(config(), &keyring(), ADMIN_EMAIL.to_string(), vec![])
+        .expect("Couldn't create admin token");
+    (header::AUTHORIZATION, format!("Bearer {token}"))
+}
+
+pub fn session_token(email: &str, access: Vec<UserAccess>) -> String {
+    token::create_token(config(), &keyring(), email.to_string(), access)
+        .expect("Couldn't create session token")
+}
+
+/// An OAuth client pointing nowhere, for endpoints that need [AppState] but never reach Google in tests.
+pub fn app_state() -> AppState {
+    AppState {
+        oauth: BasicClient::new(ClientId::new("test-client".to_string()))
+            .set_auth_uri_option(AuthUrl::new("http://localhost/auth".to_string()).ok())
+            .set_token_uri_option(TokenUrl::new("http://localhost/token".to_string()).ok())
+            .set_redirect_uri(RedirectUrl::new("http://localhost/callback".to_string()).unwrap())
+            .set_revocation_url(RevocationUrl::new("http://localhost/revoke".to_string()).unwrap()),
+        scope_url: Url::parse("http://localhost/scope").unwrap(),
+        userinfo_url: Url::parse("http://localhost/userinfo").unwrap(),
+        github: None,
+    }
+}
+
+/// Starts a fake OAuth provider handing out a fixed token and answering userinfo requests with `userinfo`.
+pub fn mock_oauth_server(userinfo: serde_json::Value) -> String {
+    let server = HttpServer::new(move || {
+        let userinfo = userinfo.clone();
+        App::new()
+            .route(
+                "/token",
+                web::post().to(|| async {
+                    HttpResponse::Ok().json(serde_json::json!({
+                        "access_token": "upstream-token",
+                        "token_type": "bearer",
+                        "expires_in": 3600
+                    }))
+                }),
+            )
+            .route(
+                "/userinfo",
+                web::get().to(move || {
+                    let userinfo = userinfo.clone();
+                    async move { HttpResponse::Ok().json(userinfo) }
+                }),
+            )
+            .route("/revoke", web::post().to(HttpResponse::Ok))
+    })
+    .bind(("127.0.0.1", 0))
+    .expect("Couldn't bind mock oauth server");
+    let url = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+    url
+}
+
+/// Starts a fake GitHub serving `emails` from its user emails API to holders of the token it hands out.
+pub fn mock_github_server(emails: serde_json::Value) -> String {
+    let server = HttpServer::new(move || {
+        let emails = emails.clone();
+        App::new()
+            .route(
+                "/login/oauth/access_token",
+                web::post().to(|| async {
+                    HttpResponse::Ok().json(serde_json::json!({
+                        "access_token": "github-token",
+                        "token_type": "bearer",
+                        "scope": "user:email"
+                    }))
+                }),
+            )
+            .route(
+                "/user/emails",
+                web::get().to(move |req: HttpRequest| {
+                    let emails = emails.clone();
+                    async move {
+                        let authorized = req
+                            .headers()
+                            .get(header::AUTHORIZATION)
+                            .is_some_and(|h| h == "Bearer github-token");
+                        if authorized && req.headers().contains_key(header::USER_AGENT) {
+                            HttpResponse::Ok().json(emails)
+                        } else {
+                            HttpResponse::Unauthorized().finish()
+                        }
+                    }
+                }),
+            )
+    })
+    .bind(("127.0.0.1", 0))
+    .expect("Couldn't bind mock github server");
+    let url = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+    url
+}
+
+/// [config] with GitHub login enabled against `github_url`.
+pub fn github_config(github_url: &str) -> AppConfig {
+    AppConfig {
+        github_client_id: Some("github-client".to_string()),
+        github_client_secret: Some("github-secret".to_string()),
+        github_auth_url: format!("{github_url}/login/oauth/authorize"),
+        github_token_url: format!("{github_url}/login/oauth/access_token"),
+        github_api_url: github_url.to_string(),
+        ..oauth_config("http://localhost:1")
+    }
+}
+
+/// [config] with every OAuth endpoint pointing at `oauth_url`.
+pub fn oauth_config(oauth_url: &str) -> AppConfig {
+    AppConfig {
+        google_auth_url: format!("{oauth_url}/auth"),
+        google_token_url: format!("{oauth_url}/token"),
+        google_revocation_url: format!("{oauth_url}/revoke"),
+        google_userinfo_url: format!("{oauth_url}/userinfo"),
+        google_scope_url: "https://www.googleapis.com/auth/userinfo.email".to_string(),
+        ..config()
+    }
+}
+
+pub const OIDC_CLIENT_ID: &str = "oidc-client";
+
+/// Starts a fake OpenID Connect provider that signs RS256 ID tokens for `email`. It has no state, so the token
+/// endpoint puts the authorization code into the `nonce` claim: tests pass the nonce from the login redirect as code.
+pub fn mock_oidc_provider(email: &str) -> String {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).expect("Couldn't bind mock oidc provider");
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let key_pair = Arc::new(
+        RsaKeyPair::from_pkcs8(include_bytes!("../fixtures/oidc_rsa_key.pk8"))
+            .expect("Couldn't read test RSA key"),
+    );
+    let email = email.to_string();
+    let provider_issuer = issuer.clone();
+    let server = HttpServer::new(move || {
+        let issuer = provider_issuer.clone();
+        let key_pair = key_pair.clone();
+        let email = email.clone();
+        let metadata = serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+        });
+        let public_key = PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+        let jwks = serde_json::json!({ "keys": [{
+            "kty": "RSA",
+            "kid": "rsa-1",
+            "alg": "RS256",
+            "n": URL_SAFE_NO_PAD.encode(&public_key.n),
+            "e": URL_SAFE_NO_PAD.encode(&public_key.e),
+        }]});
+        App::new()
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(move || {
+                    let metadata = metadata.clone();
+                    async move { HttpResponse::Ok().json(metadata) }
+                }),
+            )
+            .route(
+                "/jwks",
+                web::get().to(move || {
+                    let jwks = jwks.clone();
+                    async move { HttpResponse::Ok().json(jwks) }
+                }),
+            )
+            .route(
+                "/token",
+                web::post().to(move |form: web::Form<HashMap<String, String>>| {
+                    let claims = serde_json::json!({
+                        "iss": issuer,
+                        "aud": OIDC_CLIENT_ID,
+                        "exp": Utc::now().timestamp() + 300,
+                        "nonce": form.get("code"),
+                        "email": email,
+                        "email_verified": true,
+                    });
+                    let id_token = sign_rs256(&key_pair, &claims);
+                    async move {
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "access_token": "upstream-token",
+                            "token_type": "bearer",
+                            "expires_in": 3600,
+                            "id_token": id_token,
+                        }))
+                    }
+                }),
+            )
+    })
+    .listen(listener)
+    .expect("Couldn't start mock oidc provider");
+    actix_web::rt::spawn(server.run());
+    issuer
+}
+
+fn sign_rs256(key_pair: &RsaKeyPair, claims: &serde_json::Value) -> String {
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","kid":"rsa-1"}"#),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            signing_input.as_bytes(),
+            &mut signature,
+        )
+        .expect("Couldn't sign ID token");
+    format!("{signing_input}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
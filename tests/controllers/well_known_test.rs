@@ -0,0 +1,37 @@
+use crate::controllers::support;
+use actix_web::{test, App};
+use auth_app_rs::auth::keyring::SigningKey;
+use auth_app_rs::model::public_key::PublicKeySet;
+use paperclip_actix::web;
+
+#[cfg(test)]
+#[actix_web::test]
+pub async fn signing_keys_are_published_as_paserks() {
+    let keyring = support::keyring()
+        .with_signing_keys(
+            SigningKey::new(
+                "signing",
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            )
+            .unwrap(),
+            vec![],
+        )
+        .unwrap();
+    let app = test::init_service(
+        App::new().app_data(web::Data::new(keyring)).service(
+            web::scope("/.well-known")
+                .configure(auth_app_rs::controllers::wellknown::configure_well_known),
+        ),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/.well-known/paseto-keys")
+        .to_request();
+    let key_set: PublicKeySet = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(key_set.keys.len(), 1);
+    assert_eq!(key_set.keys[0].kid, "signing");
+    assert_eq!(
+        key_set.keys[0].paserk,
+        "k4.public.11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+    );
+}